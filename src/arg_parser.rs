@@ -16,15 +16,62 @@
 
 use std::borrow::Cow;
 use std::env::ArgsOs;
+use std::ffi::OsStr;
 use std::ffi::OsString;
+use std::ops::RangeInclusive;
 use std::process;
 
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::name;
+use super::types::EmboldenStrength;
+use super::types::FourCC;
+use super::types::GaspRange;
+use super::types::GlyphSpec;
+use super::types::HintingFilter;
+use super::types::InstanceSelection;
+use super::types::GASP_DOGRAY;
+use super::types::GASP_GRIDFIT;
+use super::types::GASP_SYMMETRIC_GRIDFIT;
+use super::types::GASP_SYMMETRIC_SMOOTHING;
+
 pub struct ArgParser {
     pub input_filename: OsString,
     pub output_filename: OsString,
+    pub instances: Option<InstanceSelection>,
     pub keep_bitmap: bool,
+    pub remove_color_bitmap: bool,
+    pub cff_to_glyf: bool,
+    pub cff_to_glyf_tolerance: f32,
+    pub embolden: Option<EmboldenStrength>,
+    pub force: bool,
+    pub bitmap_ppem_range: Option<RangeInclusive<u8>>,
+    pub grayscale_bitmap_ppems: Option<Vec<u8>>,
+    pub grayscale_bitmap_depth: u8,
     pub keep_gasp: bool,
     pub keep_hinting: bool,
+    pub autohint: bool,
+    pub hybrid_hinting_ppem: Option<u16>,
+    pub hinting_filter: Option<HintingFilter>,
+    pub stem_darkening_ppem_range: Option<RangeInclusive<u8>>,
+    pub stem_darkening_pixels: f32,
+    pub hdmx_ppems: Option<Vec<u8>>,
+    pub remove_hdmx: bool,
+    pub remove_vdmx: bool,
+    pub remove_ltsh: bool,
+    pub keep_maxp: bool,
+    pub vertical_metrics_ppem: Option<u8>,
+    pub vertical_metrics_cmap_only: bool,
+    pub gasp_ranges: Option<Vec<GaspRange>>,
+    pub merge_gasp_ppem: Option<u16>,
+    pub rename_suffix: Option<String>,
+    pub provenance: bool,
+    pub stamp_description: bool,
+    pub bump_revision: bool,
+    // The options as given, for the provenance marker
+    pub options: Vec<String>,
 }
 
 impl ArgParser {
@@ -33,35 +80,163 @@ impl ArgParser {
         let program_name = args.next();
 
         enum CurrentOption {
+            CffToGlyfTolerance,
+            ColorBitmaps,
+            Embolden,
             End,
+            FixVerticalMetrics,
+            Gasp,
+            GrayscaleBitmap,
+            GrayscaleBitmapDepth,
+            Hdmx,
+            HintKeepGlyphs,
+            HintStripGlyphs,
+            HintStripUnicode,
+            HybridHinting,
+            Instance,
+            KeepBitmapPpem,
+            MergeGasp,
             Output,
+            RenameSuffix,
+            StemDarkening,
+            StemDarkeningAmount,
         }
         let mut current_option = Option::<CurrentOption>::None;
         let mut input_filename = Option::<OsString>::None;
         let mut output_filename = Option::<OsString>::None;
+        let mut instances = Option::<InstanceSelection>::None;
         let mut keep_bitmap = false;
+        let mut remove_color_bitmap = false;
+        let mut cff_to_glyf = false;
+        let mut cff_to_glyf_tolerance = 1.0;
+        let mut embolden = Option::<EmboldenStrength>::None;
+        let mut force = false;
+        let mut bitmap_ppem_range = Option::<RangeInclusive<u8>>::None;
+        let mut grayscale_bitmap_ppems = Option::<Vec<u8>>::None;
+        let mut grayscale_bitmap_depth = 8;
         let mut keep_gasp = false;
         let mut keep_hinting = false;
+        let mut autohint = false;
+        let mut hybrid_hinting_ppem = Option::<u16>::None;
+        let mut hinting_filter = Option::<HintingFilter>::None;
+        let mut stem_darkening_ppem_range = Option::<RangeInclusive<u8>>::None;
+        let mut stem_darkening_pixels = 0.5;
+        let mut hdmx_ppems = Option::<Vec<u8>>::None;
+        let mut remove_hdmx = false;
+        let mut remove_vdmx = false;
+        let mut remove_ltsh = false;
+        let mut keep_maxp = false;
+        let mut vertical_metrics_ppem = Option::<u8>::None;
+        let mut vertical_metrics_cmap_only = false;
+        let mut gasp_ranges = Option::<Vec<GaspRange>>::None;
+        let mut merge_gasp_ppem = Option::<u16>::None;
+        let mut rename_suffix = Option::<String>::None;
+        let mut provenance = false;
+        let mut stamp_description = false;
+        let mut bump_revision = false;
 
-        for arg in args {
+        let args = args.collect::<Vec<_>>();
+        for arg in args.iter().cloned() {
             match current_option {
                 None => {
                     if arg == "--" {
                         current_option = Some(CurrentOption::End);
                     } else if arg == "--help" {
                         Self::print_help_and_exit(&program_name, 0);
+                    } else if arg == "--autohint" {
+                        autohint = true;
+                        keep_hinting = false;
+                        hybrid_hinting_ppem = None;
+                    } else if arg == "--bump-revision" {
+                        bump_revision = true;
+                        provenance = true;
+                    } else if arg == "--cff-to-glyf" {
+                        cff_to_glyf = true;
+                    } else if arg == "--cff-to-glyf-tolerance" {
+                        current_option = Some(CurrentOption::CffToGlyfTolerance);
+                    } else if arg == "--color-bitmaps" {
+                        current_option = Some(CurrentOption::ColorBitmaps);
+                    } else if let Some(policy) = arg
+                        .to_str()
+                        .and_then(|x| x.strip_prefix("--color-bitmaps="))
+                    {
+                        remove_color_bitmap = Self::parse_color_bitmap_policy(policy);
+                    } else if arg == "--embolden" {
+                        current_option = Some(CurrentOption::Embolden);
+                    } else if arg == "--fix-vertical-metrics" {
+                        current_option = Some(CurrentOption::FixVerticalMetrics);
+                    } else if arg == "--force" {
+                        force = true;
+                    } else if arg == "--gasp" {
+                        current_option = Some(CurrentOption::Gasp);
+                    } else if arg == "--grayscale-bitmap" {
+                        current_option = Some(CurrentOption::GrayscaleBitmap);
+                    } else if arg == "--grayscale-bitmap-depth" {
+                        current_option = Some(CurrentOption::GrayscaleBitmapDepth);
+                    } else if arg == "--hdmx" {
+                        current_option = Some(CurrentOption::Hdmx);
+                    } else if arg == "--hint-keep-glyphs" {
+                        current_option = Some(CurrentOption::HintKeepGlyphs);
+                    } else if arg == "--hint-strip-glyphs" {
+                        current_option = Some(CurrentOption::HintStripGlyphs);
+                    } else if arg == "--hint-strip-unicode" {
+                        current_option = Some(CurrentOption::HintStripUnicode);
+                    } else if arg == "--hybrid-hinting" {
+                        current_option = Some(CurrentOption::HybridHinting);
+                    } else if arg == "--instance" {
+                        current_option = Some(CurrentOption::Instance);
                     } else if arg == "--keep-bitmap" {
                         keep_bitmap = true;
+                        bitmap_ppem_range = None;
+                        grayscale_bitmap_ppems = None;
+                    } else if arg == "--keep-bitmap-ppem" {
+                        current_option = Some(CurrentOption::KeepBitmapPpem);
                     } else if arg == "--keep-gasp" {
                         keep_gasp = true;
+                    } else if arg == "--keep-maxp" {
+                        keep_maxp = true;
                     } else if arg == "--keep-hinting" {
                         keep_hinting = true;
+                        autohint = false;
+                        hybrid_hinting_ppem = None;
+                    } else if arg == "--merge-gasp" {
+                        current_option = Some(CurrentOption::MergeGasp);
                     } else if arg == "--modify-gasp" {
                         keep_gasp = false;
+                    } else if arg == "--no-provenance" {
+                        provenance = false;
+                        stamp_description = false;
+                        bump_revision = false;
+                    } else if arg == "--named-instances" {
+                        instances = Some(InstanceSelection::Named);
                     } else if arg == "--remove-bitmap" {
                         keep_bitmap = false;
+                        bitmap_ppem_range = None;
+                        grayscale_bitmap_ppems = None;
+                    } else if arg == "--remove-hdmx" {
+                        remove_hdmx = true;
+                        hdmx_ppems = None;
+                    } else if arg == "--remove-ltsh" {
+                        remove_ltsh = true;
+                    } else if arg == "--remove-vdmx" {
+                        remove_vdmx = true;
                     } else if arg == "--remove-hinting" {
                         keep_hinting = false;
+                        autohint = false;
+                        hybrid_hinting_ppem = None;
+                    } else if arg == "--rename-suffix" {
+                        current_option = Some(CurrentOption::RenameSuffix);
+                    } else if arg == "--stamp-provenance" {
+                        provenance = true;
+                    } else if arg == "--stamp-description" {
+                        stamp_description = true;
+                        provenance = true;
+                    } else if arg == "--stem-darkening" {
+                        current_option = Some(CurrentOption::StemDarkening);
+                    } else if arg == "--stem-darkening-amount" {
+                        current_option = Some(CurrentOption::StemDarkeningAmount);
+                    } else if arg == "--vertical-metrics-cmap-only" {
+                        vertical_metrics_cmap_only = true;
                     } else if arg == "-o" || arg == "--output" {
                         current_option = Some(CurrentOption::Output);
                     } else {
@@ -71,12 +246,157 @@ impl ArgParser {
                         input_filename = Some(arg);
                     }
                 }
+                Some(CurrentOption::CffToGlyfTolerance) => {
+                    cff_to_glyf_tolerance = match arg.to_str().and_then(|x| x.parse::<f32>().ok()) {
+                        Some(x) if x > 0.0 && x.is_finite() => x,
+                        _ => {
+                            eprintln!(
+                                "[ FAIL ] --cff-to-glyf-tolerance: expected a positive number, found “{}”",
+                                arg.to_string_lossy()
+                            );
+                            process::exit(1);
+                        }
+                    };
+                    cff_to_glyf = true;
+                    current_option = None;
+                }
+                Some(CurrentOption::ColorBitmaps) => {
+                    remove_color_bitmap = Self::parse_color_bitmap_policy(&arg.to_string_lossy());
+                    current_option = None;
+                }
                 Some(CurrentOption::End) => {
                     if input_filename.is_some() {
                         Self::print_help_and_exit(&program_name, 1);
                     }
                     input_filename = Some(arg);
                 }
+                Some(CurrentOption::Gasp) => {
+                    gasp_ranges = Some(Self::parse_gasp_ranges(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --gasp: {}", e);
+                        process::exit(1);
+                    }));
+                    merge_gasp_ppem = None;
+                    keep_gasp = false;
+                    current_option = None;
+                }
+                Some(CurrentOption::GrayscaleBitmap) => {
+                    grayscale_bitmap_ppems =
+                        Some(Self::parse_ppem_list(&arg).unwrap_or_else(|e| {
+                            eprintln!("[ FAIL ] --grayscale-bitmap: {}", e);
+                            process::exit(1);
+                        }));
+                    keep_bitmap = false;
+                    bitmap_ppem_range = None;
+                    current_option = None;
+                }
+                Some(CurrentOption::GrayscaleBitmapDepth) => {
+                    grayscale_bitmap_depth = match arg.to_str() {
+                        Some("4") => 4,
+                        Some("8") => 8,
+                        _ => {
+                            eprintln!(
+                                "[ FAIL ] --grayscale-bitmap-depth: expected “4” or “8”, found “{}”",
+                                arg.to_string_lossy()
+                            );
+                            process::exit(1);
+                        }
+                    };
+                    current_option = None;
+                }
+                Some(CurrentOption::Hdmx) => {
+                    hdmx_ppems = Some(Self::parse_ppem_list(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --hdmx: {}", e);
+                        process::exit(1);
+                    }));
+                    remove_hdmx = false;
+                    current_option = None;
+                }
+                Some(CurrentOption::HintKeepGlyphs) => {
+                    let keep_glyphs = Self::parse_glyph_specs(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --hint-keep-glyphs: {}", e);
+                        process::exit(1);
+                    });
+                    hinting_filter
+                        .get_or_insert_with(HintingFilter::default)
+                        .keep_glyphs
+                        .extend(keep_glyphs);
+                    current_option = None;
+                }
+                Some(CurrentOption::HintStripGlyphs) => {
+                    let strip_glyphs = Self::parse_glyph_specs(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --hint-strip-glyphs: {}", e);
+                        process::exit(1);
+                    });
+                    hinting_filter
+                        .get_or_insert_with(HintingFilter::default)
+                        .strip_glyphs
+                        .extend(strip_glyphs);
+                    current_option = None;
+                }
+                Some(CurrentOption::HintStripUnicode) => {
+                    let strip_unicode = Self::parse_unicode_ranges(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --hint-strip-unicode: {}", e);
+                        process::exit(1);
+                    });
+                    hinting_filter
+                        .get_or_insert_with(HintingFilter::default)
+                        .strip_unicode
+                        .extend(strip_unicode);
+                    current_option = None;
+                }
+                Some(CurrentOption::Embolden) => {
+                    embolden = Some(Self::parse_embolden_strength(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --embolden: {}", e);
+                        process::exit(1);
+                    }));
+                    current_option = None;
+                }
+                Some(CurrentOption::FixVerticalMetrics) => {
+                    vertical_metrics_ppem = match arg.to_str().and_then(|x| x.parse::<u8>().ok()) {
+                        Some(x) if x != 0 => Some(x),
+                        _ => {
+                            eprintln!(
+                                "[ FAIL ] --fix-vertical-metrics: expected a ppem from 1 to 255, found “{}”",
+                                arg.to_string_lossy()
+                            );
+                            process::exit(1);
+                        }
+                    };
+                    current_option = None;
+                }
+                Some(CurrentOption::HybridHinting) => {
+                    hybrid_hinting_ppem =
+                        Some(Self::parse_ppem("--hybrid-hinting", &arg, 1..=0x7fff));
+                    keep_hinting = false;
+                    autohint = false;
+                    current_option = None;
+                }
+                Some(CurrentOption::Instance) => {
+                    let location = Self::parse_location(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --instance: {}", e);
+                        process::exit(1);
+                    });
+                    match &mut instances {
+                        Some(InstanceSelection::Locations(locations)) => locations.push(location),
+                        _ => instances = Some(InstanceSelection::Locations(vec![location])),
+                    }
+                    current_option = None;
+                }
+                Some(CurrentOption::KeepBitmapPpem) => {
+                    bitmap_ppem_range = Some(Self::parse_ppem_range(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --keep-bitmap-ppem: {}", e);
+                        process::exit(1);
+                    }));
+                    keep_bitmap = false;
+                    grayscale_bitmap_ppems = None;
+                    current_option = None;
+                }
+                Some(CurrentOption::MergeGasp) => {
+                    merge_gasp_ppem = Some(Self::parse_ppem("--merge-gasp", &arg, 1..=0xffff));
+                    gasp_ranges = None;
+                    keep_gasp = false;
+                    current_option = None;
+                }
                 Some(CurrentOption::Output) => {
                     if output_filename.is_some() {
                         Self::print_help_and_exit(&program_name, 1);
@@ -84,6 +404,52 @@ impl ArgParser {
                     output_filename = Some(arg);
                     current_option = None;
                 }
+                Some(CurrentOption::RenameSuffix) => {
+                    rename_suffix = match arg.to_str() {
+                        // The PostScript name needs to change too
+                        Some(x) if !name::sanitize_post_script_name(x).is_empty() => {
+                            Some(x.to_owned())
+                        }
+                        _ => {
+                            eprintln!(
+                                "[ FAIL ] --rename-suffix: expected some letters or digits, found “{}”",
+                                arg.to_string_lossy()
+                            );
+                            process::exit(1);
+                        }
+                    };
+                    current_option = None;
+                }
+                Some(CurrentOption::StemDarkening) => {
+                    let ppem_range = Self::parse_ppem_range(&arg).unwrap_or_else(|e| {
+                        eprintln!("[ FAIL ] --stem-darkening: {}", e);
+                        process::exit(1);
+                    });
+                    if ppem_range.start() >= ppem_range.end() {
+                        eprintln!(
+                            "[ FAIL ] --stem-darkening: expected “FROM-TO” with FROM below TO, found “{}”",
+                            arg.to_string_lossy()
+                        );
+                        process::exit(1);
+                    }
+                    stem_darkening_ppem_range = Some(ppem_range);
+                    keep_hinting = false;
+                    hybrid_hinting_ppem = None;
+                    current_option = None;
+                }
+                Some(CurrentOption::StemDarkeningAmount) => {
+                    stem_darkening_pixels = match arg.to_str().and_then(|x| x.parse::<f32>().ok()) {
+                        Some(x) if x > 0.0 && x <= 4.0 => x,
+                        _ => {
+                            eprintln!(
+                                "[ FAIL ] --stem-darkening-amount: expected a number of pixels from 0 to 4, found “{}”",
+                                arg.to_string_lossy()
+                            );
+                            process::exit(1);
+                        }
+                    };
+                    current_option = None;
+                }
             }
         }
         match current_option {
@@ -92,14 +458,366 @@ impl ArgParser {
             _ => Self::print_help_and_exit(&program_name, 1),
         }
 
+        let input_filename =
+            input_filename.unwrap_or_else(|| Self::print_help_and_exit(&program_name, 1));
+        let output_filename =
+            output_filename.unwrap_or_else(|| Self::print_help_and_exit(&program_name, 1));
+        // Everything but the file names, quoted if needed
+        let options = args
+            .iter()
+            .filter(|&x| {
+                *x != input_filename
+                    && *x != output_filename
+                    && x != "-o"
+                    && x != "--output"
+                    && x != "--"
+            })
+            .map(|x| {
+                let x = x.to_string_lossy();
+                if x.is_empty() || x.contains(' ') {
+                    format!("\"{}\"", x)
+                } else {
+                    x.into_owned()
+                }
+            })
+            .collect();
+
         Self {
-            input_filename: input_filename
-                .unwrap_or_else(|| Self::print_help_and_exit(&program_name, 1)),
-            output_filename: output_filename
-                .unwrap_or_else(|| Self::print_help_and_exit(&program_name, 1)),
+            input_filename,
+            output_filename,
+            instances,
             keep_bitmap,
+            remove_color_bitmap,
+            cff_to_glyf,
+            cff_to_glyf_tolerance,
+            embolden,
+            force,
+            bitmap_ppem_range,
+            grayscale_bitmap_ppems,
+            grayscale_bitmap_depth,
             keep_gasp,
             keep_hinting,
+            autohint,
+            hybrid_hinting_ppem,
+            hinting_filter,
+            stem_darkening_ppem_range,
+            stem_darkening_pixels,
+            hdmx_ppems,
+            remove_hdmx,
+            remove_vdmx,
+            remove_ltsh,
+            keep_maxp,
+            vertical_metrics_ppem,
+            vertical_metrics_cmap_only,
+            gasp_ranges,
+            merge_gasp_ppem,
+            rename_suffix,
+            provenance,
+            stamp_description,
+            bump_revision,
+            options,
+        }
+    }
+
+    // Returns whether color bitmaps should be removed.
+    fn parse_color_bitmap_policy(arg: &str) -> bool {
+        match arg {
+            "keep" => false,
+            "remove" => true,
+            _ => {
+                eprintln!(
+                    "[ FAIL ] --color-bitmaps: expected “keep” or “remove”, found “{}”",
+                    arg
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    // Parses axis coordinates, e.g. “wght=700,wdth=87.5”.
+    fn parse_location(arg: &OsStr) -> Result<Vec<(FourCC, f32)>> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid axis coordinates “{}”", arg.to_string_lossy()))?;
+        let mut location = Vec::new();
+        for coordinate in arg.split(',') {
+            let (tag, value) = coordinate
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected “AXIS=VALUE”, found “{}”", coordinate))?;
+            if tag.is_empty() || tag.len() > 4 || !tag.bytes().all(|x| x.is_ascii_graphic()) {
+                bail!("invalid axis tag “{}”", tag);
+            }
+            // Short tags are padded with spaces
+            let mut tag_bytes = *b"    ";
+            tag_bytes[..tag.len()].copy_from_slice(tag.as_bytes());
+            let value = value
+                .parse::<f32>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .ok_or_else(|| anyhow!("invalid value “{}” for axis “{}”", value, tag))?;
+            location.push((FourCC(tag_bytes), value));
+        }
+        Ok(location)
+    }
+
+    fn parse_ppem(option: &str, arg: &OsStr, range: RangeInclusive<u16>) -> u16 {
+        match arg.to_str().and_then(|x| x.parse::<u16>().ok()) {
+            Some(ppem) if range.contains(&ppem) => ppem,
+            _ => {
+                eprintln!(
+                    "[ FAIL ] {}: expected a ppem from {} to {}, found “{}”",
+                    option,
+                    range.start(),
+                    range.end(),
+                    arg.to_string_lossy()
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    // Parses a width in font units like "20", or in percent of the em size
+    // like "1.5%".
+    fn parse_embolden_strength(arg: &OsStr) -> Result<EmboldenStrength> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid strength: {}", arg.to_string_lossy()))?;
+        let (value, is_percent) = match arg.strip_suffix('%') {
+            Some(value) => (value, true),
+            None => (arg, false),
+        };
+        match value.trim().parse::<f32>() {
+            Ok(x) if x > 0.0 && x.is_finite() => Ok(if is_percent {
+                EmboldenStrength::Percent(x)
+            } else {
+                EmboldenStrength::Units(x)
+            }),
+            _ => bail!(
+                "expected a positive number of units or percent, found “{}”",
+                arg
+            ),
+        }
+    }
+
+    // Parses a range like "10-12", or a single value like "12".
+    fn parse_ppem_range(arg: &OsStr) -> Result<RangeInclusive<u8>> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid ppem range: {}", arg.to_string_lossy()))?;
+        let parse_ppem = |x: &str| {
+            x.trim()
+                .parse::<u8>()
+                .map_err(|_| anyhow!("invalid ppem value “{}”", x))
+        };
+        let (from, to) = match arg.split_once('-') {
+            Some((from, to)) => (parse_ppem(from)?, parse_ppem(to)?),
+            None => (parse_ppem(arg)?, parse_ppem(arg)?),
+        };
+        if from > to {
+            bail!("invalid range “{}”", arg);
+        }
+        Ok(from..=to)
+    }
+
+    // Parses a list like "9-16,18,20", sorted and without duplicates.
+    fn parse_ppem_list(arg: &OsStr) -> Result<Vec<u8>> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid ppem list: {}", arg.to_string_lossy()))?;
+        let mut ppems = Vec::new();
+        for item in arg.split(',') {
+            let range = Self::parse_ppem_range(OsStr::new(item))?;
+            if *range.start() == 0 {
+                bail!("invalid ppem value “{}”", item);
+            }
+            ppems.extend(range);
+        }
+        ppems.sort_unstable();
+        ppems.dedup();
+        Ok(ppems)
+    }
+
+    // Parses a list like "12,100-200,uni4E00,a.alt".
+    fn parse_glyph_specs(arg: &OsStr) -> Result<Vec<GlyphSpec>> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid glyph list: {}", arg.to_string_lossy()))?;
+        let mut specs = Vec::new();
+        for item in arg.split(',') {
+            let item = item.trim();
+            if item.is_empty() {
+                bail!("empty glyph in list “{}”", arg);
+            }
+            // Anything that does not look like a glyph ID or a range is a glyph name
+            let range = match item.split_once('-') {
+                Some((from, to)) => from.parse::<u16>().ok().zip(to.parse::<u16>().ok()),
+                None => item.parse::<u16>().ok().map(|x| (x, x)),
+            };
+            match range {
+                Some((from, to)) if from > to => bail!("invalid range “{}”", item),
+                Some((from, to)) => specs.push(GlyphSpec::Range(from..=to)),
+                None => specs.push(GlyphSpec::Name(item.to_owned())),
+            }
+        }
+        Ok(specs)
+    }
+
+    // Parses a list like "U+4E00-9FFF,U+3007,hiragana,katakana".
+    fn parse_unicode_ranges(arg: &OsStr) -> Result<Vec<RangeInclusive<u32>>> {
+        // Approximate blocks, good enough to tell scripts apart in CJK fonts
+        const SCRIPTS: [(&str, &[(u32, u32)]); 8] = [
+            (
+                "latin",
+                &[
+                    (0x0000, 0x024f),
+                    (0x1e00, 0x1eff),
+                    (0x2c60, 0x2c7f),
+                    (0xa720, 0xa7ff),
+                    (0xab30, 0xab6f),
+                    (0xfb00, 0xfb06),
+                    (0xff01, 0xff5e),
+                ],
+            ),
+            ("greek", &[(0x0370, 0x03ff), (0x1f00, 0x1fff)]),
+            (
+                "cyrillic",
+                &[
+                    (0x0400, 0x052f),
+                    (0x1c80, 0x1c8f),
+                    (0x2de0, 0x2dff),
+                    (0xa640, 0xa69f),
+                ],
+            ),
+            (
+                "han",
+                &[
+                    (0x2e80, 0x2fdf),
+                    (0x3005, 0x3007),
+                    (0x3021, 0x3029),
+                    (0x3038, 0x303b),
+                    (0x3190, 0x319f),
+                    (0x31c0, 0x31ef),
+                    (0x3400, 0x4dbf),
+                    (0x4e00, 0x9fff),
+                    (0xf900, 0xfaff),
+                    (0x20000, 0x3ffff),
+                ],
+            ),
+            ("hiragana", &[(0x3040, 0x309f), (0x1b000, 0x1b16f)]),
+            (
+                "katakana",
+                &[(0x30a0, 0x30ff), (0x31f0, 0x31ff), (0xff65, 0xff9f)],
+            ),
+            (
+                "hangul",
+                &[
+                    (0x1100, 0x11ff),
+                    (0x3130, 0x318f),
+                    (0xa960, 0xa97f),
+                    (0xac00, 0xd7ff),
+                    (0xffa0, 0xffdc),
+                ],
+            ),
+            ("bopomofo", &[(0x3100, 0x312f), (0x31a0, 0x31bf)]),
+        ];
+        // "cjk" is everything above plus CJK punctuation and compatibility forms
+        const CJK_EXTRA: [(u32, u32); 5] = [
+            (0x3000, 0x303f),
+            (0x3200, 0x33ff),
+            (0xfe30, 0xfe4f),
+            (0xff00, 0xff64),
+            (0xffa0, 0xffef),
+        ];
+
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid range list: {}", arg.to_string_lossy()))?;
+        let mut ranges = Vec::new();
+        for item in arg.split(',') {
+            let item = item.trim();
+            let lowercase = item.to_ascii_lowercase();
+            if lowercase == "cjk" {
+                for &(name, blocks) in SCRIPTS.iter() {
+                    if name != "latin" && name != "greek" && name != "cyrillic" {
+                        ranges.extend(blocks.iter().map(|&(from, to)| from..=to));
+                    }
+                }
+                ranges.extend(CJK_EXTRA.iter().map(|&(from, to)| from..=to));
+                continue;
+            }
+            if let Some(&(_, blocks)) = SCRIPTS.iter().find(|&&(name, _)| name == lowercase) {
+                ranges.extend(blocks.iter().map(|&(from, to)| from..=to));
+                continue;
+            }
+
+            let parse_code_point = |x: &str| {
+                let x = x.trim();
+                let hex = x
+                    .strip_prefix("U+")
+                    .or_else(|| x.strip_prefix("u+"))
+                    .unwrap_or(x);
+                u32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|&x| x <= 0x10ffff)
+                    .ok_or_else(|| anyhow!("invalid code point or script name “{}”", x))
+            };
+            let (from, to) = match item.split_once('-') {
+                Some((from, to)) => (parse_code_point(from)?, parse_code_point(to)?),
+                None => {
+                    let code_point = parse_code_point(item)?;
+                    (code_point, code_point)
+                }
+            };
+            if from > to {
+                bail!("invalid range “{}”", item);
+            }
+            ranges.push(from..=to);
+        }
+        Ok(ranges)
+    }
+
+    // Parses a range list like "8:gridfit,16:gray+gridfit,65535:gray+symmetric-smoothing".
+    fn parse_gasp_ranges(arg: &OsStr) -> Result<Vec<GaspRange>> {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid range list: {}", arg.to_string_lossy()))?;
+        let mut gasp_ranges = Vec::<GaspRange>::new();
+        for range in arg.split(',') {
+            let (range_max_ppem, range_gasp_behavior) = range
+                .split_once(':')
+                .ok_or_else(|| anyhow!("expected “PPEM:BEHAVIOR”, found “{}”", range))?;
+            let range_max_ppem = range_max_ppem
+                .trim()
+                .parse::<u16>()
+                .map_err(|_| anyhow!("invalid ppem value “{}”", range_max_ppem))?;
+            let mut behavior = 0;
+            for flag in range_gasp_behavior.split('+') {
+                behavior |= match flag.trim() {
+                    "none" => 0,
+                    "gridfit" => GASP_GRIDFIT,
+                    "gray" => GASP_DOGRAY,
+                    "symmetric-gridfit" => GASP_SYMMETRIC_GRIDFIT,
+                    "symmetric-smoothing" => GASP_SYMMETRIC_SMOOTHING,
+                    _ => bail!("unknown behavior “{}”", flag),
+                };
+            }
+            if let Some(last) = gasp_ranges.last() {
+                if range_max_ppem <= last.range_max_ppem {
+                    bail!(
+                        "ppem values must be in ascending order ({} after {})",
+                        range_max_ppem,
+                        last.range_max_ppem
+                    );
+                }
+            }
+            gasp_ranges.push(GaspRange {
+                range_max_ppem,
+                range_gasp_behavior: behavior,
+            });
+        }
+        match gasp_ranges.last() {
+            Some(last) if last.range_max_ppem == 0xffff => Ok(gasp_ranges),
+            _ => bail!("the last range must end at ppem 65535"),
         }
     }
 
@@ -115,17 +833,129 @@ impl ArgParser {
         );
         println!();
         println!("Options:");
+        println!("    --instance AXES     Create a static instance of variable fonts");
+        println!("                        at the given coordinates, e.g. “wght=700,");
+        println!("                        wdth=87.5”.  Repeat to create several");
+        println!("                        instances, written together as a TTC.");
+        println!("    --named-instances   Create static instances  of  all  named");
+        println!("                        instances of variable fonts.");
+        println!();
+        println!("    --cff-to-glyf       Convert  CFF  outlines  to  TrueType,  so");
+        println!("                        that “gasp” applies to them.");
+        println!("    --cff-to-glyf-tolerance UNITS");
+        println!("                        Maximum  distance  between  the  converted");
+        println!("                        curves and the original ones,  in font");
+        println!("                        units, “1” by default.");
+        println!("    --embolden STRENGTH Widen strokes by STRENGTH font units, or");
+        println!("                        percent of the em size, e.g. “2%”.  Needs");
+        println!("                        TrueType outlines.");
+        println!();
         println!("    --remove-bitmap     Remove embedded bitmap.                    [Default]");
         println!("    --keep-bitmap       Do not remove embedded bitmap.");
+        println!("    --keep-bitmap-ppem MIN-MAX");
+        println!("                        Only keep embedded bitmap strikes between");
+        println!("                        MIN and MAX ppem, e.g. “10-12”.");
+        println!("    --grayscale-bitmap PPEMS");
+        println!("                        Replace  embedded  bitmap  with  anti-");
+        println!("                        aliased  strikes  rendered  from  the");
+        println!("                        outlines at the listed sizes, e.g. “9-16”.");
+        println!("    --grayscale-bitmap-depth <4,8>");
+        println!("                        Bits per pixel of the rendered strikes,");
+        println!("                        “8” (default) or “4”.");
+        println!("    --force             Remove  embedded  bitmap  even  if  some");
+        println!("                        characters would be left with no outline.");
+        println!("    --color-bitmaps=<keep,remove>");
+        println!("                        Keep (default) or remove color bitmap in");
+        println!("                        “CBDT”,  “CBLC”  and  “sbix”,  which  are");
+        println!("                        not  affected  by  “--remove-bitmap”.");
+        println!("                        Emoji fonts often have no other glyphs.");
         println!();
         println!("    --modify-gasp       Modify “gasp” table to enable 6 × 5 super- [Default]");
         println!("                        sampled  anti-aliasing.  Consider  also");
         println!("                        removing  hinting  instructions  if  the");
         println!("                        rendering becomes buggy at certain sizes.");
+        println!("    --gasp RANGES       Modify “gasp” table with custom ranges,  e.g.");
+        println!("                        “8:gridfit,16:gray+gridfit,65535:gray+");
+        println!("                        symmetric-smoothing”.  Each range applies");
+        println!("                        up to its ppem and combines “none”,");
+        println!("                        “gridfit”,  “gray”,  “symmetric-gridfit”,");
+        println!("                        “symmetric-smoothing”.");
+        println!("    --merge-gasp PPEM   Keep the original “gasp” ranges up to PPEM,");
+        println!("                        and  enable  symmetric  anti-aliasing  only");
+        println!("                        above it.  Useful with “--keep-hinting”.");
         println!("    --keep-gasp         Keep the original “gasp” table.");
         println!();
         println!("    --remove-hinting    Remove TrueType hinting instructions.      [Default]");
         println!("    --keep-hinting      Do not remove TrueType hinting.");
+        println!("    --autohint          Replace  TrueType  hinting  with  generated");
+        println!("                        instructions that fit horizontal stems and");
+        println!("                        blue  zones  in  the  y-direction  only,");
+        println!("                        like FreeType's light hinting.  Unless set");
+        println!("                        otherwise,  “gasp”  is  modified  to  match.");
+        println!("    --hint-strip-unicode RANGES");
+        println!("                        Only remove hinting from glyphs mapped from");
+        println!("                        the  listed  code  points,  e.g.  “U+4E00-");
+        println!("                        9FFF,U+3007”,  or  scripts:  “latin”,");
+        println!("                        “greek”,  “cyrillic”,  “han”,  “hiragana”,");
+        println!("                        “katakana”,  “hangul”,  “bopomofo”,  “cjk”.");
+        println!("                        The font program is kept for other glyphs.");
+        println!("    --hint-strip-glyphs GLYPHS");
+        println!("                        Also remove hinting from the listed glyphs,");
+        println!("                        e.g. “12,100-200,uni4E00,a.alt”, given as");
+        println!("                        glyph IDs, ranges, or names from “post”.");
+        println!("    --hint-keep-glyphs GLYPHS");
+        println!("                        Keep hinting of the listed glyphs.  If used");
+        println!("                        alone, remove hinting from all other glyphs.");
+        println!("    --hybrid-hinting PPEM");
+        println!("                        Keep TrueType hinting up to PPEM, and turn");
+        println!("                        grid-fitting  off  above  it.  Unless  set");
+        println!("                        otherwise,  “gasp”  is  modified to match,");
+        println!("                        and  “hdmx”  keeps  only sizes up to PPEM.");
+        println!("                        PPEM is from 1 to 32767.");
+        println!("    --stem-darkening FROM-TO");
+        println!("                        When hinting is removed, add instructions");
+        println!("                        that darken strokes at small sizes, fully");
+        println!("                        up to FROM ppem, and fading out at TO ppem,");
+        println!("                        e.g.  “9-24”.  Grid-fitting  is  turned on");
+        println!("                        below TO ppem, as glyph programs need it,");
+        println!("                        but outlines are not snapped to pixels.");
+        println!("    --stem-darkening-amount PIXELS");
+        println!("                        How much wider strokes get at FROM ppem,");
+        println!("                        “0.5” pixels by default.");
+        println!("    --hdmx PPEMS        When  hinting  is  removed,  regenerate");
+        println!("                        “hdmx”  at  the  listed  sizes,  e.g.");
+        println!("                        “9-24,32”.  By default, only fonts with");
+        println!("                        “hdmx” get a new one, at the same sizes.");
+        println!("    --remove-hdmx       Remove “hdmx” instead of regenerating it.");
+        println!("    --remove-vdmx       Remove  “VDMX”  instead  of  regenerating  it");
+        println!("                        from the unhinted glyph extents.");
+        println!("    --remove-ltsh       Remove “LTSH” instead of regenerating it,");
+        println!("                        with all glyphs linear from 1 ppem.");
+        println!();
+        println!("    --fix-vertical-metrics PPEM");
+        println!("                        Enlarge   “usWinAscent”,   “usWinDescent”");
+        println!("                        and  the “hhea” ascender and descender, so");
+        println!("                        that  anti-aliased  glyphs  from  PPEM  up");
+        println!("                        are  not clipped.  The typographic metrics");
+        println!("                        “sTypoAscender”,    “sTypoDescender”   and");
+        println!("                        “sTypoLineGap” are left untouched.");
+        println!("    --vertical-metrics-cmap-only");
+        println!("                        Only measure glyphs mapped from characters.");
+        println!("    --rename-suffix SUFFIX");
+        println!("                        Append SUFFIX, e.g. \" FT\", to the family");
+        println!("                        names,  so  that  the  modified  fonts  can");
+        println!("                        be installed next to the original ones.");
+        println!("    --no-provenance     Leave the version string and the “head”    [Default]");
+        println!("                        modification time unchanged, so identical");
+        println!("                        runs give identical output.");
+        println!("    --stamp-provenance  Append  “FaithType”  and  the  options  to");
+        println!("                        the  version  string,  and  set the “head”");
+        println!("                        modification time to the current time, or");
+        println!("                        to SOURCE_DATE_EPOCH if set.");
+        println!("    --stamp-description Also append them to the description.");
+        println!("    --bump-revision     Also increase the font revision by 0.001.");
+        println!("    --keep-maxp         Do not recalculate the limits in “maxp”");
+        println!("                        from the glyph outlines and instructions.");
         println!();
         process::exit(exit_code);
     }
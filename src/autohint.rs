@@ -0,0 +1,406 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use super::glyf::SimpleGlyph;
+
+// Characters measured for each blue zone, as in the Latin script of
+// ttfautohint, and whether the zone holds the tops of the glyphs.
+const BLUE_STRINGS: [(bool, &str); 6] = [
+    (true, "THEZOCQS"),  // capital top
+    (false, "HEZLOCUS"), // capital bottom
+    (true, "xzroesc"),   // small top
+    (false, "xzroesc"),  // small bottom
+    (true, "bdhkl"),     // ascender
+    (false, "pqgjy"),    // descender
+];
+
+// Function that rounds a blue zone
+const ZONE_FUNCTION: u8 = 0;
+
+// Vertical positions that edges of all glyphs snap to
+pub struct BlueZone {
+    // Whether the zone holds the tops of the glyphs
+    pub top: bool,
+    // Position of flat edges, in font units
+    pub reference: i32,
+    // Position of round edges, beyond the reference
+    pub overshoot: i32,
+}
+
+// A horizontal edge of a glyph, made of one or more segments at the same
+// height, possibly from different contours
+struct Edge {
+    y: i32,
+    // Whether the ink is below the edge
+    top: bool,
+    // Whether the edge has a straight line, not only a curve extremum
+    flat: bool,
+    x_min: i32,
+    x_max: i32,
+    points: Vec<usize>,
+}
+
+// Finds the horizontal edges of a glyph. Straight lines shorter than
+// min_length are ignored.
+fn find_edges(glyph: &SimpleGlyph, min_length: i32) -> Vec<Edge> {
+    // TrueType contours go clockwise around the ink, with holes going the
+    // other way. Glyphs converted from PostScript may go counterclockwise.
+    let mut area = 0i64;
+    let mut start = 0;
+    for &end in glyph.end_points.iter() {
+        let contour = &glyph.points[start..=end];
+        for (i, &(x0, y0, _)) in contour.iter().enumerate() {
+            let (x1, y1, _) = contour[(i + 1) % contour.len()];
+            area += i64::from(x0) * i64::from(y1) - i64::from(x1) * i64::from(y0);
+        }
+        start = end + 1;
+    }
+    let clockwise = area <= 0;
+
+    // Segments are runs of consecutive points at the same height
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for &end in glyph.end_points.iter() {
+        let contour = &glyph.points[start..=end];
+        let len = contour.len();
+        // Start the runs after a change of height, so that none wraps around
+        let first = match (0..len).find(|&i| contour[(i + len - 1) % len].1 != contour[i].1) {
+            Some(first) => first,
+            None => {
+                start = end + 1;
+                continue;
+            }
+        };
+        let mut i = 0;
+        while i < len {
+            let mut run = vec![(first + i) % len];
+            while i + run.len() < len
+                && contour[(first + i + run.len()) % len].1 == contour[run[0]].1
+            {
+                run.push((first + i + run.len()) % len);
+            }
+            i += run.len();
+            if run.len() < 2 {
+                continue;
+            }
+            let (x_first, y, _) = contour[run[0]];
+            let x_last = contour[*run.last().unwrap()].0;
+            if x_first == x_last {
+                continue;
+            }
+            let flat = run.windows(2).any(|x| contour[x[0]].2 && contour[x[1]].2);
+            let x_min = run.iter().map(|&x| contour[x].0).min().unwrap();
+            let x_max = run.iter().map(|&x| contour[x].0).max().unwrap();
+            if flat && x_max - x_min < min_length {
+                continue;
+            }
+            segments.push(Edge {
+                y,
+                top: (x_last > x_first) == clockwise,
+                flat,
+                x_min,
+                x_max,
+                points: run.into_iter().map(|x| start + x).collect(),
+            });
+        }
+        start = end + 1;
+    }
+
+    // Segments at the same height facing the same way form one edge
+    let mut edges = BTreeMap::<(i32, bool), Edge>::new();
+    for segment in segments {
+        match edges.get_mut(&(segment.y, segment.top)) {
+            Some(edge) => {
+                edge.flat |= segment.flat;
+                edge.x_min = edge.x_min.min(segment.x_min);
+                edge.x_max = edge.x_max.max(segment.x_max);
+                edge.points.extend(segment.points);
+            }
+            None => {
+                edges.insert((segment.y, segment.top), segment);
+            }
+        }
+    }
+    edges.into_values().collect()
+}
+
+// Measures the blue zones from the reference characters found in the font.
+// Flat edges give the reference position, and round ones the overshoot.
+pub fn find_blue_zones(glyphs: &BTreeMap<char, SimpleGlyph>, units_per_em: u16) -> Vec<BlueZone> {
+    let min_length = i32::from(units_per_em) / 40;
+    let fuzz = i32::from(units_per_em) / 40;
+    let mut zones = Vec::<BlueZone>::new();
+    for &(top, characters) in BLUE_STRINGS.iter() {
+        let mut flat = Vec::new();
+        let mut round = Vec::new();
+        for glyph in characters.chars().filter_map(|x| glyphs.get(&x)) {
+            let edges = find_edges(glyph, min_length);
+            let extreme = edges.iter().filter(|x| x.top == top).reduce(|acc, x| {
+                if (x.y > acc.y) == top {
+                    x
+                } else {
+                    acc
+                }
+            });
+            match extreme {
+                Some(edge) if edge.flat => flat.push(edge.y),
+                Some(edge) => round.push(edge.y),
+                None => (),
+            }
+        }
+        if flat.is_empty() {
+            continue;
+        }
+        let average = |x: &[i32]| {
+            (x.iter().map(|&x| f64::from(x)).sum::<f64>() / x.len() as f64).round() as i32
+        };
+        let reference = average(&flat);
+        let overshoot = if round.is_empty() {
+            reference
+        } else {
+            average(&round)
+        };
+        // Overshoots go beyond the reference, or do not exist
+        let overshoot = if (overshoot > reference) == top {
+            overshoot
+        } else {
+            reference
+        };
+        // Capital and small letters usually share the baseline
+        if zones
+            .iter()
+            .any(|x| x.top == top && (x.reference - reference).abs() <= fuzz)
+        {
+            continue;
+        }
+        zones.push(BlueZone {
+            top,
+            reference,
+            overshoot,
+        });
+    }
+    zones
+}
+
+// Control value table, with the reference and the overshoot of each zone
+pub fn autohint_cvt(zones: &[BlueZone]) -> Vec<u8> {
+    let mut cvt = Vec::with_capacity(zones.len() * 4);
+    for zone in zones.iter() {
+        for value in [zone.reference, zone.overshoot] {
+            let value = value.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+            cvt.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    cvt
+}
+
+// Font program defining the zone function. It takes the CVT index of a zone
+// reference, followed by its overshoot. The reference is rounded to the
+// pixel grid, and the overshoot is kept at a whole number of pixels from it,
+// so that it disappears at small sizes.
+pub fn autohint_fpgm() -> Vec<u8> {
+    vec![
+        0xb0, // PUSHB[0]
+        ZONE_FUNCTION,
+        0x2c, // FDEF[]
+        //
+        0x20, // DUP[]
+        0xb0, // PUSHB[0]
+        0x01, // n = 1
+        0x60, // ADD[], overshoot index
+        0x20, // DUP[]
+        0x45, // RCVT[]
+        0xb0, // PUSHB[0]
+        0x03, // k = 3
+        0x25, // CINDEX[], reference index
+        0x45, // RCVT[]
+        0x61, // SUB[], overshoot - reference
+        0x68, // ROUND[0]
+        0xb0, // PUSHB[0]
+        0x03, // k = 3
+        0x25, // CINDEX[], reference index
+        0x45, // RCVT[]
+        0x68, // ROUND[0]
+        0x60, // ADD[]
+        0x44, // WCVTP[], overshoot
+        //
+        0x20, // DUP[]
+        0x45, // RCVT[]
+        0x68, // ROUND[0]
+        0x44, // WCVTP[], reference
+        //
+        0x2d, // ENDF[]
+    ]
+}
+
+// CVT program that turns on Microsoft ClearType and dropout control, then
+// rounds the blue zones.
+pub fn autohint_prep(num_zones: usize) -> Vec<u8> {
+    let mut prep = vec![
+        0xb1, // PUSHB[1]
+        0x04, // value = 4
+        0x03, // s = 3
+        0x8e, // INSTRCTRL[], turn Microsoft ClearType on
+        //
+        0xb8, // PUSHW[0]
+        0x01, 0xff, // n = 0x01ff, always do dropout control
+        0x85, // SCANCTRL[]
+        //
+        0xb0, // PUSHB[0]
+        0x04, // n = 4, smart dropout control scan conversion including stubs
+        0x8d, // SCANTYPE[]
+    ];
+    if num_zones != 0 {
+        let mut arguments = (0..num_zones).map(|x| (x * 2) as u16).collect::<Vec<_>>();
+        arguments.push(num_zones as u16);
+        arguments.push(ZONE_FUNCTION.into());
+        push(&mut prep, &arguments);
+        prep.push(0x2a); // LOOPCALL[]
+    }
+    prep
+}
+
+// Appends the shortest instructions pushing the values.
+fn push(program: &mut Vec<u8>, values: &[u16]) {
+    for chunk in values.chunks(255) {
+        let bytes = chunk.iter().all(|&x| x <= 0xff);
+        match (bytes, chunk.len()) {
+            (true, 1..=8) => program.push(0xb0 + chunk.len() as u8 - 1), // PUSHB[n]
+            (false, 1..=8) => program.push(0xb8 + chunk.len() as u8 - 1), // PUSHW[n]
+            (true, _) => program.extend_from_slice(&[0x40, chunk.len() as u8]), // NPUSHB[]
+            (false, _) => program.extend_from_slice(&[0x41, chunk.len() as u8]), // NPUSHW[]
+        }
+        for &value in chunk.iter() {
+            if bytes {
+                program.push(value as u8);
+            } else {
+                program.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+}
+
+// Glyph program that fits the horizontal edges of a simple glyph to the
+// pixel grid, in the y-direction only. Edges in blue zones snap to the
+// zones, stems keep a whole number of pixels, and the other points are
+// interpolated. Returns the program and the stack depth it needs.
+pub fn autohint_glyph(
+    glyph: &SimpleGlyph,
+    zones: &[BlueZone],
+    units_per_em: u16,
+) -> (Vec<u8>, usize) {
+    let min_length = i32::from(units_per_em) / 40;
+    let fuzz = i32::from(units_per_em) / 40;
+    let max_stem_width = i32::from(units_per_em) / 5;
+    let edges = find_edges(glyph, min_length);
+    if edges.is_empty() {
+        return (Vec::new(), 0);
+    }
+
+    let mut program = vec![0x00]; // SVTCA[0]
+    let mut max_stack_elements = 2;
+    // Moves the remaining points of an edge along with its first point,
+    // which is rp0
+    let mut align_edge = |program: &mut Vec<u8>, edge: &Edge| {
+        let rest = edge.points[1..].iter().map(|&x| x as u16);
+        if rest.len() != 0 {
+            let mut arguments = rest.collect::<Vec<_>>();
+            arguments.push(arguments.len() as u16);
+            max_stack_elements = max_stack_elements.max(arguments.len());
+            push(program, &arguments);
+            program.push(0x17); // SLOOP[]
+            program.push(0x3c); // ALIGNRP[]
+        }
+    };
+
+    // Edges in blue zones go to the rounded reference or overshoot
+    let mut anchored = vec![false; edges.len()];
+    for (edge_index, edge) in edges.iter().enumerate() {
+        let zone = zones.iter().enumerate().find(|(_, zone)| {
+            let low = zone.reference.min(zone.overshoot) - fuzz;
+            let high = zone.reference.max(zone.overshoot) + fuzz;
+            zone.top == edge.top && (low..=high).contains(&edge.y)
+        });
+        if let Some((zone_index, zone)) = zone {
+            let cvt_index = if (edge.y - zone.reference).abs() <= (edge.y - zone.overshoot).abs() {
+                zone_index * 2
+            } else {
+                zone_index * 2 + 1
+            };
+            push(&mut program, &[edge.points[0] as u16, cvt_index as u16]);
+            program.push(0x3e); // MIAP[0]
+            align_edge(&mut program, edge);
+            anchored[edge_index] = true;
+        }
+    }
+
+    // Stems are a bottom edge and the nearest overlapping top edge above it
+    let mut candidates = Vec::new();
+    for (bottom_index, bottom) in edges.iter().enumerate().filter(|(_, x)| !x.top) {
+        for (top_index, top) in edges.iter().enumerate().filter(|(_, x)| x.top) {
+            let width = top.y - bottom.y;
+            if width > 0
+                && width <= max_stem_width
+                && top.x_min <= bottom.x_max
+                && bottom.x_min <= top.x_max
+            {
+                candidates.push((width, bottom_index, top_index));
+            }
+        }
+    }
+    candidates.sort_unstable();
+    let mut paired = vec![false; edges.len()];
+    let mut stems = Vec::new();
+    for (_, bottom_index, top_index) in candidates {
+        if !paired[bottom_index] && !paired[top_index] {
+            paired[bottom_index] = true;
+            paired[top_index] = true;
+            stems.push((bottom_index, top_index));
+        }
+    }
+    // From the bottom up, so that lower stems are fitted first
+    stems.sort_unstable_by_key(|&(bottom_index, _)| edges[bottom_index].y);
+    for (bottom_index, top_index) in stems {
+        let (from, to) = match (anchored[bottom_index], anchored[top_index]) {
+            (true, true) => continue,
+            (false, true) => (top_index, bottom_index),
+            (true, false) => (bottom_index, top_index),
+            (false, false) => {
+                push(&mut program, &[edges[bottom_index].points[0] as u16]);
+                program.push(0x2f); // MDAP[1], round
+                align_edge(&mut program, &edges[bottom_index]);
+                (bottom_index, top_index)
+            }
+        };
+        push(
+            &mut program,
+            &[edges[to].points[0] as u16, edges[from].points[0] as u16],
+        );
+        program.push(0x10); // SRP0[]
+        program.push(0xdc); // MDRP[11100], set rp0, keep minimum distance, round
+        align_edge(&mut program, &edges[to]);
+        anchored[from] = true;
+        anchored[to] = true;
+    }
+
+    if !anchored.contains(&true) {
+        return (Vec::new(), 0);
+    }
+    program.push(0x30); // IUP[0]
+    (program, max_stack_elements)
+}
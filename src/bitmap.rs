@@ -0,0 +1,450 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::raster::GrayscaleImage;
+use super::types::FourCC;
+
+// One strike from "EBLC" (or "bloc"), with its index subtables.
+#[derive(Clone)]
+pub struct BitmapSize {
+    // The original BitmapSize record, offsets are rewritten when building
+    pub record: [u8; 48],
+    pub subtables: Vec<IndexSubTable>,
+}
+
+#[derive(Clone)]
+pub struct IndexSubTable {
+    pub first_glyph_index: u16,
+    pub last_glyph_index: u16,
+    pub index_format: u16,
+    pub image_format: u16,
+    pub image_data_offset: u32,
+    // Everything after the 8-byte header, in the original byte order
+    pub body: Vec<u8>,
+}
+
+impl BitmapSize {
+    pub fn ppem_x(&self) -> u8 {
+        self.record[44]
+    }
+
+    pub fn ppem_y(&self) -> u8 {
+        self.record[45]
+    }
+}
+
+impl IndexSubTable {
+    // Returns each glyph that has image data, with the range of that data
+    // relative to image_data_offset.
+    pub fn glyph_images(&self) -> Vec<(u16, usize, usize)> {
+        let num_glyphs =
+            usize::from(self.last_glyph_index.saturating_sub(self.first_glyph_index)) + 1;
+        let u16_at =
+            |pos: usize| usize::from(u16::from_be_bytes([self.body[pos], self.body[pos + 1]]));
+        let u32_at = |pos: usize| {
+            usize::try_from(u32::from_be_bytes(
+                self.body[pos..pos + 4].try_into().unwrap(),
+            ))
+            .unwrap()
+        };
+        let mut images = Vec::new();
+        match self.index_format {
+            1 | 3 => {
+                let offset_at = |i: usize| {
+                    if self.index_format == 1 {
+                        u32_at(i * 4)
+                    } else {
+                        u16_at(i * 2)
+                    }
+                };
+                for i in 0..num_glyphs {
+                    let (from, to) = (offset_at(i), offset_at(i + 1));
+                    if from < to {
+                        images.push((self.first_glyph_index + u16::try_from(i).unwrap(), from, to));
+                    }
+                }
+            }
+            2 => {
+                let image_size = u32_at(0);
+                for i in 0..num_glyphs {
+                    images.push((
+                        self.first_glyph_index + u16::try_from(i).unwrap(),
+                        i * image_size,
+                        (i + 1) * image_size,
+                    ));
+                }
+            }
+            4 => {
+                let num_pairs = u32_at(0);
+                for i in 0..num_pairs {
+                    let glyph_id = u16_at(4 + i * 4);
+                    let (from, to) = (u16_at(6 + i * 4), u16_at(10 + i * 4));
+                    if from < to {
+                        images.push((u16::try_from(glyph_id).unwrap(), from, to));
+                    }
+                }
+            }
+            5 => {
+                let image_size = u32_at(0);
+                let num_ids = u32_at(12);
+                for i in 0..num_ids {
+                    images.push((
+                        u16::try_from(u16_at(16 + i * 2)).unwrap(),
+                        i * image_size,
+                        (i + 1) * image_size,
+                    ));
+                }
+            }
+            _ => unreachable!(),
+        }
+        images
+    }
+
+    // The range of image data referenced by this subtable,
+    // relative to image_data_offset.
+    fn data_range(&self) -> (usize, usize) {
+        let images = self.glyph_images();
+        (
+            images.iter().map(|x| x.1).min().unwrap_or(0),
+            images.iter().map(|x| x.2).max().unwrap_or(0),
+        )
+    }
+}
+
+// Parses "EBLC", "bloc" or "CBLC" into the version and a list of strikes.
+pub fn read_bitmap_location(
+    sfnt_index: usize,
+    table_tag: FourCC,
+    raw_loc: &[u8],
+) -> Result<(u32, Vec<BitmapSize>)> {
+    let truncated = |from: usize, to: usize| {
+        anyhow!(
+            "sfnt {} table {}: data truncated ({}..{})",
+            sfnt_index,
+            table_tag,
+            from,
+            to
+        )
+    };
+    let u16_at = |pos: usize| -> Result<u16> {
+        Ok(u16::from_be_bytes(
+            raw_loc
+                .get(pos..pos + 2)
+                .ok_or_else(|| truncated(pos, pos + 2))?
+                .try_into()?,
+        ))
+    };
+    let u32_at = |pos: usize| -> Result<usize> {
+        Ok(usize::try_from(u32::from_be_bytes(
+            raw_loc
+                .get(pos..pos + 4)
+                .ok_or_else(|| truncated(pos, pos + 4))?
+                .try_into()?,
+        ))
+        .unwrap())
+    };
+
+    let version = u32::try_from(u32_at(0)?).unwrap();
+    let num_sizes = u32_at(4)?;
+    let mut strikes = Vec::with_capacity(num_sizes.min(256));
+    for i in 0..num_sizes {
+        let record_pos = 8 + i * 48;
+        let record: [u8; 48] = raw_loc
+            .get(record_pos..record_pos + 48)
+            .ok_or_else(|| truncated(record_pos, record_pos + 48))?
+            .try_into()?;
+        let array_offset = u32_at(record_pos)?;
+        let num_subtables = u32_at(record_pos + 8)?;
+
+        let mut subtables = Vec::with_capacity(num_subtables.min(65536));
+        for j in 0..num_subtables {
+            let entry_pos = array_offset + j * 8;
+            let first_glyph_index = u16_at(entry_pos)?;
+            let last_glyph_index = u16_at(entry_pos + 2)?;
+            let subtable_pos = array_offset + u32_at(entry_pos + 4)?;
+            let index_format = u16_at(subtable_pos)?;
+            let image_format = u16_at(subtable_pos + 2)?;
+            let image_data_offset = u32::try_from(u32_at(subtable_pos + 4)?).unwrap();
+            if first_glyph_index > last_glyph_index {
+                bail!(
+                    "sfnt {} table {}: strike {} subtable {} has an invalid glyph range ({} > {})",
+                    sfnt_index,
+                    table_tag,
+                    i,
+                    j,
+                    first_glyph_index,
+                    last_glyph_index
+                );
+            }
+            let num_glyphs = usize::from(last_glyph_index - first_glyph_index) + 1;
+            let body_len = match index_format {
+                1 => (num_glyphs + 1) * 4,
+                2 => 12,
+                3 => (num_glyphs + 1) * 2,
+                4 => 4 + (u32_at(subtable_pos + 8)? + 1) * 4,
+                5 => 16 + u32_at(subtable_pos + 20)? * 2,
+                _ => bail!(
+                    "sfnt {} table {}: strike {} subtable {} has an unsupported index format: {}",
+                    sfnt_index,
+                    table_tag,
+                    i,
+                    j,
+                    index_format
+                ),
+            };
+            let body = raw_loc
+                .get(subtable_pos + 8..subtable_pos + 8 + body_len)
+                .ok_or_else(|| truncated(subtable_pos + 8, subtable_pos + 8 + body_len))?
+                .to_vec();
+            subtables.push(IndexSubTable {
+                first_glyph_index,
+                last_glyph_index,
+                index_format,
+                image_format,
+                image_data_offset,
+                body,
+            });
+        }
+        strikes.push(BitmapSize { record, subtables });
+    }
+    Ok((version, strikes))
+}
+
+// Builds new location and data tables for the given strikes, copying only the
+// image data that is still referenced from the original data table.
+pub fn build_bitmap_tables(
+    sfnt_index: usize,
+    table_tag: FourCC,
+    version: u32,
+    strikes: &[BitmapSize],
+    raw_dat: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    // Data table header: version, same as the location table
+    let mut new_dat = raw_dat.get(..4).unwrap_or(&[0, 2, 0, 0]).to_vec();
+    let mut copied_ranges = HashMap::<(usize, usize), usize>::new();
+
+    let mut new_loc = Vec::new();
+    new_loc.extend_from_slice(&version.to_be_bytes());
+    new_loc.extend_from_slice(&u32::try_from(strikes.len()).unwrap().to_be_bytes());
+    let mut array_pos = new_loc.len() + strikes.len() * 48;
+    let mut arrays = Vec::new();
+    for strike in strikes {
+        // IndexSubTableArray, followed by the subtables
+        let mut array = Vec::new();
+        let mut subtables = Vec::new();
+        let array_len = strike.subtables.len() * 8;
+        for subtable in strike.subtables.iter() {
+            let (from, to) = subtable.data_range();
+            let old_from = usize::try_from(subtable.image_data_offset).unwrap() + from;
+            let old_to = usize::try_from(subtable.image_data_offset).unwrap() + to;
+            let data = raw_dat.get(old_from..old_to).ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table {}: image data out of bounds ({}..{})",
+                    sfnt_index,
+                    table_tag,
+                    old_from,
+                    old_to
+                )
+            })?;
+            let new_from = *copied_ranges.entry((old_from, old_to)).or_insert_with(|| {
+                let pos = new_dat.len();
+                new_dat.extend_from_slice(data);
+                pos
+            });
+            let image_data_offset = u32::try_from(new_from - from).map_err(|_| {
+                anyhow!(
+                    "sfnt {} table {}: image data exceeds 4 GiB",
+                    sfnt_index,
+                    table_tag
+                )
+            })?;
+
+            array.extend_from_slice(&subtable.first_glyph_index.to_be_bytes());
+            array.extend_from_slice(&subtable.last_glyph_index.to_be_bytes());
+            array.extend_from_slice(
+                &u32::try_from(array_len + subtables.len())
+                    .unwrap()
+                    .to_be_bytes(),
+            );
+            subtables.extend_from_slice(&subtable.index_format.to_be_bytes());
+            subtables.extend_from_slice(&subtable.image_format.to_be_bytes());
+            subtables.extend_from_slice(&image_data_offset.to_be_bytes());
+            subtables.extend_from_slice(&subtable.body);
+            // Subtables are 32-bit aligned
+            while subtables.len() % 4 != 0 {
+                subtables.push(0);
+            }
+        }
+        array.extend(subtables);
+
+        let mut record = strike.record;
+        record[0..4].clone_from_slice(&u32::try_from(array_pos).unwrap().to_be_bytes());
+        record[4..8].clone_from_slice(&u32::try_from(array.len()).unwrap().to_be_bytes());
+        record[8..12]
+            .clone_from_slice(&u32::try_from(strike.subtables.len()).unwrap().to_be_bytes());
+        new_loc.extend_from_slice(&record);
+        array_pos += array.len();
+        arrays.push(array);
+    }
+    for array in arrays {
+        new_loc.extend(array);
+    }
+    Ok((new_loc, new_dat))
+}
+
+// A glyph rendered for a grayscale strike
+pub struct GrayscaleGlyph {
+    pub glyph_index: u16,
+    // Advance width in pixels
+    pub advance: i32,
+    pub image: GrayscaleImage,
+}
+
+// Builds a strike of image format 1 (small metrics, byte-aligned) with index
+// format 1, appending the image data to raw_dat.
+// ascender and descender are in pixels, glyphs are sorted by glyph index.
+// Returns None if no glyph can be stored.
+pub fn build_grayscale_strike(
+    sfnt_index: usize,
+    ppem: u8,
+    bit_depth: u8,
+    (ascender, descender): (i32, i32),
+    glyphs: &[GrayscaleGlyph],
+    raw_dat: &mut Vec<u8>,
+) -> Option<BitmapSize> {
+    let fits_i8 = |x: i32| i8::try_from(x).is_ok();
+    let fits_u8 = |x: i32| u8::try_from(x).is_ok();
+    let (glyphs, skipped): (Vec<_>, Vec<_>) = glyphs.iter().partition(|x| {
+        fits_u8(i32::try_from(x.image.width).unwrap_or(-1))
+            && fits_u8(i32::try_from(x.image.height).unwrap_or(-1))
+            && fits_i8(x.image.left)
+            && fits_i8(x.image.top)
+            && fits_u8(x.advance)
+    });
+    if !skipped.is_empty() {
+        eprintln!(
+            "[ WARN ] sfnt {} table “EBLC”: {} glyphs are too large for the {} ppem strike",
+            sfnt_index,
+            skipped.len(),
+            ppem
+        );
+    }
+    let first_glyph_index = glyphs.first()?.glyph_index;
+    let last_glyph_index = glyphs.last()?.glyph_index;
+
+    let image_data_offset = raw_dat.len();
+    let mut offsets = Vec::new();
+    let mut glyphs_iter = glyphs.iter().peekable();
+    for glyph_index in first_glyph_index..=last_glyph_index {
+        offsets.push(raw_dat.len() - image_data_offset);
+        let glyph = match glyphs_iter.next_if(|x| x.glyph_index == glyph_index) {
+            Some(glyph) => glyph,
+            None => continue,
+        };
+        // smallGlyphMetrics: height, width, bearingX, bearingY, advance
+        raw_dat.push(u8::try_from(glyph.image.height).unwrap());
+        raw_dat.push(u8::try_from(glyph.image.width).unwrap());
+        raw_dat.push(i8::try_from(glyph.image.left).unwrap() as u8);
+        raw_dat.push(i8::try_from(glyph.image.top).unwrap() as u8);
+        raw_dat.push(u8::try_from(glyph.advance).unwrap());
+        let max_value = (1u32 << bit_depth) - 1;
+        for row in glyph.image.coverage.chunks_exact(glyph.image.width) {
+            // Each row starts at a byte boundary, most significant bits first
+            let mut bits = 0u32;
+            let mut num_bits = 0;
+            for &x in row {
+                bits = bits << bit_depth | (x * max_value as f32 + 0.5) as u32;
+                num_bits += u32::from(bit_depth);
+                if num_bits == 8 {
+                    raw_dat.push(bits as u8);
+                    bits = 0;
+                    num_bits = 0;
+                }
+            }
+            if num_bits != 0 {
+                raw_dat.push((bits << (8 - num_bits)) as u8);
+            }
+        }
+    }
+    offsets.push(raw_dat.len() - image_data_offset);
+
+    let mut body = Vec::with_capacity(offsets.len() * 4);
+    for offset in offsets {
+        body.extend_from_slice(&u32::try_from(offset).ok()?.to_be_bytes());
+    }
+
+    // sbitLineMetrics: ascender, descender, widthMax, caretSlopeNumerator,
+    // caretSlopeDenominator, caretOffset, minOriginSB, minAdvanceSB,
+    // maxBeforeBL, minAfterBL, pad1, pad2
+    let clamp_i8 = |x: i32| x.clamp(-128, 127) as i8 as u8;
+    let line_metrics = [
+        clamp_i8(ascender),
+        clamp_i8(descender),
+        glyphs.iter().map(|x| x.image.width).max().unwrap_or(0) as u8,
+        1,
+        0,
+        0,
+        clamp_i8(glyphs.iter().map(|x| x.image.left).min().unwrap_or(0)),
+        clamp_i8(
+            glyphs
+                .iter()
+                .map(|x| x.advance - x.image.left - i32::try_from(x.image.width).unwrap())
+                .min()
+                .unwrap_or(0),
+        ),
+        clamp_i8(glyphs.iter().map(|x| x.image.top).max().unwrap_or(0)),
+        clamp_i8(
+            glyphs
+                .iter()
+                .map(|x| x.image.top - i32::try_from(x.image.height).unwrap())
+                .min()
+                .unwrap_or(0),
+        ),
+        0,
+        0,
+    ];
+    let mut record = [0u8; 48];
+    // byte 0..16: offsets, sizes and colorRef, filled in when building
+    record[16..28].clone_from_slice(&line_metrics);
+    record[28..40].clone_from_slice(&line_metrics);
+    record[40..42].clone_from_slice(&first_glyph_index.to_be_bytes());
+    record[42..44].clone_from_slice(&last_glyph_index.to_be_bytes());
+    record[44] = ppem;
+    record[45] = ppem;
+    record[46] = bit_depth;
+    // HORIZONTAL_METRICS
+    record[47] = 0x01;
+
+    Some(BitmapSize {
+        record,
+        subtables: vec![IndexSubTable {
+            first_glyph_index,
+            last_glyph_index,
+            index_format: 1,
+            image_format: 1,
+            image_data_offset: u32::try_from(image_data_offset).ok()?,
+            body,
+        }],
+    })
+}
@@ -0,0 +1,1889 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::types::FourCC;
+
+// Top DICT operators
+const OP_CHARSET: u16 = 15;
+const OP_ENCODING: u16 = 16;
+const OP_CHAR_STRINGS: u16 = 17;
+const OP_PRIVATE: u16 = 18;
+const OP_VSTORE: u16 = 24;
+const OP_CHARSTRING_TYPE: u16 = 0x0c06;
+const OP_FONT_MATRIX: u16 = 0x0c07;
+const OP_ROS: u16 = 0x0c1e;
+const OP_FD_ARRAY: u16 = 0x0c24;
+const OP_FD_SELECT: u16 = 0x0c25;
+
+// Private DICT operators
+const OP_SUBRS: u16 = 19;
+const OP_VSINDEX: u16 = 22;
+// BlueValues, OtherBlues, FamilyBlues, FamilyOtherBlues, StdHW, StdVW,
+// BlueScale, BlueShift, BlueFuzz, StemSnapH, StemSnapV, ForceBold
+const PRIVATE_HINTING_OPERATORS: [u16; 12] = [
+    6, 7, 8, 9, 10, 11, 0x0c09, 0x0c0a, 0x0c0b, 0x0c0c, 0x0c0d, 0x0c0e,
+];
+
+// DICT operator that only appears among the operands in CFF2
+const OP_BLEND: u8 = 23;
+
+// Type 2 CharString operators
+const CS_HSTEM: u8 = 1;
+const CS_VSTEM: u8 = 3;
+const CS_RETURN: u8 = 11;
+const CS_ESCAPE: u8 = 12;
+const CS_ENDCHAR: u8 = 14;
+const CS_VSINDEX: u8 = 15;
+const CS_BLEND: u8 = 16;
+const CS_HSTEMHM: u8 = 18;
+const CS_HINTMASK: u8 = 19;
+const CS_CNTRMASK: u8 = 20;
+const CS_VSTEMHM: u8 = 23;
+const CS_CALLSUBR: u8 = 10;
+const CS_CALLGSUBR: u8 = 29;
+const CS_SHORTINT: u8 = 28;
+
+const MAX_SUBR_NESTING: usize = 10;
+
+// Removes hinting from a "CFF " or "CFF2" table: stem hints and hint masks
+// from the CharStrings and subroutines, and alignment zones and standard stem
+// widths from the Private DICTs. Variation data is kept. Returns the new table.
+pub fn remove_cff_hinting(sfnt_index: usize, table_tag: FourCC, raw_cff: &[u8]) -> Result<Vec<u8>> {
+    let cff2 = table_tag == FourCC(*b"CFF2");
+    let err = Context {
+        sfnt_index,
+        table_tag,
+        cff2,
+    };
+
+    let font = read_font(&err, raw_cff)?;
+    let Font {
+        global_subrs,
+        charstrings,
+        variation_store,
+        privates,
+        glyph_fds,
+        default_vsindices,
+        ..
+    } = &font;
+
+    // Find out the hint mask length used by each CharString
+    let mut tracer = Tracer {
+        err: &err,
+        global_subrs,
+        local_subrs: privates.iter().map(|x| x.1.as_slice()).collect(),
+        region_counts: variation_store.as_ref().map_or(&[], |x| x.1.as_slice()),
+        mask_lengths: HashMap::new(),
+    };
+    for (glyph_index, charstring) in charstrings.iter().enumerate() {
+        let fd = glyph_fds[glyph_index];
+        let mut state = TraceState {
+            fd,
+            vsindex: default_vsindices[fd],
+            stack: Vec::new(),
+            num_stems: 0,
+            depth: 0,
+        };
+        tracer.trace(Charstring::Glyph(glyph_index), charstring, &mut state)?;
+    }
+
+    let mask_length = |key: Charstring| tracer.mask_lengths.get(&key).copied();
+    let new_charstrings = charstrings
+        .iter()
+        .enumerate()
+        .map(|(i, x)| strip_charstring(&err, x, mask_length(Charstring::Glyph(i))))
+        .collect::<Result<Vec<_>>>()?;
+    let new_global_subrs = strip_subrs(&err, global_subrs, |i| mask_length(Charstring::Global(i)))?;
+    let new_privates = privates
+        .iter()
+        .enumerate()
+        .map(|(fd, (private, local_subrs))| {
+            let mut private = private.clone();
+            for &op in PRIVATE_HINTING_OPERATORS.iter() {
+                private.remove(op);
+            }
+            let local_subrs =
+                strip_subrs(&err, local_subrs, |i| mask_length(Charstring::Local(fd, i)))?;
+            Ok((private, local_subrs))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let raw_header = font.raw_header;
+    write_font(
+        &err,
+        raw_cff,
+        font,
+        raw_header,
+        &new_charstrings,
+        &new_global_subrs,
+        new_privates,
+    )
+}
+
+// Replaces the FontName in the Name INDEX of a "CFF " table. Returns the new
+// table.
+pub fn rename_cff(sfnt_index: usize, raw_cff: &[u8], font_name: &str) -> Result<Vec<u8>> {
+    let err = Context {
+        sfnt_index,
+        table_tag: FourCC(*b"CFF "),
+        cff2: false,
+    };
+    let font = read_font(&err, raw_cff)?;
+    let header_size = usize::from(font.raw_header[2]);
+    let mut raw_header = font.raw_header[..header_size].to_vec();
+    raw_header.extend(build_index(false, &[font_name.as_bytes()]));
+    let charstrings = font.charstrings.clone();
+    let global_subrs = font.global_subrs.clone();
+    let privates = font.privates.clone();
+    write_font(
+        &err,
+        raw_cff,
+        font,
+        &raw_header,
+        &charstrings,
+        &global_subrs,
+        privates,
+    )
+}
+
+// Writes a CFF or CFF2 table with new header and Name INDEX, CharStrings,
+// global subroutines, and Private DICTs with their local subroutines. Other
+// tables are copied verbatim.
+fn write_font(
+    err: &Context,
+    raw_cff: &[u8],
+    font: Font,
+    raw_header: &[u8],
+    new_charstrings: &[impl AsRef<[u8]>],
+    new_global_subrs: &[impl AsRef<[u8]>],
+    new_privates: Vec<(Dict, Vec<impl AsRef<[u8]>>)>,
+) -> Result<Vec<u8>> {
+    let cff2 = err.cff2;
+    let Font {
+        mut top_dict,
+        raw_string_index,
+        variation_store,
+        mut font_dicts,
+        fd_select,
+        ..
+    } = font;
+    let num_glyphs = new_charstrings.len();
+
+    // Tables between the header and the CharStrings are copied verbatim
+    let charset = match top_dict.offset(err, OP_CHARSET)? {
+        // Predefined charsets
+        Some(0..=2) | None => None,
+        Some(offset) => Some(read_charset(err, raw_cff, offset, num_glyphs)?),
+    };
+    let encoding = match top_dict.offset(err, OP_ENCODING)? {
+        // Predefined encodings
+        Some(0..=1) | None => None,
+        Some(offset) => Some(read_encoding(err, raw_cff, offset)?),
+    };
+
+    // Offsets are always written as 5-byte integers, so the size of each DICT
+    // is known before the layout is final.
+    let new_charstrings_index = build_index(cff2, new_charstrings);
+    let new_global_subrs_index = build_index(cff2, new_global_subrs);
+    let mut new_privates_data = Vec::with_capacity(new_privates.len());
+    for (mut private, local_subrs) in new_privates {
+        if private.contains(OP_SUBRS) {
+            private.set_offsets(OP_SUBRS, &[0]);
+            let private_len = private.to_bytes().len();
+            private.set_offsets(OP_SUBRS, &[private_len]);
+        }
+        let mut data = private.to_bytes();
+        let private_len = data.len();
+        if private.contains(OP_SUBRS) {
+            data.extend(build_index(cff2, &local_subrs));
+        }
+        new_privates_data.push((private_len, data));
+    }
+
+    let layout = |top_dict: &mut Dict, font_dicts: &mut Option<Vec<Dict>>, base: usize| {
+        let mut pos = base;
+        if let Some((raw_variation_store, _)) = &variation_store {
+            top_dict.set_offsets(OP_VSTORE, &[pos]);
+            pos += raw_variation_store.len();
+        }
+        if let Some(charset) = charset {
+            top_dict.set_offsets(OP_CHARSET, &[pos]);
+            pos += charset.len();
+        }
+        if let Some(encoding) = encoding {
+            top_dict.set_offsets(OP_ENCODING, &[pos]);
+            pos += encoding.len();
+        }
+        if let Some((raw_fd_select, _)) = &fd_select {
+            top_dict.set_offsets(OP_FD_SELECT, &[pos]);
+            pos += raw_fd_select.len();
+        }
+        top_dict.set_offsets(OP_CHAR_STRINGS, &[pos]);
+        pos += new_charstrings_index.len();
+        let (private_dicts, mut private_pos): (&mut [Dict], _) = match font_dicts {
+            Some(font_dicts) => {
+                top_dict.set_offsets(OP_FD_ARRAY, &[pos]);
+                let raw_font_dicts = font_dicts.iter().map(Dict::to_bytes).collect::<Vec<_>>();
+                (font_dicts, pos + build_index(cff2, &raw_font_dicts).len())
+            }
+            None => (std::slice::from_mut(top_dict), pos),
+        };
+        for (dict, (private_len, data)) in private_dicts.iter_mut().zip(new_privates_data.iter()) {
+            dict.set_offsets(OP_PRIVATE, &[*private_len, private_pos]);
+            private_pos += data.len();
+        }
+    };
+    // The Top DICT is stored in an INDEX in CFF, or by itself in CFF2
+    let raw_top_dict_data = |top_dict: &Dict| {
+        if cff2 {
+            top_dict.to_bytes()
+        } else {
+            build_index(cff2, &[top_dict.to_bytes()])
+        }
+    };
+
+    // First pass with placeholder offsets to find the sizes, then the real one
+    layout(&mut top_dict, &mut font_dicts, 0);
+    let base = raw_header.len()
+        + raw_top_dict_data(&top_dict).len()
+        + raw_string_index.len()
+        + new_global_subrs_index.len();
+    layout(&mut top_dict, &mut font_dicts, base);
+
+    let mut new_cff = Vec::with_capacity(raw_cff.len());
+    new_cff.extend_from_slice(raw_header);
+    let new_top_dict_data = raw_top_dict_data(&top_dict);
+    if cff2 {
+        let top_dict_length = u16::try_from(new_top_dict_data.len()).map_err(|_| {
+            anyhow!(
+                "sfnt {} table {}: Top DICT too large",
+                err.sfnt_index,
+                err.table_tag
+            )
+        })?;
+        new_cff[3..5].copy_from_slice(&top_dict_length.to_be_bytes());
+    }
+    new_cff.extend(new_top_dict_data);
+    new_cff.extend_from_slice(raw_string_index);
+    new_cff.extend(new_global_subrs_index);
+    debug_assert_eq!(new_cff.len(), base);
+    if let Some((raw_variation_store, _)) = &variation_store {
+        new_cff.extend_from_slice(raw_variation_store);
+    }
+    if let Some(charset) = charset {
+        new_cff.extend_from_slice(charset);
+    }
+    if let Some(encoding) = encoding {
+        new_cff.extend_from_slice(encoding);
+    }
+    if let Some((raw_fd_select, _)) = &fd_select {
+        new_cff.extend_from_slice(raw_fd_select);
+    }
+    new_cff.extend(new_charstrings_index);
+    if let Some(font_dicts) = &font_dicts {
+        new_cff.extend(build_index(
+            cff2,
+            &font_dicts.iter().map(Dict::to_bytes).collect::<Vec<_>>(),
+        ));
+    }
+    for (_, data) in new_privates_data {
+        new_cff.extend(data);
+    }
+    Ok(new_cff)
+}
+
+// The parts of a CFF or CFF2 table needed to rewrite or interpret it
+struct Font<'a> {
+    top_dict: Dict,
+    // Header and Name INDEX, or only the header in CFF2
+    raw_header: &'a [u8],
+    // Empty in CFF2
+    raw_string_index: &'a [u8],
+    global_subrs: Vec<&'a [u8]>,
+    charstrings: Vec<&'a [u8]>,
+    // Raw vstore and the number of regions of each ItemVariationData
+    variation_store: Option<(&'a [u8], Vec<usize>)>,
+    // Present in CID-keyed fonts and in CFF2
+    font_dicts: Option<Vec<Dict>>,
+    fd_select: Option<(&'a [u8], Vec<u16>)>,
+    // Private DICTs with their local subroutines
+    privates: Vec<(Dict, Vec<&'a [u8]>)>,
+    // Index into privates for each glyph
+    glyph_fds: Vec<usize>,
+    default_vsindices: Vec<usize>,
+}
+
+fn read_font<'a>(err: &Context, raw_cff: &'a [u8]) -> Result<Font<'a>> {
+    let header = err.get(raw_cff, 0, if err.cff2 { 5 } else { 4 })?;
+    if header[0] != if err.cff2 { 2 } else { 1 } {
+        bail!(
+            "sfnt {} table {}: unsupported major version: {}",
+            err.sfnt_index,
+            err.table_tag,
+            header[0]
+        );
+    }
+    let header_size = usize::from(header[2]);
+
+    // CFF:  Header, Name INDEX, Top DICT INDEX, String INDEX, Global Subr INDEX
+    // CFF2: Header, Top DICT, Global Subr INDEX
+    let (raw_top_dict, name_index_end, raw_string_index, global_subrs_offset) = if err.cff2 {
+        let top_dict_length = usize::from(u16::from_be_bytes([header[3], header[4]]));
+        let top_dict_end = header_size + top_dict_length;
+        let raw_top_dict = err.get(raw_cff, header_size, top_dict_end)?;
+        (raw_top_dict, header_size, &[][..], top_dict_end)
+    } else {
+        let (names, name_index_end) = read_index(err, raw_cff, header_size)?;
+        let (top_dicts, top_dict_index_end) = read_index(err, raw_cff, name_index_end)?;
+        let (_, string_index_end) = read_index(err, raw_cff, top_dict_index_end)?;
+        if names.len() != 1 || top_dicts.len() != 1 {
+            bail!(
+                "sfnt {} table {}: expected 1 font, found {}",
+                err.sfnt_index,
+                err.table_tag,
+                names.len()
+            );
+        }
+        let raw_string_index = &raw_cff[top_dict_index_end..string_index_end];
+        (
+            top_dicts[0],
+            name_index_end,
+            raw_string_index,
+            string_index_end,
+        )
+    };
+    let (global_subrs, _) = read_index(err, raw_cff, global_subrs_offset)?;
+
+    let top_dict = Dict::parse(err, raw_top_dict)?;
+    if let Some(charstring_type) = top_dict.integers(err, OP_CHARSTRING_TYPE)? {
+        if charstring_type != [2] {
+            bail!(
+                "sfnt {} table {}: unsupported CharstringType: {:?}",
+                err.sfnt_index,
+                err.table_tag,
+                charstring_type
+            );
+        }
+    }
+    let charstrings_offset = top_dict.offset(err, OP_CHAR_STRINGS)?.ok_or_else(|| {
+        anyhow!(
+            "sfnt {} table {}: no CharStrings",
+            err.sfnt_index,
+            err.table_tag
+        )
+    })?;
+    let (charstrings, _) = read_index(err, raw_cff, charstrings_offset)?;
+    let num_glyphs = charstrings.len();
+
+    // Variation data is copied verbatim, only the number of regions is needed
+    // to know how many operands each blend takes.
+    let variation_store = match top_dict.offset(err, OP_VSTORE)? {
+        Some(offset) if err.cff2 => Some(read_variation_store(err, raw_cff, offset)?),
+        _ => None,
+    };
+
+    // Private DICTs with their local subroutines, and which one each glyph uses
+    let font_dicts = match top_dict.offset(err, OP_FD_ARRAY)? {
+        Some(fd_array_offset) => {
+            let (raw_font_dicts, _) = read_index(err, raw_cff, fd_array_offset)?;
+            let font_dicts = raw_font_dicts
+                .iter()
+                .map(|x| Dict::parse(err, x))
+                .collect::<Result<Vec<_>>>()?;
+            Some(font_dicts)
+        }
+        None if err.cff2 => bail!(
+            "sfnt {} table {}: no FDArray",
+            err.sfnt_index,
+            err.table_tag
+        ),
+        None => None,
+    };
+    let fd_select = match (&font_dicts, top_dict.offset(err, OP_FD_SELECT)?) {
+        (Some(_), Some(fd_select_offset)) => {
+            Some(read_fd_select(err, raw_cff, fd_select_offset, num_glyphs)?)
+        }
+        // FDSelect is optional in CFF2 if there is only one Font DICT
+        (Some(_), None) if !err.cff2 => {
+            bail!(
+                "sfnt {} table {}: no FDSelect",
+                err.sfnt_index,
+                err.table_tag
+            )
+        }
+        _ => None,
+    };
+    let mut privates = Vec::new();
+    if let Some(font_dicts) = &font_dicts {
+        for font_dict in font_dicts.iter() {
+            privates.push(read_private(err, raw_cff, font_dict)?);
+        }
+    } else {
+        privates.push(read_private(err, raw_cff, &top_dict)?);
+    }
+    let glyph_fds = (0..num_glyphs)
+        .map(|glyph_index| {
+            let fd = fd_select
+                .as_ref()
+                .map_or(0, |x| usize::from(x.1[glyph_index]));
+            if fd >= privates.len() {
+                bail!(
+                    "sfnt {} table {}: glyph {} uses an invalid Font DICT {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    glyph_index,
+                    fd
+                );
+            }
+            Ok(fd)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let default_vsindices = privates
+        .iter()
+        .map(|(private, _)| {
+            let vsindex = private.integers(err, OP_VSINDEX)?;
+            let vsindex = vsindex
+                .as_ref()
+                .and_then(|x| x.last())
+                .copied()
+                .unwrap_or(0);
+            usize::try_from(vsindex).map_err(|_| {
+                anyhow!(
+                    "sfnt {} table {}: invalid vsindex {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    vsindex
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Font {
+        top_dict,
+        raw_header: &raw_cff[..name_index_end],
+        raw_string_index,
+        global_subrs,
+        charstrings,
+        variation_store,
+        font_dicts,
+        fd_select,
+        privates,
+        glyph_fds,
+        default_vsindices,
+    })
+}
+
+struct Context {
+    sfnt_index: usize,
+    table_tag: FourCC,
+    cff2: bool,
+}
+
+impl Context {
+    fn truncated(&self, from: usize, to: usize) -> anyhow::Error {
+        anyhow!(
+            "sfnt {} table {}: data truncated ({}..{})",
+            self.sfnt_index,
+            self.table_tag,
+            from,
+            to
+        )
+    }
+
+    fn get<'a>(&self, data: &'a [u8], from: usize, to: usize) -> Result<&'a [u8]> {
+        data.get(from..to).ok_or_else(|| self.truncated(from, to))
+    }
+}
+
+// Reads an INDEX, returns the items and the end position.
+fn read_index<'a>(err: &Context, data: &'a [u8], pos: usize) -> Result<(Vec<&'a [u8]>, usize)> {
+    // The count is 32-bit in CFF2
+    let count_size = if err.cff2 { 4 } else { 2 };
+    let count = err
+        .get(data, pos, pos + count_size)?
+        .iter()
+        .fold(0usize, |acc, &b| acc << 8 | usize::from(b));
+    if count == 0 {
+        return Ok((Vec::new(), pos + count_size));
+    }
+    let off_size_pos = pos + count_size;
+    let off_size = usize::from(err.get(data, off_size_pos, off_size_pos + 1)?[0]);
+    if !(1..=4).contains(&off_size) {
+        bail!(
+            "sfnt {} table {}: invalid INDEX offSize {} at {}",
+            err.sfnt_index,
+            err.table_tag,
+            off_size,
+            pos
+        );
+    }
+    let offsets_pos = off_size_pos + 1;
+    let offsets = err.get(data, offsets_pos, offsets_pos + (count + 1) * off_size)?;
+    let offsets = offsets
+        .chunks_exact(off_size)
+        .map(|x| x.iter().fold(0usize, |acc, &b| acc << 8 | usize::from(b)))
+        .collect::<Vec<_>>();
+    // Offsets are 1-based, relative to the byte before the object data
+    let data_base = offsets_pos + (count + 1) * off_size - 1;
+    let mut items = Vec::with_capacity(count);
+    for x in offsets.windows(2) {
+        if x[0] == 0 || x[0] > x[1] {
+            bail!(
+                "sfnt {} table {}: invalid INDEX offsets at {}",
+                err.sfnt_index,
+                err.table_tag,
+                pos
+            );
+        }
+        items.push(err.get(data, data_base + x[0], data_base + x[1])?);
+    }
+    Ok((items, data_base + offsets[count]))
+}
+
+fn build_index(cff2: bool, items: &[impl AsRef<[u8]>]) -> Vec<u8> {
+    let mut index = Vec::new();
+    if cff2 {
+        index.extend_from_slice(&u32::try_from(items.len()).unwrap().to_be_bytes());
+    } else {
+        index.extend_from_slice(&u16::try_from(items.len()).unwrap().to_be_bytes());
+    }
+    if items.is_empty() {
+        return index;
+    }
+    let data_len = items.iter().map(|x| x.as_ref().len()).sum::<usize>();
+    let off_size = match data_len + 1 {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x10000..=0xffffff => 3,
+        _ => 4,
+    };
+    index.push(u8::try_from(off_size).unwrap());
+    let mut offset = 1usize;
+    for item in items.iter().map(AsRef::as_ref).chain([[].as_ref()]) {
+        index.extend_from_slice(&u32::try_from(offset).unwrap().to_be_bytes()[4 - off_size..]);
+        offset += item.len();
+    }
+    for item in items {
+        index.extend_from_slice(item.as_ref());
+    }
+    index
+}
+
+// A DICT, keeping the original encoding of each operand
+#[derive(Clone)]
+struct Dict(Vec<(u16, Vec<u8>)>);
+
+impl Dict {
+    fn parse(err: &Context, data: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut operands = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let b0 = data[pos];
+            let len = match b0 {
+                // Variation data stays with the operands it applies to
+                OP_BLEND if err.cff2 => 1,
+                0..=27 => {
+                    let operator = if b0 == 12 {
+                        pos += 1;
+                        0x0c00
+                            | u16::from(*data.get(pos).ok_or_else(|| err.truncated(pos, pos + 1))?)
+                    } else {
+                        u16::from(b0)
+                    };
+                    entries.push((operator, std::mem::take(&mut operands)));
+                    pos += 1;
+                    continue;
+                }
+                28 => 3,
+                29 => 5,
+                // Real number, nibbles until 0xf
+                30 => {
+                    let end = data[pos + 1..]
+                        .iter()
+                        .position(|x| x & 0x0f == 0x0f || x & 0xf0 == 0xf0)
+                        .ok_or_else(|| err.truncated(pos, data.len() + 1))?;
+                    end + 2
+                }
+                32..=246 => 1,
+                247..=254 => 2,
+                _ => bail!(
+                    "sfnt {} table {}: invalid DICT data ({})",
+                    err.sfnt_index,
+                    err.table_tag,
+                    b0
+                ),
+            };
+            operands.extend_from_slice(err.get(data, pos, pos + len)?);
+            pos += len;
+        }
+        Ok(Self(entries))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (operator, operands) in self.0.iter() {
+            data.extend_from_slice(operands);
+            if operator & 0xff00 == 0x0c00 {
+                data.push(12);
+            }
+            data.push(*operator as u8);
+        }
+        data
+    }
+
+    fn contains(&self, operator: u16) -> bool {
+        self.0.iter().any(|x| x.0 == operator)
+    }
+
+    fn remove(&mut self, operator: u16) {
+        self.0.retain(|x| x.0 != operator);
+    }
+
+    // Reads integer operands, real numbers are not expected here.
+    fn integers(&self, err: &Context, operator: u16) -> Result<Option<Vec<i32>>> {
+        let operands = match self.0.iter().find(|x| x.0 == operator) {
+            Some((_, operands)) => operands,
+            None => return Ok(None),
+        };
+        let mut values = Vec::new();
+        let mut pos = 0;
+        while pos < operands.len() {
+            let (value, len) = read_dict_integer(operands, pos).ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table {}: expected integer operands for DICT operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    operator
+                )
+            })?;
+            values.push(value);
+            pos += len;
+        }
+        Ok(Some(values))
+    }
+
+    // Reads operands that may be real numbers.
+    fn numbers(&self, err: &Context, operator: u16) -> Result<Option<Vec<f64>>> {
+        let operands = match self.0.iter().find(|x| x.0 == operator) {
+            Some((_, operands)) => operands,
+            None => return Ok(None),
+        };
+        let mut values = Vec::new();
+        let mut pos = 0;
+        while pos < operands.len() {
+            if let Some((value, len)) = read_dict_integer(operands, pos) {
+                values.push(f64::from(value));
+                pos += len;
+                continue;
+            }
+            if operands[pos] != 30 {
+                bail!(
+                    "sfnt {} table {}: expected numeric operands for DICT operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    operator
+                );
+            }
+            // Real number, nibbles until 0xf
+            let mut text = String::new();
+            pos += 1;
+            'nibbles: while let Some(&b) = operands.get(pos) {
+                pos += 1;
+                for &nibble in [b >> 4, b & 0x0f].iter() {
+                    match nibble {
+                        0..=9 => text.push(char::from(b'0' + nibble)),
+                        0xa => text.push('.'),
+                        0xb => text.push('E'),
+                        0xc => text.push_str("E-"),
+                        0xe => text.push('-'),
+                        0xf => break 'nibbles,
+                        _ => (),
+                    }
+                }
+            }
+            values.push(text.parse::<f64>().map_err(|_| {
+                anyhow!(
+                    "sfnt {} table {}: invalid real number “{}” for DICT operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    text,
+                    operator
+                )
+            })?);
+        }
+        Ok(Some(values))
+    }
+
+    // Reads the last operand as an offset.
+    fn offset(&self, err: &Context, operator: u16) -> Result<Option<usize>> {
+        match self.integers(err, operator)? {
+            Some(values) => match values.last().map(|&x| usize::try_from(x)) {
+                Some(Ok(offset)) => Ok(Some(offset)),
+                _ => bail!(
+                    "sfnt {} table {}: invalid offset for DICT operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    operator
+                ),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Replaces the operands with 5-byte integers.
+    fn set_offsets(&mut self, operator: u16, values: &[usize]) {
+        let mut operands = Vec::with_capacity(values.len() * 5);
+        for &value in values {
+            operands.push(29);
+            operands.extend_from_slice(&i32::try_from(value).unwrap().to_be_bytes());
+        }
+        match self.0.iter_mut().find(|x| x.0 == operator) {
+            Some(entry) => entry.1 = operands,
+            None => self.0.push((operator, operands)),
+        }
+    }
+}
+
+// Returns the value and length of an integer DICT operand, or None for a real number.
+fn read_dict_integer(operands: &[u8], pos: usize) -> Option<(i32, usize)> {
+    let b0 = operands[pos];
+    let byte = |i: usize| operands.get(pos + i).copied();
+    Some(match b0 {
+        28 => (i32::from(i16::from_be_bytes([byte(1)?, byte(2)?])), 3),
+        29 => (
+            i32::from_be_bytes([byte(1)?, byte(2)?, byte(3)?, byte(4)?]),
+            5,
+        ),
+        32..=246 => (i32::from(b0) - 139, 1),
+        247..=250 => ((i32::from(b0) - 247) * 256 + i32::from(byte(1)?) + 108, 2),
+        251..=254 => (-(i32::from(b0) - 251) * 256 - i32::from(byte(1)?) - 108, 2),
+        _ => return None,
+    })
+}
+
+// Reads the Private DICT referenced by a Top DICT or Font DICT, and its local subroutines.
+fn read_private<'a>(
+    err: &Context,
+    raw_cff: &'a [u8],
+    parent: &Dict,
+) -> Result<(Dict, Vec<&'a [u8]>)> {
+    let (size, offset) = match parent.integers(err, OP_PRIVATE)?.as_deref() {
+        Some(&[size, offset]) => match (usize::try_from(size), usize::try_from(offset)) {
+            (Ok(size), Ok(offset)) => (size, offset),
+            _ => bail!(
+                "sfnt {} table {}: invalid Private DICT location",
+                err.sfnt_index,
+                err.table_tag
+            ),
+        },
+        // Private DICT is required, but an empty one is harmless
+        _ => return Ok((Dict(Vec::new()), Vec::new())),
+    };
+    let private = Dict::parse(err, err.get(raw_cff, offset, offset + size)?)?;
+    let local_subrs = match private.offset(err, OP_SUBRS)? {
+        Some(subrs_offset) => read_index(err, raw_cff, offset + subrs_offset)?.0,
+        None => Vec::new(),
+    };
+    Ok((private, local_subrs))
+}
+
+// Returns the raw FDSelect and the Font DICT index of each glyph.
+fn read_fd_select<'a>(
+    err: &Context,
+    raw_cff: &'a [u8],
+    offset: usize,
+    num_glyphs: usize,
+) -> Result<(&'a [u8], Vec<u16>)> {
+    let format = err.get(raw_cff, offset, offset + 1)?[0];
+    match format {
+        0 => {
+            let raw = err.get(raw_cff, offset, offset + 1 + num_glyphs)?;
+            Ok((raw, raw[1..].iter().map(|&x| u16::from(x)).collect()))
+        }
+        3 => {
+            let num_ranges = usize::from(u16::from_be_bytes(
+                err.get(raw_cff, offset + 1, offset + 3)?
+                    .try_into()
+                    .unwrap(),
+            ));
+            let raw = err.get(raw_cff, offset, offset + 5 + num_ranges * 3)?;
+            let mut fds = vec![0; num_glyphs];
+            for i in 0..num_ranges {
+                let range = &raw[3 + i * 3..8 + i * 3];
+                let first = usize::from(u16::from_be_bytes([range[0], range[1]]));
+                let next = usize::from(u16::from_be_bytes([range[3], range[4]]));
+                for fd in fds.iter_mut().take(next).skip(first) {
+                    *fd = u16::from(range[2]);
+                }
+            }
+            Ok((raw, fds))
+        }
+        // CFF2 only, 32-bit glyph indices and 16-bit Font DICT indices
+        4 if err.cff2 => {
+            let num_ranges = u32::from_be_bytes(
+                err.get(raw_cff, offset + 1, offset + 5)?
+                    .try_into()
+                    .unwrap(),
+            );
+            let num_ranges = usize::try_from(num_ranges).unwrap();
+            let raw = err.get(raw_cff, offset, offset + 9 + num_ranges * 6)?;
+            let mut fds = vec![0; num_glyphs];
+            for i in 0..num_ranges {
+                let range = &raw[5 + i * 6..15 + i * 6];
+                let first = u32::from_be_bytes(range[0..4].try_into().unwrap());
+                let next = u32::from_be_bytes(range[6..10].try_into().unwrap());
+                let fd = u16::from_be_bytes([range[4], range[5]]);
+                let (first, next) = (
+                    usize::try_from(first).unwrap(),
+                    usize::try_from(next).unwrap(),
+                );
+                for x in fds.iter_mut().take(next).skip(first) {
+                    *x = fd;
+                }
+            }
+            Ok((raw, fds))
+        }
+        _ => bail!(
+            "sfnt {} table {}: unsupported FDSelect format: {}",
+            err.sfnt_index,
+            err.table_tag,
+            format
+        ),
+    }
+}
+
+// Returns the raw vstore data, including its length field, and the number of
+// regions of each ItemVariationData.
+fn read_variation_store<'a>(
+    err: &Context,
+    raw_cff: &'a [u8],
+    offset: usize,
+) -> Result<(&'a [u8], Vec<usize>)> {
+    let length = u16::from_be_bytes(err.get(raw_cff, offset, offset + 2)?.try_into().unwrap());
+    let raw = err.get(raw_cff, offset, offset + 2 + usize::from(length))?;
+    // ItemVariationStore: format, variationRegionListOffset,
+    // itemVariationDataCount, itemVariationDataOffsets
+    let store = &raw[2..];
+    let header = err.get(store, 0, 8)?;
+    let data_count = usize::from(u16::from_be_bytes([header[6], header[7]]));
+    let data_offsets = err.get(store, 8, 8 + data_count * 4)?;
+    let region_counts = data_offsets
+        .chunks_exact(4)
+        .map(|x| {
+            let data_offset = usize::try_from(u32::from_be_bytes(x.try_into().unwrap())).unwrap();
+            // ItemVariationData: itemCount, wordDeltaCount, regionIndexCount
+            let data = err.get(store, data_offset, data_offset + 6)?;
+            Ok(usize::from(u16::from_be_bytes([data[4], data[5]])))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((raw, region_counts))
+}
+
+fn read_charset<'a>(
+    err: &Context,
+    raw_cff: &'a [u8],
+    offset: usize,
+    num_glyphs: usize,
+) -> Result<&'a [u8]> {
+    let format = err.get(raw_cff, offset, offset + 1)?[0];
+    let len = match format {
+        0 => 1 + num_glyphs.saturating_sub(1) * 2,
+        1 | 2 => {
+            // Ranges of (first SID, number left) until all glyphs but .notdef are covered
+            let range_len = if format == 1 { 3 } else { 4 };
+            let mut pos = offset + 1;
+            let mut covered = 1;
+            while covered < num_glyphs {
+                let range = err.get(raw_cff, pos, pos + range_len)?;
+                let num_left = if format == 1 {
+                    usize::from(range[2])
+                } else {
+                    usize::from(u16::from_be_bytes([range[2], range[3]]))
+                };
+                covered += num_left + 1;
+                pos += range_len;
+            }
+            pos - offset
+        }
+        _ => bail!(
+            "sfnt {} table {}: unsupported charset format: {}",
+            err.sfnt_index,
+            err.table_tag,
+            format
+        ),
+    };
+    err.get(raw_cff, offset, offset + len)
+}
+
+fn read_encoding<'a>(err: &Context, raw_cff: &'a [u8], offset: usize) -> Result<&'a [u8]> {
+    let header = err.get(raw_cff, offset, offset + 2)?;
+    let mut len = match header[0] & 0x7f {
+        // Codes, or ranges of (first code, number left)
+        0 => 2 + usize::from(header[1]),
+        1 => 2 + usize::from(header[1]) * 2,
+        format => bail!(
+            "sfnt {} table {}: unsupported encoding format: {}",
+            err.sfnt_index,
+            err.table_tag,
+            format
+        ),
+    };
+    // Supplemental encodings
+    if header[0] & 0x80 != 0 {
+        len += 1 + usize::from(err.get(raw_cff, offset + len, offset + len + 1)?[0]) * 3;
+    }
+    err.get(raw_cff, offset, offset + len)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Charstring {
+    Glyph(usize),
+    Local(usize, usize),
+    Global(usize),
+}
+
+// Follows the subroutine calls of each glyph to count the stem hints, since
+// the length of a hint mask depends on the number of stems before it.
+struct Tracer<'a> {
+    err: &'a Context,
+    global_subrs: &'a [&'a [u8]],
+    local_subrs: Vec<&'a [&'a [u8]]>,
+    // Number of regions of each ItemVariationData, CFF2 only
+    region_counts: &'a [usize],
+    // Hint mask length in bytes, for each CharString that contains hint masks
+    mask_lengths: HashMap<Charstring, usize>,
+}
+
+struct TraceState {
+    fd: usize,
+    vsindex: usize,
+    // Operand values, with the CharString that pushed them
+    stack: Vec<(i32, Charstring)>,
+    num_stems: usize,
+    depth: usize,
+}
+
+impl Tracer<'_> {
+    // Returns true when reaching endchar.
+    fn trace(&mut self, key: Charstring, data: &[u8], state: &mut TraceState) -> Result<bool> {
+        let err = self.err;
+        let mut pos = 0;
+        while pos < data.len() {
+            let b0 = data[pos];
+            if let Some((value, len)) = read_charstring_number(err, data, pos)? {
+                state.stack.push((value, key));
+                pos += len;
+                continue;
+            }
+            pos += 1;
+            match b0 {
+                CS_HSTEM | CS_VSTEM | CS_HSTEMHM | CS_VSTEMHM | CS_HINTMASK | CS_CNTRMASK => {
+                    // Stem operands must come from this CharString, so that
+                    // they can be removed together with the operator.
+                    if state.stack.iter().any(|x| x.1 != key) {
+                        bail!(
+                            "sfnt {} table {}: unsupported hint operands across subroutines",
+                            err.sfnt_index,
+                            err.table_tag
+                        );
+                    }
+                    state.num_stems += state.stack.len() / 2;
+                    state.stack.clear();
+                    if b0 == CS_HINTMASK || b0 == CS_CNTRMASK {
+                        let mask_length = state.num_stems.div_ceil(8);
+                        match self.mask_lengths.insert(key, mask_length) {
+                            Some(x) if x != mask_length => bail!(
+                                "sfnt {} table {}: subroutine used with different numbers of stems",
+                                err.sfnt_index,
+                                err.table_tag
+                            ),
+                            _ => (),
+                        }
+                        pos += mask_length;
+                    }
+                }
+                CS_CALLSUBR | CS_CALLGSUBR => {
+                    let (index, _) = self.pop(&mut state.stack)?;
+                    let subrs = if b0 == CS_CALLSUBR {
+                        self.local_subrs[state.fd]
+                    } else {
+                        self.global_subrs
+                    };
+                    let index = usize::try_from(index + subr_bias(subrs.len()))
+                        .ok()
+                        .filter(|&x| x < subrs.len())
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "sfnt {} table {}: invalid subroutine index {}",
+                                err.sfnt_index,
+                                err.table_tag,
+                                index
+                            )
+                        })?;
+                    if state.depth >= MAX_SUBR_NESTING {
+                        bail!(
+                            "sfnt {} table {}: subroutines nested too deep",
+                            err.sfnt_index,
+                            err.table_tag
+                        );
+                    }
+                    let subr_key = if b0 == CS_CALLSUBR {
+                        Charstring::Local(state.fd, index)
+                    } else {
+                        Charstring::Global(index)
+                    };
+                    state.depth += 1;
+                    let end = self.trace(subr_key, subrs[index], state)?;
+                    state.depth -= 1;
+                    if end {
+                        return Ok(true);
+                    }
+                }
+                CS_VSINDEX if err.cff2 => {
+                    let (vsindex, _) = self.pop(&mut state.stack)?;
+                    state.vsindex = usize::try_from(vsindex)
+                        .ok()
+                        .filter(|&x| x < self.region_counts.len())
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "sfnt {} table {}: invalid vsindex {}",
+                                err.sfnt_index,
+                                err.table_tag,
+                                vsindex
+                            )
+                        })?;
+                    state.stack.clear();
+                }
+                CS_BLEND if err.cff2 => {
+                    // n default values, followed by n deltas for each region,
+                    // leaving the n default values on the stack
+                    let (n, n_key) = self.pop(&mut state.stack)?;
+                    let num_regions = self.region_counts.get(state.vsindex).copied();
+                    let num_operands = usize::try_from(n)
+                        .ok()
+                        .zip(num_regions)
+                        .map(|(n, k)| (n, n * (k + 1)))
+                        .filter(|&(_, x)| x <= state.stack.len());
+                    let (n, num_operands) = num_operands.ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table {}: invalid blend",
+                            err.sfnt_index,
+                            err.table_tag
+                        )
+                    })?;
+                    let operands = state.stack.split_off(state.stack.len() - num_operands);
+                    // The result belongs to another CharString if any part of it does
+                    let result_key = operands
+                        .iter()
+                        .map(|x| x.1)
+                        .chain([n_key])
+                        .find(|&x| x != key)
+                        .unwrap_or(key);
+                    state
+                        .stack
+                        .extend(operands[..n].iter().map(|x| (x.0, result_key)));
+                }
+                CS_RETURN => return Ok(false),
+                CS_ENDCHAR => return Ok(true),
+                CS_ESCAPE => {
+                    let b1 = *data.get(pos).ok_or_else(|| err.truncated(pos, pos + 1))?;
+                    pos += 1;
+                    match b1 {
+                        // flex, hflex, hflex1, flex1
+                        34..=37 => state.stack.clear(),
+                        _ => bail!(
+                            "sfnt {} table {}: unsupported CharString operator 12 {}",
+                            err.sfnt_index,
+                            err.table_tag,
+                            b1
+                        ),
+                    }
+                }
+                // Path construction operators
+                4..=8 | 21 | 22 | 24..=27 | 30 | 31 => state.stack.clear(),
+                _ => bail!(
+                    "sfnt {} table {}: unsupported CharString operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    b0
+                ),
+            }
+        }
+        // CFF2 has no return or endchar, a CharString ends with its data
+        Ok(false)
+    }
+
+    fn pop(&self, stack: &mut Vec<(i32, Charstring)>) -> Result<(i32, Charstring)> {
+        stack.pop().ok_or_else(|| {
+            anyhow!(
+                "sfnt {} table {}: stack underflow",
+                self.err.sfnt_index,
+                self.err.table_tag
+            )
+        })
+    }
+}
+
+fn subr_bias(num_subrs: usize) -> i32 {
+    if num_subrs < 1240 {
+        107
+    } else if num_subrs < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+// Returns the value (integer part) and length of a number, or None for an operator.
+fn read_charstring_number(err: &Context, data: &[u8], pos: usize) -> Result<Option<(i32, usize)>> {
+    let b0 = data[pos];
+    Ok(Some(match b0 {
+        CS_SHORTINT => {
+            let x = err.get(data, pos + 1, pos + 3)?;
+            (i32::from(i16::from_be_bytes([x[0], x[1]])), 3)
+        }
+        32..=246 => (i32::from(b0) - 139, 1),
+        247..=250 => {
+            let b1 = err.get(data, pos + 1, pos + 2)?[0];
+            ((i32::from(b0) - 247) * 256 + i32::from(b1) + 108, 2)
+        }
+        251..=254 => {
+            let b1 = err.get(data, pos + 1, pos + 2)?[0];
+            (-(i32::from(b0) - 251) * 256 - i32::from(b1) - 108, 2)
+        }
+        // 16.16 fixed point
+        255 => {
+            let x = err.get(data, pos + 1, pos + 5)?;
+            (i32::from(i16::from_be_bytes([x[0], x[1]])), 5)
+        }
+        _ => return Ok(None),
+    }))
+}
+
+// Removes stem hints and hint masks from a CharString or subroutine.
+fn strip_charstring(err: &Context, data: &[u8], mask_length: Option<usize>) -> Result<Vec<u8>> {
+    let mut new_data = Vec::with_capacity(data.len());
+    // Positions of the operands since the last operator
+    let mut operands = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let b0 = data[pos];
+        if let Some((_, len)) = read_charstring_number(err, data, pos)? {
+            operands.push((pos, pos + len));
+            pos += len;
+            continue;
+        }
+        // Blended values are removed or kept together with their operator
+        if b0 == CS_BLEND && err.cff2 {
+            operands.push((pos, pos + 1));
+            pos += 1;
+            continue;
+        }
+        let mut operator_end = pos + 1;
+        if b0 == CS_ESCAPE {
+            operator_end += 1;
+        }
+        match b0 {
+            CS_HSTEM | CS_VSTEM | CS_HSTEMHM | CS_VSTEMHM | CS_HINTMASK | CS_CNTRMASK => {
+                // Stems come in pairs, an extra operand is the advance width.
+                // CFF2 has no advance width in CharStrings.
+                if operands.len() % 2 != 0 && !err.cff2 {
+                    let (from, to) = operands[0];
+                    new_data.extend_from_slice(&data[from..to]);
+                }
+                if b0 == CS_HINTMASK || b0 == CS_CNTRMASK {
+                    operator_end += mask_length.ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table {}: hint mask in an unused subroutine",
+                            err.sfnt_index,
+                            err.table_tag
+                        )
+                    })?;
+                }
+            }
+            _ => {
+                if let (Some(&(from, _)), Some(&(_, to))) = (operands.first(), operands.last()) {
+                    new_data.extend_from_slice(&data[from..to]);
+                }
+                new_data.extend_from_slice(err.get(data, pos, operator_end)?);
+            }
+        }
+        operands.clear();
+        pos = operator_end;
+    }
+    // Trailing operands, normally consumed by the caller of a subroutine
+    if let (Some(&(from, _)), Some(&(_, to))) = (operands.first(), operands.last()) {
+        new_data.extend_from_slice(&data[from..to]);
+    }
+    Ok(new_data)
+}
+
+// Strips each subroutine. Unused subroutines with hint masks can't be parsed,
+// they are replaced with an empty one.
+fn strip_subrs(
+    err: &Context,
+    subrs: &[&[u8]],
+    mask_length: impl Fn(usize) -> Option<usize>,
+) -> Result<Vec<Vec<u8>>> {
+    subrs
+        .iter()
+        .enumerate()
+        .map(
+            |(i, subr)| match strip_charstring(err, subr, mask_length(i)) {
+                Ok(subr) => Ok(subr),
+                Err(_) if mask_length(i).is_none() && err.cff2 => Ok(Vec::new()),
+                Err(_) if mask_length(i).is_none() => Ok(vec![CS_RETURN]),
+                Err(e) => Err(e),
+            },
+        )
+        .collect()
+}
+
+// A point of a cubic outline. Off-curve points come in pairs, as the two
+// control points of a cubic Bézier curve.
+pub type CubicPoint = (f32, f32, bool);
+
+pub struct CffOutlines {
+    // Closed contours of each glyph, in font units
+    pub glyphs: Vec<Vec<Vec<CubicPoint>>>,
+    // Glyph names from the charset, or empty if the font is CID-keyed or uses
+    // a predefined expert charset
+    pub glyph_names: Vec<String>,
+    pub cid_keyed: bool,
+}
+
+// Reads the outlines and glyph names of a "CFF " table.
+pub fn read_cff_outlines(
+    sfnt_index: usize,
+    raw_cff: &[u8],
+    units_per_em: u16,
+) -> Result<CffOutlines> {
+    let err = Context {
+        sfnt_index,
+        table_tag: FourCC(*b"CFF "),
+        cff2: false,
+    };
+    let font = read_font(&err, raw_cff)?;
+    let num_glyphs = font.charstrings.len();
+    let is_cid = font.top_dict.contains(OP_ROS);
+
+    // Glyph space to font units. The Font DICT matrix applies before the Top
+    // DICT one, which then defaults to identity rather than 1/1000.
+    let default_matrix = [0.001, 0.0, 0.0, 0.001, 0.0, 0.0];
+    let identity_matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+    let top_matrix = read_font_matrix(&err, &font.top_dict)?;
+    let matrices = match &font.font_dicts {
+        Some(font_dicts) => font_dicts
+            .iter()
+            .map(|x| {
+                Ok(match (read_font_matrix(&err, x)?, top_matrix) {
+                    (Some(fd_matrix), top_matrix) => {
+                        multiply_matrix(&fd_matrix, &top_matrix.unwrap_or(identity_matrix))
+                    }
+                    (None, top_matrix) => top_matrix.unwrap_or(default_matrix),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![top_matrix.unwrap_or(default_matrix)],
+    };
+    let matrices = matrices
+        .iter()
+        .map(|x| x.map(|x| x * f64::from(units_per_em)))
+        .collect::<Vec<_>>();
+
+    let charset_ids = match font.top_dict.offset(&err, OP_CHARSET)? {
+        // ISOAdobe
+        Some(0) | None => Some((0..num_glyphs).map(|x| x as u16).collect()),
+        // Expert and ExpertSubset
+        Some(1..=2) => None,
+        Some(offset) => Some(read_charset_ids(
+            read_charset(&err, raw_cff, offset, num_glyphs)?,
+            num_glyphs,
+        )),
+    };
+    let glyph_names = match &charset_ids {
+        // CIDs are not names
+        _ if is_cid => Vec::new(),
+        Some(sids) => {
+            let (strings, _) = read_index(&err, font.raw_string_index, 0)?;
+            sids.iter()
+                .map(|&sid| match STANDARD_STRINGS.get(usize::from(sid)) {
+                    Some(name) => Ok((*name).to_owned()),
+                    None => strings
+                        .get(usize::from(sid) - STANDARD_STRINGS.len())
+                        .map(|x| String::from_utf8_lossy(x).into_owned())
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "sfnt {} table {}: invalid string ID {}",
+                                sfnt_index,
+                                err.table_tag,
+                                sid
+                            )
+                        }),
+                })
+                .collect::<Result<Vec<_>>>()?
+        }
+        None => Vec::new(),
+    };
+
+    let interpreter = Interpreter {
+        err: &err,
+        font: &font,
+    };
+    let mut glyphs = Vec::with_capacity(num_glyphs);
+    for glyph_index in 0..num_glyphs {
+        let mut contours = match interpreter.run(glyph_index)? {
+            (contours, None) => contours,
+            // Accented character made of two other glyphs, by standard encoding
+            (mut contours, Some((adx, ady, base_code, accent_code))) => {
+                let find_glyph = |code: f32| {
+                    let sid = standard_encoding_sid(code);
+                    let component = charset_ids
+                        .as_ref()
+                        .filter(|_| !is_cid)
+                        .zip(sid)
+                        .and_then(|(sids, sid)| sids.iter().position(|&x| x == sid));
+                    component.ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table {}: glyph {} uses an invalid seac code {}",
+                            sfnt_index,
+                            err.table_tag,
+                            glyph_index,
+                            code
+                        )
+                    })
+                };
+                let (base, _) = interpreter.run(find_glyph(base_code)?)?;
+                let (accent, _) = interpreter.run(find_glyph(accent_code)?)?;
+                contours.extend(base);
+                contours.extend(accent.into_iter().map(|contour| {
+                    contour
+                        .into_iter()
+                        .map(|(x, y, on_curve)| (x + adx, y + ady, on_curve))
+                        .collect()
+                }));
+                contours
+            }
+        };
+        let m = &matrices[font.glyph_fds[glyph_index]];
+        for point in contours.iter_mut().flatten() {
+            let (x, y) = (f64::from(point.0), f64::from(point.1));
+            point.0 = (m[0] * x + m[2] * y + m[4]) as f32;
+            point.1 = (m[1] * x + m[3] * y + m[5]) as f32;
+        }
+        glyphs.push(contours);
+    }
+    Ok(CffOutlines {
+        glyphs,
+        glyph_names,
+        cid_keyed: is_cid,
+    })
+}
+
+fn read_font_matrix(err: &Context, dict: &Dict) -> Result<Option<[f64; 6]>> {
+    match dict.numbers(err, OP_FONT_MATRIX)? {
+        Some(values) => match <[f64; 6]>::try_from(values.as_slice()) {
+            Ok(matrix) => Ok(Some(matrix)),
+            Err(_) => bail!(
+                "sfnt {} table {}: invalid FontMatrix",
+                err.sfnt_index,
+                err.table_tag
+            ),
+        },
+        None => Ok(None),
+    }
+}
+
+// Applies a, then b.
+fn multiply_matrix(a: &[f64; 6], b: &[f64; 6]) -> [f64; 6] {
+    [
+        a[0] * b[0] + a[1] * b[2],
+        a[0] * b[1] + a[1] * b[3],
+        a[2] * b[0] + a[3] * b[2],
+        a[2] * b[1] + a[3] * b[3],
+        a[4] * b[0] + a[5] * b[2] + b[4],
+        a[4] * b[1] + a[5] * b[3] + b[5],
+    ]
+}
+
+// Standard strings, referenced by SIDs below 391
+#[rustfmt::skip]
+const STANDARD_STRINGS: [&str; 391] = [
+    ".notdef", "space", "exclam", "quotedbl", "numbersign", "dollar", "percent", "ampersand",
+    "quoteright", "parenleft", "parenright", "asterisk", "plus", "comma", "hyphen", "period",
+    "slash", "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    "colon", "semicolon", "less", "equal", "greater", "question", "at", "A", "B", "C", "D", "E",
+    "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X",
+    "Y", "Z", "bracketleft", "backslash", "bracketright", "asciicircum", "underscore", "quoteleft",
+    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s",
+    "t", "u", "v", "w", "x", "y", "z", "braceleft", "bar", "braceright", "asciitilde",
+    "exclamdown", "cent", "sterling", "fraction", "yen", "florin", "section", "currency",
+    "quotesingle", "quotedblleft", "guillemotleft", "guilsinglleft", "guilsinglright", "fi", "fl",
+    "endash", "dagger", "daggerdbl", "periodcentered", "paragraph", "bullet", "quotesinglbase",
+    "quotedblbase", "quotedblright", "guillemotright", "ellipsis", "perthousand", "questiondown",
+    "grave", "acute", "circumflex", "tilde", "macron", "breve", "dotaccent", "dieresis", "ring",
+    "cedilla", "hungarumlaut", "ogonek", "caron", "emdash", "AE", "ordfeminine", "Lslash",
+    "Oslash", "OE", "ordmasculine", "ae", "dotlessi", "lslash", "oslash", "oe", "germandbls",
+    "onesuperior", "logicalnot", "mu", "trademark", "Eth", "onehalf", "plusminus", "Thorn",
+    "onequarter", "divide", "brokenbar", "degree", "thorn", "threequarters", "twosuperior",
+    "registered", "minus", "eth", "multiply", "threesuperior", "copyright", "Aacute",
+    "Acircumflex", "Adieresis", "Agrave", "Aring", "Atilde", "Ccedilla", "Eacute", "Ecircumflex",
+    "Edieresis", "Egrave", "Iacute", "Icircumflex", "Idieresis", "Igrave", "Ntilde", "Oacute",
+    "Ocircumflex", "Odieresis", "Ograve", "Otilde", "Scaron", "Uacute", "Ucircumflex", "Udieresis",
+    "Ugrave", "Yacute", "Ydieresis", "Zcaron", "aacute", "acircumflex", "adieresis", "agrave",
+    "aring", "atilde", "ccedilla", "eacute", "ecircumflex", "edieresis", "egrave", "iacute",
+    "icircumflex", "idieresis", "igrave", "ntilde", "oacute", "ocircumflex", "odieresis", "ograve",
+    "otilde", "scaron", "uacute", "ucircumflex", "udieresis", "ugrave", "yacute", "ydieresis",
+    "zcaron", "exclamsmall", "Hungarumlautsmall", "dollaroldstyle", "dollarsuperior",
+    "ampersandsmall", "Acutesmall", "parenleftsuperior", "parenrightsuperior", "twodotenleader",
+    "onedotenleader", "zerooldstyle", "oneoldstyle", "twooldstyle", "threeoldstyle",
+    "fouroldstyle", "fiveoldstyle", "sixoldstyle", "sevenoldstyle", "eightoldstyle",
+    "nineoldstyle", "commasuperior", "threequartersemdash", "periodsuperior", "questionsmall",
+    "asuperior", "bsuperior", "centsuperior", "dsuperior", "esuperior", "isuperior", "lsuperior",
+    "msuperior", "nsuperior", "osuperior", "rsuperior", "ssuperior", "tsuperior", "ff", "ffi",
+    "ffl", "parenleftinferior", "parenrightinferior", "Circumflexsmall", "hyphensuperior",
+    "Gravesmall", "Asmall", "Bsmall", "Csmall", "Dsmall", "Esmall", "Fsmall", "Gsmall", "Hsmall",
+    "Ismall", "Jsmall", "Ksmall", "Lsmall", "Msmall", "Nsmall", "Osmall", "Psmall", "Qsmall",
+    "Rsmall", "Ssmall", "Tsmall", "Usmall", "Vsmall", "Wsmall", "Xsmall", "Ysmall", "Zsmall",
+    "colonmonetary", "onefitted", "rupiah", "Tildesmall", "exclamdownsmall", "centoldstyle",
+    "Lslashsmall", "Scaronsmall", "Zcaronsmall", "Dieresissmall", "Brevesmall", "Caronsmall",
+    "Dotaccentsmall", "Macronsmall", "figuredash", "hypheninferior", "Ogoneksmall", "Ringsmall",
+    "Cedillasmall", "questiondownsmall", "oneeighth", "threeeighths", "fiveeighths",
+    "seveneighths", "onethird", "twothirds", "zerosuperior", "foursuperior", "fivesuperior",
+    "sixsuperior", "sevensuperior", "eightsuperior", "ninesuperior", "zeroinferior", "oneinferior",
+    "twoinferior", "threeinferior", "fourinferior", "fiveinferior", "sixinferior", "seveninferior",
+    "eightinferior", "nineinferior", "centinferior", "dollarinferior", "periodinferior",
+    "commainferior", "Agravesmall", "Aacutesmall", "Acircumflexsmall", "Atildesmall",
+    "Adieresissmall", "Aringsmall", "AEsmall", "Ccedillasmall", "Egravesmall", "Eacutesmall",
+    "Ecircumflexsmall", "Edieresissmall", "Igravesmall", "Iacutesmall", "Icircumflexsmall",
+    "Idieresissmall", "Ethsmall", "Ntildesmall", "Ogravesmall", "Oacutesmall", "Ocircumflexsmall",
+    "Otildesmall", "Odieresissmall", "OEsmall", "Oslashsmall", "Ugravesmall", "Uacutesmall",
+    "Ucircumflexsmall", "Udieresissmall", "Yacutesmall", "Thornsmall", "Ydieresissmall", "001.000",
+    "001.001", "001.002", "001.003", "Black", "Bold", "Book", "Light", "Medium", "Regular",
+    "Roman", "Semibold",
+];
+
+// Returns the SID or CID of each glyph from a custom charset.
+fn read_charset_ids(raw_charset: &[u8], num_glyphs: usize) -> Vec<u16> {
+    let mut ids = Vec::with_capacity(num_glyphs);
+    ids.push(0);
+    match raw_charset[0] {
+        0 => ids.extend(
+            raw_charset[1..]
+                .chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]])),
+        ),
+        format => {
+            let range_len = if format == 1 { 3 } else { 4 };
+            for range in raw_charset[1..].chunks_exact(range_len) {
+                let first = u16::from_be_bytes([range[0], range[1]]);
+                let num_left = if format == 1 {
+                    u16::from(range[2])
+                } else {
+                    u16::from_be_bytes([range[2], range[3]])
+                };
+                ids.extend((0..=num_left).map(|x| first.wrapping_add(x)));
+            }
+        }
+    }
+    ids.truncate(num_glyphs);
+    ids
+}
+
+// Maps a character code in the Standard Encoding to its SID.
+fn standard_encoding_sid(code: f32) -> Option<u16> {
+    // Ranges of (first code, last code, first SID)
+    const RANGES: [(u8, u8, u16); 14] = [
+        (32, 126, 1),
+        (161, 175, 96),
+        (177, 180, 111),
+        (182, 189, 115),
+        (191, 191, 123),
+        (193, 200, 124),
+        (202, 203, 132),
+        (205, 208, 134),
+        (225, 225, 138),
+        (227, 227, 139),
+        (232, 235, 140),
+        (241, 241, 144),
+        (245, 245, 145),
+        (248, 251, 146),
+    ];
+    let code = u8::try_from(code as i32).ok()?;
+    RANGES
+        .iter()
+        .find(|&&(first, last, _)| (first..=last).contains(&code))
+        .map(|&(first, _, sid)| sid + u16::from(code - first))
+}
+
+// Type 2 CharString path construction operators
+const CS_VMOVETO: u8 = 4;
+const CS_RLINETO: u8 = 5;
+const CS_HLINETO: u8 = 6;
+const CS_VLINETO: u8 = 7;
+const CS_RRCURVETO: u8 = 8;
+const CS_RMOVETO: u8 = 21;
+const CS_HMOVETO: u8 = 22;
+const CS_RCURVELINE: u8 = 24;
+const CS_RLINECURVE: u8 = 25;
+const CS_VVCURVETO: u8 = 26;
+const CS_HHCURVETO: u8 = 27;
+const CS_VHCURVETO: u8 = 30;
+const CS_HVCURVETO: u8 = 31;
+// Escaped with CS_ESCAPE
+const CS_HFLEX: u8 = 34;
+const CS_FLEX: u8 = 35;
+const CS_HFLEX1: u8 = 36;
+const CS_FLEX1: u8 = 37;
+
+const MAX_STACK_DEPTH: usize = 48;
+
+// Accent offset, base and accent character codes of an accented character
+type Seac = (f32, f32, f32, f32);
+
+// Runs Type 2 CharStrings to build cubic outlines in glyph space.
+struct Interpreter<'a> {
+    err: &'a Context,
+    font: &'a Font<'a>,
+}
+
+struct PathState {
+    fd: usize,
+    stack: Vec<f32>,
+    // The advance width may come before the first stack-clearing operator
+    width_pending: bool,
+    num_stems: usize,
+    depth: usize,
+    x: f32,
+    y: f32,
+    contours: Vec<Vec<CubicPoint>>,
+    contour_open: bool,
+    seac: Option<Seac>,
+}
+
+impl Interpreter<'_> {
+    fn run(&self, glyph_index: usize) -> Result<(Vec<Vec<CubicPoint>>, Option<Seac>)> {
+        let mut state = PathState {
+            fd: self.font.glyph_fds[glyph_index],
+            stack: Vec::new(),
+            width_pending: true,
+            num_stems: 0,
+            depth: 0,
+            x: 0.0,
+            y: 0.0,
+            contours: Vec::new(),
+            contour_open: false,
+            seac: None,
+        };
+        self.execute(self.font.charstrings[glyph_index], &mut state)?;
+        state.close_contour();
+        Ok((state.contours, state.seac))
+    }
+
+    // Returns true when reaching endchar.
+    fn execute(&self, data: &[u8], state: &mut PathState) -> Result<bool> {
+        let err = self.err;
+        let mut pos = 0;
+        while pos < data.len() {
+            if let Some((value, len)) = read_charstring_value(err, data, pos)? {
+                if state.stack.len() >= MAX_STACK_DEPTH {
+                    bail!(
+                        "sfnt {} table {}: stack overflow",
+                        err.sfnt_index,
+                        err.table_tag
+                    );
+                }
+                state.stack.push(value);
+                pos += len;
+                continue;
+            }
+            let b0 = data[pos];
+            pos += 1;
+            let args = std::mem::take(&mut state.stack);
+            match b0 {
+                CS_HSTEM | CS_VSTEM | CS_HSTEMHM | CS_VSTEMHM | CS_HINTMASK | CS_CNTRMASK => {
+                    let args = state.take_width(&args, !args.len().is_multiple_of(2));
+                    state.num_stems += args.len() / 2;
+                    if b0 == CS_HINTMASK || b0 == CS_CNTRMASK {
+                        pos += state.num_stems.div_ceil(8);
+                    }
+                }
+                CS_RMOVETO => {
+                    let args = state.take_width(&args, args.len() > 2);
+                    if let [dx, dy, ..] = *args {
+                        state.move_to(dx, dy);
+                    }
+                }
+                CS_HMOVETO | CS_VMOVETO => {
+                    let args = state.take_width(&args, args.len() > 1);
+                    if let Some(&d) = args.first() {
+                        match b0 {
+                            CS_HMOVETO => state.move_to(d, 0.0),
+                            _ => state.move_to(0.0, d),
+                        }
+                    }
+                }
+                CS_RLINETO => {
+                    for d in args.chunks_exact(2) {
+                        state.line_to(d[0], d[1]);
+                    }
+                }
+                CS_HLINETO | CS_VLINETO => {
+                    for (i, &d) in args.iter().enumerate() {
+                        if (i % 2 == 0) == (b0 == CS_HLINETO) {
+                            state.line_to(d, 0.0);
+                        } else {
+                            state.line_to(0.0, d);
+                        }
+                    }
+                }
+                CS_RRCURVETO => {
+                    for d in args.chunks_exact(6) {
+                        state.curve_to(d[0], d[1], d[2], d[3], d[4], d[5]);
+                    }
+                }
+                CS_RCURVELINE => {
+                    let (curves, line) = args.split_at(args.len().saturating_sub(2));
+                    for d in curves.chunks_exact(6) {
+                        state.curve_to(d[0], d[1], d[2], d[3], d[4], d[5]);
+                    }
+                    if let [dx, dy] = *line {
+                        state.line_to(dx, dy);
+                    }
+                }
+                CS_RLINECURVE => {
+                    let (lines, curve) = args.split_at(args.len().saturating_sub(6));
+                    for d in lines.chunks_exact(2) {
+                        state.line_to(d[0], d[1]);
+                    }
+                    if let [dxa, dya, dxb, dyb, dxc, dyc] = *curve {
+                        state.curve_to(dxa, dya, dxb, dyb, dxc, dyc);
+                    }
+                }
+                CS_HHCURVETO | CS_VVCURVETO => {
+                    // An odd operand first moves perpendicular to the curves
+                    let (mut d1, curves) = match args.len() % 2 {
+                        0 => (0.0, &args[..]),
+                        _ => (args[0], &args[1..]),
+                    };
+                    for d in curves.chunks_exact(4) {
+                        if b0 == CS_HHCURVETO {
+                            state.curve_to(d[0], d1, d[1], d[2], d[3], 0.0);
+                        } else {
+                            state.curve_to(d1, d[0], d[1], d[2], 0.0, d[3]);
+                        }
+                        d1 = 0.0;
+                    }
+                }
+                CS_HVCURVETO | CS_VHCURVETO => {
+                    // Curves alternate between starting horizontal and vertical,
+                    // the last one may take an extra operand for its end point.
+                    let mut horizontal = b0 == CS_HVCURVETO;
+                    let mut i = 0;
+                    while args.len() - i >= 4 {
+                        let d = &args[i..i + 4];
+                        let last = if args.len() - i == 5 {
+                            args[i + 4]
+                        } else {
+                            0.0
+                        };
+                        if horizontal {
+                            state.curve_to(d[0], 0.0, d[1], d[2], last, d[3]);
+                        } else {
+                            state.curve_to(0.0, d[0], d[1], d[2], d[3], last);
+                        }
+                        horizontal = !horizontal;
+                        i += 4;
+                    }
+                }
+                CS_CALLSUBR | CS_CALLGSUBR => {
+                    state.stack = args;
+                    let index = state.stack.pop().ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table {}: stack underflow",
+                            err.sfnt_index,
+                            err.table_tag
+                        )
+                    })?;
+                    let subrs = if b0 == CS_CALLSUBR {
+                        &self.font.privates[state.fd].1
+                    } else {
+                        &self.font.global_subrs
+                    };
+                    let subr = usize::try_from(index as i32 + subr_bias(subrs.len()))
+                        .ok()
+                        .and_then(|x| subrs.get(x))
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "sfnt {} table {}: invalid subroutine index {}",
+                                err.sfnt_index,
+                                err.table_tag,
+                                index
+                            )
+                        })?;
+                    if state.depth >= MAX_SUBR_NESTING {
+                        bail!(
+                            "sfnt {} table {}: subroutines nested too deep",
+                            err.sfnt_index,
+                            err.table_tag
+                        );
+                    }
+                    state.depth += 1;
+                    let end = self.execute(subr, state)?;
+                    state.depth -= 1;
+                    if end {
+                        return Ok(true);
+                    }
+                }
+                CS_RETURN => {
+                    state.stack = args;
+                    return Ok(false);
+                }
+                CS_ENDCHAR => {
+                    let args = state.take_width(&args, args.len() == 1 || args.len() == 5);
+                    if let [adx, ady, base_code, accent_code] = *args {
+                        state.seac = Some((adx, ady, base_code, accent_code));
+                    }
+                    return Ok(true);
+                }
+                CS_ESCAPE => {
+                    let b1 = *data.get(pos).ok_or_else(|| err.truncated(pos, pos + 1))?;
+                    pos += 1;
+                    match (b1, &args[..]) {
+                        (CS_FLEX, &[d1, d2, d3, d4, d5, d6, d7, d8, d9, d10, d11, d12, _]) => {
+                            state.curve_to(d1, d2, d3, d4, d5, d6);
+                            state.curve_to(d7, d8, d9, d10, d11, d12);
+                        }
+                        (CS_HFLEX, &[dx1, dx2, dy2, dx3, dx4, dx5, dx6]) => {
+                            state.curve_to(dx1, 0.0, dx2, dy2, dx3, 0.0);
+                            state.curve_to(dx4, 0.0, dx5, -dy2, dx6, 0.0);
+                        }
+                        (CS_HFLEX1, &[dx1, dy1, dx2, dy2, dx3, dx4, dx5, dy5, dx6]) => {
+                            state.curve_to(dx1, dy1, dx2, dy2, dx3, 0.0);
+                            state.curve_to(dx4, 0.0, dx5, dy5, dx6, -(dy1 + dy2 + dy5));
+                        }
+                        (CS_FLEX1, &[dx1, dy1, dx2, dy2, dx3, dy3, dx4, dy4, dx5, dy5, d6]) => {
+                            // The last point returns to the starting line
+                            let dx = dx1 + dx2 + dx3 + dx4 + dx5;
+                            let dy = dy1 + dy2 + dy3 + dy4 + dy5;
+                            let (dx6, dy6) = if dx.abs() > dy.abs() {
+                                (d6, -dy)
+                            } else {
+                                (-dx, d6)
+                            };
+                            state.curve_to(dx1, dy1, dx2, dy2, dx3, dy3);
+                            state.curve_to(dx4, dy4, dx5, dy5, dx6, dy6);
+                        }
+                        _ => bail!(
+                            "sfnt {} table {}: unsupported CharString operator 12 {} with {} operands",
+                            err.sfnt_index,
+                            err.table_tag,
+                            b1,
+                            args.len()
+                        ),
+                    }
+                }
+                _ => bail!(
+                    "sfnt {} table {}: unsupported CharString operator {}",
+                    err.sfnt_index,
+                    err.table_tag,
+                    b0
+                ),
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl PathState {
+    // Drops the advance width in front of the operands of the first
+    // stack-clearing operator, widths come from "hmtx" instead.
+    fn take_width<'a>(&mut self, args: &'a [f32], has_width: bool) -> &'a [f32] {
+        let width_pending = std::mem::replace(&mut self.width_pending, false);
+        if width_pending && has_width {
+            &args[1..]
+        } else {
+            args
+        }
+    }
+
+    fn move_to(&mut self, dx: f32, dy: f32) {
+        self.close_contour();
+        self.x += dx;
+        self.y += dy;
+        self.contours.push(vec![(self.x, self.y, true)]);
+        self.contour_open = true;
+    }
+
+    fn line_to(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+        self.push_point(self.x, self.y, true);
+    }
+
+    fn curve_to(&mut self, dxa: f32, dya: f32, dxb: f32, dyb: f32, dxc: f32, dyc: f32) {
+        let (x1, y1) = (self.x + dxa, self.y + dya);
+        let (x2, y2) = (x1 + dxb, y1 + dyb);
+        self.x = x2 + dxc;
+        self.y = y2 + dyc;
+        self.push_point(x1, y1, false);
+        self.push_point(x2, y2, false);
+        self.push_point(self.x, self.y, true);
+    }
+
+    fn push_point(&mut self, x: f32, y: f32, on_curve: bool) {
+        // Drawing without moveto starts from the current point
+        if !self.contour_open {
+            self.contours.push(vec![(self.x, self.y, true)]);
+            self.contour_open = true;
+        }
+        self.contours.last_mut().unwrap().push((x, y, on_curve));
+    }
+
+    // Contours are closed implicitly, drop the end point if it repeats the start.
+    fn close_contour(&mut self) {
+        if !std::mem::replace(&mut self.contour_open, false) {
+            return;
+        }
+        let contour = self.contours.last_mut().unwrap();
+        if contour.len() > 1 && contour.last() == contour.first() {
+            contour.pop();
+        }
+        if contour.len() < 2 {
+            self.contours.pop();
+        }
+    }
+}
+
+// Returns the value and length of a number, with the fraction of 16.16 fixed
+// point numbers, or None for an operator.
+fn read_charstring_value(err: &Context, data: &[u8], pos: usize) -> Result<Option<(f32, usize)>> {
+    if data[pos] == 255 {
+        let x = err.get(data, pos + 1, pos + 5)?;
+        let value = i32::from_be_bytes(x.try_into().unwrap()) as f32 / 65536.0;
+        return Ok(Some((value, 5)));
+    }
+    Ok(read_charstring_number(err, data, pos)?.map(|(value, len)| (value as f32, len)))
+}
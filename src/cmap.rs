@@ -0,0 +1,182 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use super::types::SfntHeader;
+
+// Reads all Unicode subtables of "cmap" into a map from code point to glyph index.
+pub fn read_unicode_cmap(sfnt_index: usize, sfnt: &SfntHeader) -> Result<BTreeMap<u32, u16>> {
+    let mut cmap = BTreeMap::new();
+    let raw_cmap = match sfnt.table_records.get(&b"cmap".into()) {
+        Some(cmap) => &cmap.raw_data,
+        None => return Ok(cmap),
+    };
+
+    let num_tables = usize::from(read_u16be(sfnt_index, raw_cmap, 2)?);
+    for i in 0..num_tables {
+        let platform_id = read_u16be(sfnt_index, raw_cmap, 4 + i * 8)?;
+        let encoding_id = read_u16be(sfnt_index, raw_cmap, 6 + i * 8)?;
+        let offset = usize::try_from(read_u32be(sfnt_index, raw_cmap, 8 + i * 8)?).unwrap();
+        match (platform_id, encoding_id) {
+            // Unicode, except Unicode Variation Sequences
+            (0, 0..=4) | (0, 6) => (),
+            // Windows, Unicode BMP or full repertoire
+            (3, 1) | (3, 10) => (),
+            _ => continue,
+        }
+
+        let format = read_u16be(sfnt_index, raw_cmap, offset)?;
+        match format {
+            // Byte encoding table
+            0 => {
+                for code_point in 0..256 {
+                    let glyph_index = *raw_cmap.get(offset + 6 + code_point).ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table “cmap”: data truncated ({})",
+                            sfnt_index,
+                            offset + 6 + code_point
+                        )
+                    })?;
+                    if glyph_index != 0 {
+                        cmap.entry(code_point.try_into().unwrap())
+                            .or_insert_with(|| glyph_index.into());
+                    }
+                }
+            }
+            // Segment mapping to delta values
+            4 => {
+                let seg_count = usize::from(read_u16be(sfnt_index, raw_cmap, offset + 6)? / 2);
+                let end_codes = offset + 14;
+                let start_codes = end_codes + seg_count * 2 + 2;
+                let id_deltas = start_codes + seg_count * 2;
+                let id_range_offsets = id_deltas + seg_count * 2;
+                for seg in 0..seg_count {
+                    let end_code = read_u16be(sfnt_index, raw_cmap, end_codes + seg * 2)?;
+                    let start_code = read_u16be(sfnt_index, raw_cmap, start_codes + seg * 2)?;
+                    let id_delta = read_u16be(sfnt_index, raw_cmap, id_deltas + seg * 2)?;
+                    let id_range_offset =
+                        read_u16be(sfnt_index, raw_cmap, id_range_offsets + seg * 2)?;
+                    if start_code > end_code {
+                        continue;
+                    }
+                    for code_point in start_code..=end_code {
+                        let glyph_index = if id_range_offset == 0 {
+                            code_point.wrapping_add(id_delta)
+                        } else {
+                            let glyph_index = read_u16be(
+                                sfnt_index,
+                                raw_cmap,
+                                id_range_offsets
+                                    + seg * 2
+                                    + usize::from(id_range_offset)
+                                    + usize::from(code_point - start_code) * 2,
+                            )?;
+                            if glyph_index == 0 {
+                                0
+                            } else {
+                                glyph_index.wrapping_add(id_delta)
+                            }
+                        };
+                        if glyph_index != 0 {
+                            cmap.entry(code_point.into()).or_insert(glyph_index);
+                        }
+                    }
+                }
+            }
+            // Trimmed table mapping
+            6 => {
+                let first_code = read_u16be(sfnt_index, raw_cmap, offset + 6)?;
+                let entry_count = read_u16be(sfnt_index, raw_cmap, offset + 8)?;
+                for i in 0..entry_count {
+                    let glyph_index =
+                        read_u16be(sfnt_index, raw_cmap, offset + 10 + usize::from(i) * 2)?;
+                    if glyph_index != 0 {
+                        cmap.entry(u32::from(first_code) + u32::from(i))
+                            .or_insert(glyph_index);
+                    }
+                }
+            }
+            // Segmented coverage
+            12 => {
+                let num_groups =
+                    usize::try_from(read_u32be(sfnt_index, raw_cmap, offset + 12)?).unwrap();
+                for group in 0..num_groups {
+                    let group_offset = offset + 16 + group * 12;
+                    let start_char_code = read_u32be(sfnt_index, raw_cmap, group_offset)?;
+                    let end_char_code = read_u32be(sfnt_index, raw_cmap, group_offset + 4)?;
+                    let start_glyph_id = read_u32be(sfnt_index, raw_cmap, group_offset + 8)?;
+                    if start_char_code > end_char_code || end_char_code > 0x10ffff {
+                        continue;
+                    }
+                    for code_point in start_char_code..=end_char_code {
+                        if let Ok(glyph_index) =
+                            u16::try_from(start_glyph_id + (code_point - start_char_code))
+                        {
+                            if glyph_index != 0 {
+                                cmap.entry(code_point).or_insert(glyph_index);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “cmap”: unsupported subtable format: {}",
+                    sfnt_index, format
+                );
+            }
+        }
+    }
+    Ok(cmap)
+}
+
+fn read_u16be(sfnt_index: usize, raw_cmap: &[u8], pos: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(
+        raw_cmap
+            .get(pos..pos + 2)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “cmap”: data truncated ({}..{})",
+                    sfnt_index,
+                    pos,
+                    pos + 2
+                )
+            })?
+            .try_into()?,
+    ))
+}
+
+fn read_u32be(sfnt_index: usize, raw_cmap: &[u8], pos: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(
+        raw_cmap
+            .get(pos..pos + 4)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “cmap”: data truncated ({}..{})",
+                    sfnt_index,
+                    pos,
+                    pos + 4
+                )
+            })?
+            .try_into()?,
+    ))
+}
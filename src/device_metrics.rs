@@ -0,0 +1,408 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::ops::RangeInclusive;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::cff;
+use super::glyf;
+use super::types::FourCC;
+use super::types::SfntHeader;
+
+fn read_u16(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    table_tag: &'static [u8; 4],
+    pos: usize,
+) -> Result<u16> {
+    let table = sfnt.table_records.get(&table_tag.into()).ok_or_else(|| {
+        anyhow!(
+            "sfnt {}: table {} not found",
+            sfnt_index,
+            FourCC(*table_tag)
+        )
+    })?;
+    table
+        .raw_data
+        .get(pos..pos + 2)
+        .map(|x| u16::from_be_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| {
+            anyhow!(
+                "sfnt {} table {}: data truncated ({}..{})",
+                sfnt_index,
+                FourCC(*table_tag),
+                pos,
+                pos + 2
+            )
+        })
+}
+
+// Returns the advance width of each glyph from "hmtx".
+fn read_advance_widths(sfnt_index: usize, sfnt: &SfntHeader) -> Result<Vec<u16>> {
+    // "maxp" table:
+    // byte 4..6: numGlyphs
+    // "hhea" table:
+    // byte 34..36: numberOfHMetrics
+    let num_glyphs = usize::from(read_u16(sfnt_index, sfnt, b"maxp", 4)?);
+    let num_h_metrics = usize::from(read_u16(sfnt_index, sfnt, b"hhea", 34)?);
+    if num_h_metrics == 0 {
+        bail!("sfnt {} table “hhea”: numberOfHMetrics is zero", sfnt_index);
+    }
+    (0..num_glyphs)
+        .map(|glyph_index| {
+            read_u16(
+                sfnt_index,
+                sfnt,
+                b"hmtx",
+                glyph_index.min(num_h_metrics - 1) * 4,
+            )
+        })
+        .collect()
+}
+
+fn read_units_per_em(sfnt_index: usize, sfnt: &SfntHeader) -> Result<u16> {
+    // byte 18..20: unitsPerEm
+    let units_per_em = read_u16(sfnt_index, sfnt, b"head", 18)?;
+    if units_per_em == 0 {
+        bail!("sfnt {} table “head”: unitsPerEm is zero", sfnt_index);
+    }
+    Ok(units_per_em)
+}
+
+// Scales a distance in font units to whole pixels, rounding half up.
+fn scale_to_pixels(value: i32, ppem: u8, units_per_em: u16) -> i32 {
+    let (value, ppem, units_per_em) = (i64::from(value), i64::from(ppem), i64::from(units_per_em));
+    ((value * ppem * 2 + units_per_em).div_euclid(units_per_em * 2)) as i32
+}
+
+// Returns the sizes of the device records in "hdmx".
+pub fn read_hdmx_ppems(sfnt_index: usize, raw_hdmx: &[u8]) -> Result<Vec<u8>> {
+    // byte 0..2: version
+    // byte 2..4: numRecords
+    // byte 4..8: sizeDeviceRecord
+    let header = raw_hdmx
+        .get(..8)
+        .ok_or_else(|| anyhow!("sfnt {} table “hdmx”: data truncated (0..8)", sfnt_index))?;
+    let num_records = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    let record_size = usize::try_from(u32::from_be_bytes(header[4..8].try_into()?))?;
+    (0..num_records)
+        .map(|i| {
+            // byte 0: pixelSize
+            raw_hdmx.get(8 + i * record_size).copied().ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “hdmx”: data truncated ({})",
+                    sfnt_index,
+                    8 + i * record_size
+                )
+            })
+        })
+        .collect()
+}
+
+// Keeps the device records in "hdmx" up to max_ppem, returning None if no
+// record is left.
+pub fn truncate_hdmx(sfnt_index: usize, raw_hdmx: &[u8], max_ppem: u16) -> Result<Option<Vec<u8>>> {
+    let ppems = read_hdmx_ppems(sfnt_index, raw_hdmx)?;
+    let record_size = usize::try_from(u32::from_be_bytes(raw_hdmx[4..8].try_into()?))?;
+    let mut new_hdmx = raw_hdmx[..8].to_vec();
+    let mut num_records = 0u16;
+    for (i, ppem) in ppems.into_iter().enumerate() {
+        if u16::from(ppem) > max_ppem {
+            continue;
+        }
+        let record = raw_hdmx
+            .get(8 + i * record_size..8 + (i + 1) * record_size)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “hdmx”: data truncated ({}..{})",
+                    sfnt_index,
+                    8 + i * record_size,
+                    8 + (i + 1) * record_size
+                )
+            })?;
+        new_hdmx.extend_from_slice(record);
+        num_records += 1;
+    }
+    if num_records == 0 {
+        return Ok(None);
+    }
+    new_hdmx[2..4].clone_from_slice(&num_records.to_be_bytes());
+    Ok(Some(new_hdmx))
+}
+
+// Builds "hdmx" for unhinted glyphs, whose device advance widths are the
+// advance widths scaled linearly and rounded to whole pixels. Sizes where
+// some width does not fit in a byte are left out, None if no size is left.
+pub fn build_hdmx(sfnt_index: usize, sfnt: &SfntHeader, ppems: &[u8]) -> Result<Option<Vec<u8>>> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    let advance_widths = read_advance_widths(sfnt_index, sfnt)?;
+    // Device records are padded to a multiple of 4 bytes
+    let record_size = (2 + advance_widths.len()).div_ceil(4) * 4;
+
+    let mut ppems = ppems.to_vec();
+    ppems.sort_unstable();
+    ppems.dedup();
+    let mut records = Vec::new();
+    for ppem in ppems {
+        let widths = advance_widths
+            .iter()
+            .map(|&x| u8::try_from(scale_to_pixels(i32::from(x), ppem, units_per_em)))
+            .collect::<Result<Vec<_>, _>>();
+        let widths = match widths {
+            Ok(widths) => widths,
+            Err(_) => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “hdmx”: advance widths exceed 255 pixels at {} ppem, skipping",
+                    sfnt_index, ppem
+                );
+                continue;
+            }
+        };
+        // byte 0: pixelSize
+        // byte 1: maxWidth
+        // byte 2..: widths
+        records.push(ppem);
+        records.push(widths.iter().copied().max().unwrap_or(0));
+        records.extend(widths);
+        records.resize(records.len().div_ceil(record_size) * record_size, 0);
+    }
+
+    let num_records = records.len() / record_size;
+    if num_records == 0 {
+        return Ok(None);
+    }
+    let mut new_hdmx = Vec::with_capacity(8 + records.len());
+    new_hdmx.extend_from_slice(&0u16.to_be_bytes());
+    new_hdmx.extend_from_slice(&u16::try_from(num_records)?.to_be_bytes());
+    new_hdmx.extend_from_slice(&u32::try_from(record_size)?.to_be_bytes());
+    new_hdmx.extend(records);
+    Ok(Some(new_hdmx))
+}
+
+// Builds "LTSH" for unhinted glyphs, which all scale linearly from 1 ppem.
+pub fn build_ltsh(sfnt_index: usize, sfnt: &SfntHeader) -> Result<Vec<u8>> {
+    // "maxp" table:
+    // byte 4..6: numGlyphs
+    let num_glyphs = read_u16(sfnt_index, sfnt, b"maxp", 4)?;
+    // byte 0..2: version
+    // byte 2..4: numGlyphs
+    // byte 4..: yPels
+    let mut new_ltsh = Vec::with_capacity(4 + usize::from(num_glyphs));
+    new_ltsh.extend_from_slice(&0u16.to_be_bytes());
+    new_ltsh.extend_from_slice(&num_glyphs.to_be_bytes());
+    new_ltsh.resize(4 + usize::from(num_glyphs), 1);
+    Ok(new_ltsh)
+}
+
+// Returns the range of sizes covered by the groups in "VDMX".
+pub fn read_vdmx_ppem_range(sfnt_index: usize, raw_vdmx: &[u8]) -> Result<RangeInclusive<u8>> {
+    let truncated = |from: usize, to: usize| {
+        anyhow!(
+            "sfnt {} table “VDMX”: data truncated ({}..{})",
+            sfnt_index,
+            from,
+            to
+        )
+    };
+    let u16_at = |pos: usize| -> Result<u16> {
+        Ok(u16::from_be_bytes(
+            raw_vdmx
+                .get(pos..pos + 2)
+                .ok_or_else(|| truncated(pos, pos + 2))?
+                .try_into()?,
+        ))
+    };
+    // byte 0..2: version
+    // byte 2..4: numRecs
+    // byte 4..6: numRatios
+    // byte 6..: ratRange, 4 bytes each, then offset, 2 bytes each
+    let num_ratios = usize::from(u16_at(4)?);
+    let mut ranges = Vec::with_capacity(num_ratios);
+    for i in 0..num_ratios {
+        let offset = usize::from(u16_at(6 + num_ratios * 4 + i * 2)?);
+        // byte 2: startsz
+        // byte 3: endsz
+        let group = raw_vdmx
+            .get(offset..offset + 4)
+            .ok_or_else(|| truncated(offset, offset + 4))?;
+        ranges.push(group[2]..=group[3]);
+    }
+    match (
+        ranges.iter().map(|x| *x.start()).min(),
+        ranges.iter().map(|x| *x.end()).max(),
+    ) {
+        (Some(start), Some(end)) if start.max(1) <= end => Ok(start.max(1)..=end),
+        _ => bail!("sfnt {} table “VDMX”: no valid groups", sfnt_index),
+    }
+}
+
+// Builds "VDMX" from the extents of unhinted glyphs. The glyphs scale
+// linearly, so the extents at each size come from the highest and lowest
+// points of the font, rounded outwards to cover anti-aliased pixels. They
+// only depend on the vertical size, so one group serves every aspect ratio.
+pub fn build_vdmx(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    ppem_range: RangeInclusive<u8>,
+) -> Result<Vec<u8>> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    let (y_max, y_min) = read_font_y_extents(sfnt_index, sfnt, None)?;
+
+    let mut new_vdmx = Vec::new();
+    // byte 0..2: version, 1 for character set 0 to mean all glyphs
+    // byte 2..4: numRecs
+    // byte 4..6: numRatios
+    for x in [1u16, 1, 2].iter() {
+        new_vdmx.extend_from_slice(&x.to_be_bytes());
+    }
+    // ratRange: bCharSet, xRatio, yStartRatio, yEndRatio. The 1:1 ratio,
+    // then 0:0 matching any other ratio
+    new_vdmx.extend_from_slice(&[0, 1, 1, 1]);
+    new_vdmx.extend_from_slice(&[0, 0, 0, 0]);
+    // offset: both ratios share the same group
+    let group_offset = u16::try_from(new_vdmx.len() + 4)?;
+    new_vdmx.extend_from_slice(&group_offset.to_be_bytes());
+    new_vdmx.extend_from_slice(&group_offset.to_be_bytes());
+
+    // Group:
+    // byte 0..2: recs
+    // byte 2: startsz
+    // byte 3: endsz
+    // byte 4..: entries of yPelHeight, yMax, yMin
+    new_vdmx.extend_from_slice(&u16::try_from(ppem_range.clone().count())?.to_be_bytes());
+    new_vdmx.push(*ppem_range.start());
+    new_vdmx.push(*ppem_range.end());
+    for ppem in ppem_range {
+        // Scaled extents, rounded outwards
+        let scaled = |value: i32| i64::from(value) * i64::from(ppem);
+        let units_per_em = i64::from(units_per_em);
+        let ceil = -(-scaled(y_max)).div_euclid(units_per_em);
+        let floor = scaled(y_min).div_euclid(units_per_em);
+        let clamp = |x: i64| x.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        new_vdmx.extend_from_slice(&u16::from(ppem).to_be_bytes());
+        new_vdmx.extend_from_slice(&clamp(ceil).to_be_bytes());
+        new_vdmx.extend_from_slice(&clamp(floor).to_be_bytes());
+    }
+    Ok(new_vdmx)
+}
+
+// Returns the smallest ascent and descent in font units, both positive, that
+// leave one pixel above and below the glyphs at every size from min_ppem,
+// where anti-aliased pixels would otherwise be clipped. Without a list, all
+// glyphs are measured.
+pub fn read_clipping_extents(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    min_ppem: u8,
+    glyph_ids: Option<&BTreeSet<u16>>,
+) -> Result<(i32, i32)> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    let (y_max, y_min) = read_font_y_extents(sfnt_index, sfnt, glyph_ids)?;
+    // The scaled metric, rounded half up, must reach past the last pixel
+    // touched by the outline
+    let extent = |value: i32| {
+        (min_ppem.max(1)..=u8::MAX)
+            .map(|ppem| {
+                let units_per_em = i64::from(units_per_em);
+                let ppem = i64::from(ppem);
+                let pixels = -(-i64::from(value.max(0)) * ppem).div_euclid(units_per_em) + 1;
+                -(-(pixels * 2 - 1) * units_per_em).div_euclid(ppem * 2)
+            })
+            .max()
+            .unwrap_or_default() as i32
+    };
+    Ok((extent(y_max), extent(-y_min)))
+}
+
+// Returns the highest and the lowest point of the glyphs in font units, all
+// of them without a list.
+fn read_font_y_extents(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    glyph_ids: Option<&BTreeSet<u16>>,
+) -> Result<(i32, i32)> {
+    let is_selected = |glyph_index: usize| {
+        glyph_ids.is_none_or(|x| u16::try_from(glyph_index).is_ok_and(|i| x.contains(&i)))
+    };
+    let mut extents = Option::<(i32, i32)>::None;
+    let mut add_extents = |y_max: i32, y_min: i32| {
+        extents = Some(match extents {
+            Some((a, b)) => (a.max(y_max), b.min(y_min)),
+            None => (y_max, y_min),
+        });
+    };
+
+    if sfnt.table_records.contains_key(&b"CFF2".into()) {
+        bail!(
+            "sfnt {} table “CFF2”: outlines are not supported",
+            sfnt_index
+        );
+    }
+    if let Some(cff) = sfnt.table_records.get(&b"CFF ".into()) {
+        let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+        let outlines = cff::read_cff_outlines(sfnt_index, &cff.raw_data, units_per_em)?;
+        for (glyph_index, contours) in outlines.glyphs.iter().enumerate() {
+            if !is_selected(glyph_index) {
+                continue;
+            }
+            // Control points enclose the curves
+            let y = contours.iter().flatten().map(|x| x.1);
+            if let Some((max, min)) = y.clone().reduce(f32::max).zip(y.reduce(f32::min)) {
+                add_extents(max.ceil() as i32, min.floor() as i32);
+            }
+        }
+        return extents.ok_or_else(|| anyhow!("sfnt {} table “CFF ”: no outlines", sfnt_index));
+    }
+
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+    for (glyph_index, glyph) in glyphs.iter().enumerate() {
+        if !is_selected(glyph_index) {
+            continue;
+        }
+        let number_of_contours = match glyph.get(0..2) {
+            Some(x) => i16::from_be_bytes([x[0], x[1]]),
+            None => continue,
+        };
+        // Composite glyphs are measured from their components, rather than
+        // trusting the bounding box in the header
+        let glyph_extents = if number_of_contours >= 0 {
+            glyph.get(2..10).map(|x| {
+                (
+                    i32::from(i16::from_be_bytes([x[6], x[7]])),
+                    i32::from(i16::from_be_bytes([x[2], x[3]])),
+                )
+            })
+        } else {
+            let contours = glyf::glyph_outline(sfnt_index, glyph_index, &glyphs)?;
+            let y = contours.iter().flatten().map(|x| x.1);
+            y.clone()
+                .reduce(f32::max)
+                .zip(y.reduce(f32::min))
+                .map(|(max, min)| (max.ceil() as i32, min.floor() as i32))
+        };
+        if let Some((y_max, y_min)) = glyph_extents {
+            add_extents(y_max, y_min);
+        }
+    }
+    extents.ok_or_else(|| anyhow!("sfnt {} table “glyf”: no outlines", sfnt_index))
+}
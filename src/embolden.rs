@@ -0,0 +1,237 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::glyf::SimpleGlyph;
+
+// How a point moves when the outline is emboldened
+struct CornerOffset {
+    // Movement per font unit of emboldening on each side
+    direction: (f32, f32),
+    // Largest emboldening on each side before the corner would pass the end
+    // of its shorter edge
+    limit: f32,
+}
+
+// Each point moves away from the ink along the bisector of the normals of
+// its two edges, as in FT_Outline_EmboldenXY.
+fn corner_offsets(glyph: &SimpleGlyph) -> Vec<CornerOffset> {
+    // TrueType contours go clockwise around the ink, with holes going the
+    // other way. Glyphs converted from PostScript may go counterclockwise.
+    let mut area = 0.0f64;
+    let mut start = 0;
+    for &end in glyph.end_points.iter() {
+        let contour = &glyph.points[start..=end];
+        for (i, &(x0, y0, _)) in contour.iter().enumerate() {
+            let (x1, y1, _) = contour[(i + 1) % contour.len()];
+            area += f64::from(x0) * f64::from(y1) - f64::from(x1) * f64::from(y0);
+        }
+        start = end + 1;
+    }
+    let orientation = if area > 0.0 { -1.0 } else { 1.0 };
+
+    let mut offsets = Vec::with_capacity(glyph.points.len());
+    let mut start = 0;
+    for &end in glyph.end_points.iter() {
+        let contour = &glyph.points[start..=end];
+        let len = contour.len();
+        let position = |i: usize| {
+            let (x, y, _) = contour[i % len];
+            (x as f32, y as f32)
+        };
+        for i in 0..len {
+            let current = position(i);
+            // The nearest distinct points before and after
+            let previous = (1..len)
+                .map(|j| position(i + len - j))
+                .find(|&x| x != current);
+            let next = (1..len).map(|j| position(i + j)).find(|&x| x != current);
+            let offset = match (previous, next) {
+                (Some(previous), Some(next)) => {
+                    let unit = |from: (f32, f32), to: (f32, f32)| {
+                        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                        let length = dx.hypot(dy);
+                        ((dx / length, dy / length), length)
+                    };
+                    let (d_in, l_in) = unit(previous, current);
+                    let (d_out, l_out) = unit(current, next);
+                    let cos = d_in.0 * d_out.0 + d_in.1 * d_out.1;
+                    // Nearly reversing edges would make a spike
+                    if cos > -0.9375 {
+                        let d = cos + 1.0;
+                        // Sum of the normals on the side away from the ink
+                        let normal = (
+                            -(d_in.1 + d_out.1) * orientation,
+                            (d_in.0 + d_out.0) * orientation,
+                        );
+                        // Corners cannot move further than the shorter edge
+                        let q = (d_in.0 * d_out.1 - d_in.1 * d_out.0) * orientation;
+                        let l = l_in.min(l_out);
+                        CornerOffset {
+                            direction: (normal.0 / d, normal.1 / d),
+                            limit: if q > 0.0 { l * d / q } else { f32::INFINITY },
+                        }
+                    } else {
+                        CornerOffset {
+                            direction: (0.0, 0.0),
+                            limit: 0.0,
+                        }
+                    }
+                }
+                _ => CornerOffset {
+                    direction: (0.0, 0.0),
+                    limit: 0.0,
+                },
+            };
+            offsets.push(offset);
+        }
+        start = end + 1;
+    }
+    offsets
+}
+
+// Widens the strokes of a simple glyph by strength font units, in the same
+// way as FT_Outline_Embolden in FreeType. Each point moves along its corner
+// offset, then the outline shifts by half the strength, so that it only
+// grows to the right and to the top, and stays on the baseline.
+pub fn embolden_glyph(glyph: &mut SimpleGlyph, strength: f32) {
+    let half = strength / 2.0;
+    let offsets = corner_offsets(glyph);
+    for (point, offset) in glyph.points.iter_mut().zip(offsets) {
+        let shift = half.min(offset.limit);
+        point.0 = (point.0 as f32 + half + offset.direction.0 * shift).round() as i32;
+        point.1 = (point.1 as f32 + half + offset.direction.1 * shift).round() as i32;
+    }
+}
+
+// Storage location of the stem darkening amount, in 26.6 pixels on each side
+const DARKENING_STORAGE: u8 = 0;
+// Function that moves a point along its corner offset
+const DARKENING_FUNCTION: u8 = 0;
+
+// Font program defining the darkening function. It takes the point number,
+// and the x and y offsets in 26.6 multiples of the darkening amount. Points
+// are shifted by fractions of a pixel and never rounded to the grid.
+pub fn stem_darkening_fpgm() -> Vec<u8> {
+    vec![
+        0xb0, // PUSHB[0]
+        DARKENING_FUNCTION,
+        0x2c, // FDEF[]
+        //
+        0xb0, // PUSHB[0]
+        DARKENING_STORAGE,
+        0x43, // RS[]
+        0x63, // MUL[], dy = y offset × amount
+        0x23, // SWAP[]
+        0xb0, // PUSHB[0]
+        DARKENING_STORAGE,
+        0x43, // RS[]
+        0x63, // MUL[], dx = x offset × amount
+        //
+        0xb0, // PUSHB[0]
+        0x03, // k = 3
+        0x25, // CINDEX[], copy the point number
+        0x23, // SWAP[]
+        0x05, // SFVTCA[1], freedom vector on the x-axis
+        0x38, // SHPIX[], move by dx
+        0x04, // SFVTCA[0], freedom vector on the y-axis
+        0x38, // SHPIX[], move by dy
+        //
+        0x2d, // ENDF[]
+    ]
+}
+
+// CVT program code that stores the darkening amount for the current size:
+// full at from_ppem and below, fading linearly to nothing at to_ppem. Glyph
+// programs, turned off by the patched CVT program, run again while the
+// amount is not zero.
+pub fn stem_darkening_prep(from_ppem: u8, to_ppem: u8, pixels: f32) -> Vec<u8> {
+    let [to_hi, to_lo] = u16::from(to_ppem).to_be_bytes();
+    let [span_hi, span_lo] = u16::from(to_ppem - from_ppem).to_be_bytes();
+    let [amount_hi, amount_lo] = ((pixels * 32.0).round() as u16).to_be_bytes();
+    vec![
+        0xb0, // PUSHB[0]
+        DARKENING_STORAGE,
+        0xb8, // PUSHW[0]
+        to_hi,
+        to_lo, // n = to_ppem
+        0x00,  // SVTCA[0]
+        0x4b,  // MPPEM[]
+        0x61,  // SUB[], to_ppem - ppem
+        0xb0,  // PUSHB[0]
+        0x00,  // n = 0
+        0x8b,  // MAX[]
+        0xb8,  // PUSHW[0]
+        span_hi,
+        span_lo, // n = to_ppem - from_ppem
+        0x8c,    // MIN[]
+        0xb8,    // PUSHW[0]
+        span_hi,
+        span_lo, // n = to_ppem - from_ppem
+        0x62,    // DIV[], fraction of the full amount
+        0xb8,    // PUSHW[0]
+        amount_hi,
+        amount_lo, // n = half of pixels, in 26.6
+        0x63,      // MUL[]
+        //
+        0x20, // DUP[]
+        0xb0, // PUSHB[0]
+        0x00, // n = 0
+        0x52, // GT[], amount > 0
+        0x58, // IF[]
+        0xb1, // PUSHB[1]
+        0x00, // value = 0
+        0x01, // s = 1
+        0x8e, // INSTRCTRL[], turn grid-fitting on
+        0x59, // EIF[]
+        //
+        0x42, // WS[]
+    ]
+}
+
+// Glyph program that moves every point of a simple glyph along its corner
+// offset, scaled by the darkening amount. Corners are limited as if the
+// amount on each side were max_units font units.
+pub fn stem_darkening_program(glyph: &SimpleGlyph, max_units: f32) -> Vec<u8> {
+    // At most 254 words per NPUSHW, including the loop count and function
+    const POINTS_PER_CALL: usize = 84;
+
+    let mut arguments = Vec::new();
+    for (point_index, offset) in corner_offsets(glyph).into_iter().enumerate() {
+        let scale = if max_units > offset.limit {
+            offset.limit / max_units
+        } else {
+            1.0
+        };
+        let dx = (offset.direction.0 * scale * 64.0).round() as i16;
+        let dy = (offset.direction.1 * scale * 64.0).round() as i16;
+        if dx != 0 || dy != 0 {
+            arguments.push([point_index as u16, dx as u16, dy as u16]);
+        }
+    }
+
+    let mut program = Vec::new();
+    for chunk in arguments.chunks(POINTS_PER_CALL) {
+        program.push(0x41); // NPUSHW[]
+        program.push((chunk.len() * 3 + 2) as u8);
+        for &value in chunk.iter().flatten() {
+            program.extend_from_slice(&value.to_be_bytes());
+        }
+        program.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        program.extend_from_slice(&u16::from(DARKENING_FUNCTION).to_be_bytes());
+        program.push(0x2a); // LOOPCALL[]
+    }
+    program
+}
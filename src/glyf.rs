@@ -0,0 +1,967 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::cff::CubicPoint;
+use super::types::SfntHeader;
+use super::types::TableRecord;
+
+pub fn get_glyf_formats(sfnt: &SfntHeader) -> (Option<[u8; 2]>, Option<[u8; 2]>) {
+    // "head" table:
+    // byte 50..52: indexToLocFormat
+    // byte 52..54: glyphDataFormat
+    sfnt.table_records
+        .get(&b"head".into())
+        .map_or((None, None), |head| {
+            (
+                head.raw_data
+                    .get(50..52)
+                    .and_then(|x| <[_; 2]>::try_from(x).ok()),
+                head.raw_data
+                    .get(52..54)
+                    .and_then(|x| <[_; 2]>::try_from(x).ok()),
+            )
+        })
+}
+
+// Splits the "glyf" table into glyphs according to "loca".
+// Returns None if the glyph data format is not supported.
+pub fn split_glyf(sfnt_index: usize, sfnt: &SfntHeader) -> Option<Vec<&[u8]>> {
+    let (loca_format, glyf_format) = get_glyf_formats(sfnt);
+
+    // If there is a "loca" table
+    let loca =
+        sfnt.table_records
+            .get(&b"loca".into())
+            .map_or(Vec::new(), |loca| match loca_format {
+                Some([0, 0]) => {
+                    if loca.raw_data.len() % 2 != 0 {
+                        eprintln!(
+                            "[ WARN ] sfnt {} table “loca”: length {} is not multiples of 2",
+                            sfnt_index,
+                            loca.raw_data.len()
+                        );
+                    }
+                    loca.raw_data
+                        .chunks_exact(2)
+                        .map(|x| usize::from(u16::from_be_bytes(x.try_into().unwrap())) * 2)
+                        .collect::<Vec<_>>()
+                }
+                Some([0, 1]) => {
+                    if loca.raw_data.len() % 4 != 0 {
+                        eprintln!(
+                            "[ WARN ] sfnt {} table “loca”: length {} is not multiples of 4",
+                            sfnt_index,
+                            loca.raw_data.len()
+                        );
+                    }
+                    loca.raw_data
+                        .chunks_exact(4)
+                        .map(|x| {
+                            usize::try_from(u32::from_be_bytes(x.try_into().unwrap())).unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                }
+                Some(loca_format) => {
+                    eprintln!(
+                        "[ WARN ] sfnt {} table “head”: unsupported indexToLocFormat: {}",
+                        sfnt_index,
+                        u16::from_be_bytes(loca_format)
+                    );
+                    Vec::new()
+                }
+                _ => {
+                    eprintln!(
+                        "[ WARN ] sfnt {} table “head”: unspecified indexToLocFormat value",
+                        sfnt_index
+                    );
+                    Vec::new()
+                }
+            });
+
+    // If there is a "glyf" table
+    let raw_glyf = sfnt
+        .table_records
+        .get(&b"glyf".into())
+        .map_or([].as_ref(), |glyf| &glyf.raw_data);
+
+    // Extract each glyph from the "glyf" table
+    let glyf = loca
+        .windows(2)
+        .enumerate()
+        .map(|(glyph_index, glyph_offset)| {
+            let glyph_offset_from = glyph_offset[0];
+            let glyph_offset_to = glyph_offset[1];
+            if glyph_offset_from > glyph_offset_to {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “loca”: glyph {} has a negative length ({} > {})",
+                    sfnt_index, glyph_index, glyph_offset_from, glyph_offset_to
+                );
+                [].as_ref()
+            } else if let Some(glyph) = raw_glyf.get(glyph_offset_from..glyph_offset_to) {
+                glyph
+            } else {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “loca”: glyph {} is out of bounds ({}..{})",
+                    sfnt_index, glyph_index, glyph_offset_from, glyph_offset_to
+                );
+                [].as_ref()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Make sure we don't run into unknown formats
+    if !glyf.is_empty() {
+        match glyf_format {
+            Some([0, 0]) => (),
+            Some(glyf_format) => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “head”: unsupported glyphDataFormat: {}",
+                    sfnt_index,
+                    u16::from_be_bytes(glyf_format)
+                );
+                return None;
+            }
+            _ => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “head”: unspecified glyphDataFormat value",
+                    sfnt_index
+                );
+                return None;
+            }
+        }
+    }
+
+    Some(glyf)
+}
+
+// Concatenates glyphs into new "glyf" and "loca" tables.
+// Returns the raw "glyf", the raw "loca", and the new indexToLocFormat.
+pub fn build_glyf(
+    sfnt_index: usize,
+    glyphs: &[impl AsRef<[u8]>],
+) -> Option<(Vec<u8>, Vec<u8>, [u8; 2])> {
+    let mut new_raw_glyf = Vec::with_capacity(glyphs.iter().map(|x| x.as_ref().len() + 1).sum());
+    let mut new_loca = Vec::with_capacity(glyphs.len() + 1);
+    for glyph in glyphs {
+        if new_raw_glyf.len() % 2 != 0 {
+            new_raw_glyf.push(0);
+        }
+        new_loca.push(new_raw_glyf.len());
+        new_raw_glyf.extend(glyph.as_ref());
+    }
+    if new_raw_glyf.len() < 131072 && new_raw_glyf.len() % 2 != 0 {
+        new_raw_glyf.push(0);
+    }
+    new_loca.push(new_raw_glyf.len());
+
+    if new_raw_glyf.len() < 131072 {
+        let mut new_raw_loca = Vec::with_capacity(new_loca.len() * 2);
+        for x in new_loca {
+            assert_eq!(x % 2, 0);
+            new_raw_loca.extend_from_slice(&u16::try_from(x / 2).unwrap().to_be_bytes());
+        }
+        Some((new_raw_glyf, new_raw_loca, [0, 0]))
+    } else {
+        let mut new_raw_loca = Vec::with_capacity(new_loca.len() * 4);
+        for x in new_loca {
+            if let Ok(x) = u32::try_from(x) {
+                new_raw_loca.extend_from_slice(&x.to_be_bytes());
+            } else {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “glyf”: size exceeds 4 GiB",
+                    sfnt_index
+                );
+                return None;
+            }
+        }
+        Some((new_raw_glyf, new_raw_loca, [0, 1]))
+    }
+}
+
+// Replaces "glyf" and "loca", and updates indexToLocFormat in "head".
+pub fn set_glyf(sfnt: &mut SfntHeader, glyf: Rc<[u8]>, loca: Rc<[u8]>, loca_format: [u8; 2]) {
+    sfnt.table_records.insert(
+        b"loca".into(),
+        TableRecord {
+            checksum: 0,
+            offset: 0,
+            raw_data: loca,
+        },
+    );
+    sfnt.table_records.insert(
+        b"glyf".into(),
+        TableRecord {
+            checksum: 0,
+            offset: 0,
+            raw_data: glyf,
+        },
+    );
+    if let Some(head) = sfnt.table_records.get_mut(&b"head".into()) {
+        if head.raw_data.get(50..52) != Some(&loca_format) {
+            let mut new_head = head.raw_data.to_vec();
+            // byte 50..52: indexToLocFormat
+            if let Some(x) = new_head.get_mut(50..52) {
+                x.clone_from_slice(&loca_format);
+            }
+            head.raw_data = Rc::from(new_head);
+        }
+    }
+}
+
+// Returns the glyph indices referenced by a composite glyph,
+// or an empty list for a simple glyph.
+pub fn glyph_components(sfnt_index: usize, glyph_index: usize, glyph: &[u8]) -> Result<Vec<u16>> {
+    let mut components = Vec::new();
+    if glyph.len() < 2 || u16::from_be_bytes([glyph[0], glyph[1]]) < 0x8000 {
+        return Ok(components);
+    }
+
+    let mut glyph_len = 10;
+    let mut more_components = true;
+    while more_components {
+        let component = glyph.get(glyph_len..glyph_len + 4).ok_or_else(|| {
+            anyhow!(
+                "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                sfnt_index,
+                glyph_index,
+                glyph_len,
+                glyph_len + 4
+            )
+        })?;
+        let flags = [component[0], component[1]];
+        components.push(u16::from_be_bytes([component[2], component[3]]));
+        glyph_len += 4;
+        if flags[1] & 0x01 != 0 {
+            // ARG_1_AND_2_ARE_WORDS
+            glyph_len += 4;
+        } else {
+            glyph_len += 2;
+        }
+        if flags[1] & 0x08 != 0 {
+            // WE_HAVE_A_SCALE
+            glyph_len += 2;
+        } else if flags[1] & 0x40 != 0 {
+            // WE_HAVE_AN_X_AND_Y_SCALE
+            glyph_len += 4;
+        } else if flags[1] & 0x80 != 0 {
+            // WE_HAVE_A_TWO_BY_TWO
+            glyph_len += 8;
+        }
+        // MORE_COMPONENTS
+        more_components = flags[1] & 0x20 != 0;
+    }
+    Ok(components)
+}
+
+// Moves the components of a composite glyph that are placed by offsets,
+// rather than by matching points. Arguments are widened to words as needed.
+pub fn move_components(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyph: &[u8],
+    deltas: &[(i32, i32)],
+) -> Result<Vec<u8>> {
+    let truncated = |pos: usize| {
+        anyhow!(
+            "sfnt {} table “glyf”: glyph {} data truncated ({})",
+            sfnt_index,
+            glyph_index,
+            pos
+        )
+    };
+    let mut new_glyph = glyph.get(..10).ok_or_else(|| truncated(10))?.to_vec();
+    let mut pos = 10;
+    for component_index in 0.. {
+        let header = glyph.get(pos..pos + 4).ok_or_else(|| truncated(pos + 4))?;
+        let mut flags = u16::from_be_bytes([header[0], header[1]]);
+        let component_glyph = [header[2], header[3]];
+        pos += 4;
+        // ARG_1_AND_2_ARE_WORDS
+        let args_len = if flags & 0x0001 != 0 { 4 } else { 2 };
+        let args = glyph
+            .get(pos..pos + args_len)
+            .ok_or_else(|| truncated(pos + args_len))?;
+        pos += args_len;
+        let mut new_args = args.to_vec();
+        // ARGS_ARE_XY_VALUES
+        if let Some(&(dx, dy)) = deltas.get(component_index).filter(|_| flags & 0x0002 != 0) {
+            let (x, y) = if flags & 0x0001 != 0 {
+                (
+                    i32::from(i16::from_be_bytes([args[0], args[1]])) + dx,
+                    i32::from(i16::from_be_bytes([args[2], args[3]])) + dy,
+                )
+            } else {
+                (i32::from(args[0] as i8) + dx, i32::from(args[1] as i8) + dy)
+            };
+            new_args = match (i8::try_from(x), i8::try_from(y)) {
+                (Ok(x), Ok(y)) if flags & 0x0001 == 0 => vec![x as u8, y as u8],
+                _ => match (i16::try_from(x), i16::try_from(y)) {
+                    (Ok(x), Ok(y)) => {
+                        flags |= 0x0001;
+                        [x.to_be_bytes(), y.to_be_bytes()].concat()
+                    }
+                    _ => bail!(
+                        "sfnt {} table “glyf”: glyph {} has component offsets out of range",
+                        sfnt_index,
+                        glyph_index
+                    ),
+                },
+            };
+        }
+        new_glyph.extend_from_slice(&flags.to_be_bytes());
+        new_glyph.extend_from_slice(&component_glyph);
+        new_glyph.extend(new_args);
+
+        let transform_len = if flags & 0x0008 != 0 {
+            // WE_HAVE_A_SCALE
+            2
+        } else if flags & 0x0040 != 0 {
+            // WE_HAVE_AN_X_AND_Y_SCALE
+            4
+        } else if flags & 0x0080 != 0 {
+            // WE_HAVE_A_TWO_BY_TWO
+            8
+        } else {
+            0
+        };
+        new_glyph.extend_from_slice(
+            glyph
+                .get(pos..pos + transform_len)
+                .ok_or_else(|| truncated(pos + transform_len))?,
+        );
+        pos += transform_len;
+        // MORE_COMPONENTS
+        if flags & 0x0020 == 0 {
+            break;
+        }
+    }
+    // Instructions, if any
+    new_glyph.extend_from_slice(&glyph[pos..]);
+    Ok(new_glyph)
+}
+
+// A simple glyph with its instructions, for rewriting the coordinates.
+pub struct SimpleGlyph {
+    // Index of the last point of each contour
+    pub end_points: Vec<usize>,
+    pub instructions: Vec<u8>,
+    // Coordinates in font units, and whether the point is on the curve
+    pub points: Vec<(i32, i32, bool)>,
+    // OVERLAP_SIMPLE
+    pub overlap: bool,
+}
+
+pub fn is_simple_glyph(glyph: &[u8]) -> bool {
+    // byte 0..2: numberOfContours, negative for composite glyphs
+    glyph.len() >= 10 && i16::from_be_bytes([glyph[0], glyph[1]]) >= 0
+}
+
+// Passes each simple glyph through map, which modifies it in place and returns
+// whether it changed. Composite glyphs are kept as they are, they take the
+// outlines and run the programs of their components. Glyphs whose points are
+// unchanged keep the bounding box in their header.
+pub fn map_simple_glyphs(
+    sfnt_index: usize,
+    glyphs: &[&[u8]],
+    mut map: impl FnMut(usize, &mut SimpleGlyph) -> Result<bool>,
+) -> Result<Vec<Vec<u8>>> {
+    let mut new_glyphs = Vec::with_capacity(glyphs.len());
+    for (glyph_index, &glyph) in glyphs.iter().enumerate() {
+        if !is_simple_glyph(glyph) {
+            new_glyphs.push(glyph.to_vec());
+            continue;
+        }
+        let mut simple_glyph = decode_simple_glyph(sfnt_index, glyph_index, glyph)?;
+        let points = simple_glyph.points.clone();
+        if !map(glyph_index, &mut simple_glyph)? {
+            new_glyphs.push(glyph.to_vec());
+            continue;
+        }
+        let mut new_glyph = encode_simple_glyph(sfnt_index, glyph_index, &simple_glyph)?;
+        if simple_glyph.points == points && new_glyph.len() >= 10 {
+            new_glyph[2..10].clone_from_slice(&glyph[2..10]);
+        }
+        new_glyphs.push(new_glyph);
+    }
+    Ok(new_glyphs)
+}
+
+pub fn decode_simple_glyph(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyph: &[u8],
+) -> Result<SimpleGlyph> {
+    let truncated = |pos: usize| {
+        anyhow!(
+            "sfnt {} table “glyf”: glyph {} data truncated ({})",
+            sfnt_index,
+            glyph_index,
+            pos
+        )
+    };
+    let u16_at = |pos: usize| -> Result<u16> {
+        Ok(u16::from_be_bytes(
+            glyph
+                .get(pos..pos + 2)
+                .ok_or_else(|| truncated(pos))?
+                .try_into()?,
+        ))
+    };
+    let number_of_contours = usize::try_from(u16_at(0)? as i16).map_err(|_| {
+        anyhow!(
+            "sfnt {} table “glyf”: glyph {} is not a simple glyph",
+            sfnt_index,
+            glyph_index
+        )
+    })?;
+    let mut end_points = Vec::with_capacity(number_of_contours);
+    for i in 0..number_of_contours {
+        end_points.push(usize::from(u16_at(10 + i * 2)?));
+    }
+    let num_points = end_points.last().map_or(0, |x| x + 1);
+    let instruction_length = usize::from(u16_at(10 + number_of_contours * 2)?);
+    let instructions_pos = 12 + number_of_contours * 2;
+    let instructions = glyph
+        .get(instructions_pos..instructions_pos + instruction_length)
+        .ok_or_else(|| truncated(instructions_pos + instruction_length))?
+        .to_vec();
+    let mut pos = instructions_pos + instruction_length;
+
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = *glyph.get(pos).ok_or_else(|| truncated(pos))?;
+        pos += 1;
+        flags.push(flag);
+        // REPEAT_FLAG
+        if flag & 0x08 != 0 {
+            let repeat = *glyph.get(pos).ok_or_else(|| truncated(pos))?;
+            pos += 1;
+            for _ in 0..repeat {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(num_points);
+
+    // X_SHORT_VECTOR / Y_SHORT_VECTOR, and X_IS_SAME_OR_POSITIVE_X_SHORT_VECTOR
+    // / Y_IS_SAME_OR_POSITIVE_Y_SHORT_VECTOR
+    let mut coordinates = [
+        Vec::with_capacity(num_points),
+        Vec::with_capacity(num_points),
+    ];
+    for (axis, &(short_bit, same_bit)) in [(0x02, 0x10), (0x04, 0x20)].iter().enumerate() {
+        let mut value = 0i32;
+        for &flag in flags.iter() {
+            if flag & short_bit != 0 {
+                let delta = i32::from(*glyph.get(pos).ok_or_else(|| truncated(pos))?);
+                pos += 1;
+                value += if flag & same_bit != 0 { delta } else { -delta };
+            } else if flag & same_bit == 0 {
+                value += i32::from(u16_at(pos)? as i16);
+                pos += 2;
+            }
+            coordinates[axis].push(value);
+        }
+    }
+
+    let mut start = 0;
+    for &end in end_points.iter() {
+        if end < start || end >= num_points {
+            bail!(
+                "sfnt {} table “glyf”: glyph {} has invalid contour end points",
+                sfnt_index,
+                glyph_index
+            );
+        }
+        start = end + 1;
+    }
+    Ok(SimpleGlyph {
+        end_points,
+        instructions,
+        // ON_CURVE_POINT
+        points: (0..num_points)
+            .map(|i| (coordinates[0][i], coordinates[1][i], flags[i] & 0x01 != 0))
+            .collect(),
+        // OVERLAP_SIMPLE
+        overlap: flags.first().is_some_and(|x| x & 0x40 != 0),
+    })
+}
+
+// A point in font units, and whether it is on the curve.
+pub type OutlinePoint = (f32, f32, bool);
+
+// Decodes a glyph into its contours, resolving composite glyphs.
+pub fn glyph_outline(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyphs: &[&[u8]],
+) -> Result<Vec<Vec<OutlinePoint>>> {
+    // Same limit as maxComponentDepth in most fonts, with some headroom
+    const MAX_COMPONENT_DEPTH: usize = 16;
+
+    fn decode(
+        sfnt_index: usize,
+        glyph_index: usize,
+        glyphs: &[&[u8]],
+        depth: usize,
+    ) -> Result<Vec<Vec<OutlinePoint>>> {
+        let glyph = glyphs.get(glyph_index).copied().unwrap_or_default();
+        let truncated = |pos: usize| {
+            anyhow!(
+                "sfnt {} table “glyf”: glyph {} data truncated ({})",
+                sfnt_index,
+                glyph_index,
+                pos
+            )
+        };
+        let u16_at = |pos: usize| -> Result<u16> {
+            Ok(u16::from_be_bytes(
+                glyph
+                    .get(pos..pos + 2)
+                    .ok_or_else(|| truncated(pos))?
+                    .try_into()?,
+            ))
+        };
+        if glyph.len() < 10 {
+            return Ok(Vec::new());
+        }
+        let number_of_contours = i16::from_be_bytes([glyph[0], glyph[1]]);
+
+        if number_of_contours >= 0 {
+            let simple_glyph = decode_simple_glyph(sfnt_index, glyph_index, glyph)?;
+            let mut contours = Vec::with_capacity(simple_glyph.end_points.len());
+            let mut start = 0;
+            for &end in simple_glyph.end_points.iter() {
+                contours.push(
+                    simple_glyph.points[start..=end]
+                        .iter()
+                        .map(|&(x, y, on_curve)| (x as f32, y as f32, on_curve))
+                        .collect(),
+                );
+                start = end + 1;
+            }
+            return Ok(contours);
+        }
+
+        if depth >= MAX_COMPONENT_DEPTH {
+            bail!(
+                "sfnt {} table “glyf”: glyph {} exceeds the maximum component depth",
+                sfnt_index,
+                glyph_index
+            );
+        }
+        let mut contours = Vec::<Vec<OutlinePoint>>::new();
+        let mut pos = 10;
+        loop {
+            let flags = u16_at(pos)?;
+            let component_index = usize::from(u16_at(pos + 2)?);
+            pos += 4;
+            // ARG_1_AND_2_ARE_WORDS, ARGS_ARE_XY_VALUES
+            let (arg1, arg2) = match (flags & 0x0001 != 0, flags & 0x0002 != 0) {
+                (true, true) => (
+                    i32::from(u16_at(pos)? as i16),
+                    i32::from(u16_at(pos + 2)? as i16),
+                ),
+                (true, false) => (i32::from(u16_at(pos)?), i32::from(u16_at(pos + 2)?)),
+                (false, true) => {
+                    let args = glyph.get(pos..pos + 2).ok_or_else(|| truncated(pos))?;
+                    (i32::from(args[0] as i8), i32::from(args[1] as i8))
+                }
+                (false, false) => {
+                    let args = glyph.get(pos..pos + 2).ok_or_else(|| truncated(pos))?;
+                    (i32::from(args[0]), i32::from(args[1]))
+                }
+            };
+            pos += if flags & 0x0001 != 0 { 4 } else { 2 };
+            let f2dot14 =
+                |pos: usize| -> Result<f32> { Ok(f32::from(u16_at(pos)? as i16) / 16384.0) };
+            // [xx, xy, yx, yy], where x' = xx * x + yx * y, y' = xy * x + yy * y
+            let matrix = if flags & 0x0008 != 0 {
+                // WE_HAVE_A_SCALE
+                let scale = f2dot14(pos)?;
+                pos += 2;
+                [scale, 0.0, 0.0, scale]
+            } else if flags & 0x0040 != 0 {
+                // WE_HAVE_AN_X_AND_Y_SCALE
+                let matrix = [f2dot14(pos)?, 0.0, 0.0, f2dot14(pos + 2)?];
+                pos += 4;
+                matrix
+            } else if flags & 0x0080 != 0 {
+                // WE_HAVE_A_TWO_BY_TWO
+                let matrix = [
+                    f2dot14(pos)?,
+                    f2dot14(pos + 2)?,
+                    f2dot14(pos + 4)?,
+                    f2dot14(pos + 6)?,
+                ];
+                pos += 8;
+                matrix
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            let transform =
+                |x: f32, y: f32| (matrix[0] * x + matrix[2] * y, matrix[1] * x + matrix[3] * y);
+
+            let component = decode(sfnt_index, component_index, glyphs, depth + 1)?;
+            let (dx, dy) = if flags & 0x0002 != 0 {
+                // SCALED_COMPONENT_OFFSET
+                if flags & 0x0800 != 0 {
+                    transform(arg1 as f32, arg2 as f32)
+                } else {
+                    (arg1 as f32, arg2 as f32)
+                }
+            } else {
+                // Align point arg2 of the component to point arg1 of the parent
+                let parent_point = contours
+                    .iter()
+                    .flatten()
+                    .nth(usize::try_from(arg1).unwrap());
+                let child_point = component
+                    .iter()
+                    .flatten()
+                    .nth(usize::try_from(arg2).unwrap());
+                match (parent_point, child_point) {
+                    (Some(&(px, py, _)), Some(&(cx, cy, _))) => {
+                        let (cx, cy) = transform(cx, cy);
+                        (px - cx, py - cy)
+                    }
+                    _ => bail!(
+                        "sfnt {} table “glyf”: glyph {} has invalid anchor points ({}, {})",
+                        sfnt_index,
+                        glyph_index,
+                        arg1,
+                        arg2
+                    ),
+                }
+            };
+            contours.extend(component.into_iter().map(|contour| {
+                contour
+                    .into_iter()
+                    .map(|(x, y, on_curve)| {
+                        let (x, y) = transform(x, y);
+                        (x + dx, y + dy, on_curve)
+                    })
+                    .collect()
+            }));
+
+            // MORE_COMPONENTS
+            if flags & 0x0020 == 0 {
+                break;
+            }
+        }
+        Ok(contours)
+    }
+
+    decode(sfnt_index, glyph_index, glyphs, 0)
+}
+
+// Calculates the bounding box [xMin, yMin, xMax, yMax] of a glyph from its
+// points, resolving composite glyphs. Returns None if it has no points.
+pub fn glyph_bbox(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyphs: &[&[u8]],
+) -> Result<Option<[i16; 4]>> {
+    let glyph = glyphs.get(glyph_index).copied().unwrap_or_default();
+    if glyph.len() < 10 {
+        return Ok(None);
+    }
+    let bbox = if i16::from_be_bytes([glyph[0], glyph[1]]) >= 0 {
+        let points = decode_simple_glyph(sfnt_index, glyph_index, glyph)?.points;
+        points
+            .iter()
+            .fold(None, |acc: Option<[i32; 4]>, &(x, y, _)| {
+                Some(acc.map_or([x, y, x, y], |acc| {
+                    [acc[0].min(x), acc[1].min(y), acc[2].max(x), acc[3].max(y)]
+                }))
+            })
+    } else {
+        let contours = glyph_outline(sfnt_index, glyph_index, glyphs)?;
+        contours
+            .iter()
+            .flatten()
+            .fold(None, |acc: Option<[f32; 4]>, &(x, y, _)| {
+                Some(acc.map_or([x, y, x, y], |acc| {
+                    [acc[0].min(x), acc[1].min(y), acc[2].max(x), acc[3].max(y)]
+                }))
+            })
+            .map(|x| x.map(|x| x.round() as i32))
+    };
+    bbox.map(|bbox| {
+        let mut result = [0; 4];
+        for (x, &value) in result.iter_mut().zip(bbox.iter()) {
+            *x = i16::try_from(value).map_err(|_| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} has coordinates out of range ({})",
+                    sfnt_index,
+                    glyph_index,
+                    value
+                )
+            })?;
+        }
+        Ok(result)
+    })
+    .transpose()
+}
+
+// Converts a closed cubic contour into a quadratic one, splitting each curve
+// into enough pieces to stay within the tolerance, in font units. The
+// direction is reversed, as TrueType outlines are clockwise and CFF ones
+// counterclockwise.
+pub fn cubic_to_quadratic(contour: &[CubicPoint], tolerance: f32) -> Vec<OutlinePoint> {
+    let mut quadratic = Vec::with_capacity(contour.len() * 2);
+    let point = |i: usize| {
+        let (x, y, _) = contour[i % contour.len()];
+        (x, y)
+    };
+    let mut i = 0;
+    while i < contour.len() {
+        let p0 = point(i);
+        quadratic.push((p0.0, p0.1, true));
+        if contour[(i + 1) % contour.len()].2 {
+            i += 1;
+            continue;
+        }
+        let (p1, p2, p3) = (point(i + 1), point(i + 2), point(i + 3));
+        i += 3;
+
+        // The error of a single quadratic is at most √3/36 of the third
+        // difference, which shrinks with the cube of the number of pieces.
+        let d = (
+            p3.0 - 3.0 * p2.0 + 3.0 * p1.0 - p0.0,
+            p3.1 - 3.0 * p2.1 + 3.0 * p1.1 - p0.1,
+        );
+        let error = 3f32.sqrt() / 36.0 * (d.0 * d.0 + d.1 * d.1).sqrt();
+        let num_pieces = ((error / tolerance).cbrt().ceil() as usize).clamp(1, 64);
+
+        let at = |t: f32| {
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            (
+                a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+                a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+            )
+        };
+        let derivative = |t: f32| {
+            let u = 1.0 - t;
+            let (a, b, c) = (3.0 * u * u, 6.0 * u * t, 3.0 * t * t);
+            (
+                a * (p1.0 - p0.0) + b * (p2.0 - p1.0) + c * (p3.0 - p2.0),
+                a * (p1.1 - p0.1) + b * (p2.1 - p1.1) + c * (p3.1 - p2.1),
+            )
+        };
+        let step = 1.0 / num_pieces as f32;
+        for piece in 0..num_pieces {
+            let (t0, t1) = (piece as f32 * step, (piece + 1) as f32 * step);
+            let (q0, q3) = (at(t0), if piece + 1 == num_pieces { p3 } else { at(t1) });
+            let (d0, d3) = (derivative(t0), derivative(t1));
+            let q1 = (q0.0 + d0.0 * step / 3.0, q0.1 + d0.1 * step / 3.0);
+            let q2 = (q3.0 - d3.0 * step / 3.0, q3.1 - d3.1 * step / 3.0);
+            // Best single control point for the piece
+            let control = (
+                (3.0 * (q1.0 + q2.0) - q0.0 - q3.0) / 4.0,
+                (3.0 * (q1.1 + q2.1) - q0.1 - q3.1) / 4.0,
+            );
+            quadratic.push((control.0, control.1, false));
+            if piece + 1 != num_pieces {
+                quadratic.push((q3.0, q3.1, true));
+            }
+        }
+    }
+    quadratic.reverse();
+    quadratic
+}
+
+// Encodes contours as a simple glyph without instructions, with coordinates
+// rounded to integers. Returns an empty glyph if there are no contours.
+pub fn build_simple_glyph(
+    sfnt_index: usize,
+    glyph_index: usize,
+    contours: &[Vec<OutlinePoint>],
+) -> Result<Vec<u8>> {
+    let mut new_contours = Vec::with_capacity(contours.len());
+    for contour in contours {
+        let mut points = Vec::with_capacity(contour.len());
+        for &(x, y, on_curve) in contour {
+            match (
+                i16::try_from(x.round() as i32),
+                i16::try_from(y.round() as i32),
+            ) {
+                (Ok(x), Ok(y)) => points.push((x, y, on_curve)),
+                _ => bail!(
+                    "sfnt {} table “glyf”: glyph {} has coordinates out of range",
+                    sfnt_index,
+                    glyph_index
+                ),
+            }
+        }
+        // Drop repeated points, and on-curve points halfway between two
+        // off-curve points, which are implied.
+        points.dedup();
+        while points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        let mut i = 0;
+        while i < points.len() && points.len() > 2 {
+            let prev = points[(i + points.len() - 1) % points.len()];
+            let next = points[(i + 1) % points.len()];
+            let (x, y, on_curve) = points[i];
+            if on_curve
+                && !prev.2
+                && !next.2
+                && i32::from(prev.0) + i32::from(next.0) == 2 * i32::from(x)
+                && i32::from(prev.1) + i32::from(next.1) == 2 * i32::from(y)
+            {
+                points.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        if !points.is_empty() {
+            new_contours.push(points);
+        }
+    }
+    if new_contours.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut end_point = 0;
+    encode_simple_glyph(
+        sfnt_index,
+        glyph_index,
+        &SimpleGlyph {
+            end_points: new_contours
+                .iter()
+                .map(|x| {
+                    end_point += x.len();
+                    end_point - 1
+                })
+                .collect(),
+            instructions: Vec::new(),
+            points: new_contours
+                .into_iter()
+                .flatten()
+                .map(|(x, y, on_curve)| (i32::from(x), i32::from(y), on_curve))
+                .collect(),
+            overlap: false,
+        },
+    )
+}
+
+// Encodes a simple glyph, computing its bounding box.
+pub fn encode_simple_glyph(
+    sfnt_index: usize,
+    glyph_index: usize,
+    simple_glyph: &SimpleGlyph,
+) -> Result<Vec<u8>> {
+    let out_of_range = || {
+        anyhow!(
+            "sfnt {} table “glyf”: glyph {} has coordinates out of range",
+            sfnt_index,
+            glyph_index
+        )
+    };
+    let points = &simple_glyph.points;
+    if points.is_empty() {
+        return Ok(Vec::new());
+    }
+    let x_min = points.iter().map(|x| x.0).min().unwrap();
+    let y_min = points.iter().map(|x| x.1).min().unwrap();
+    let x_max = points.iter().map(|x| x.0).max().unwrap();
+    let y_max = points.iter().map(|x| x.1).max().unwrap();
+    let num_points = points.len();
+    if num_points > 0xffff {
+        bail!(
+            "sfnt {} table “glyf”: glyph {} has too many points",
+            sfnt_index,
+            glyph_index
+        );
+    }
+
+    let mut glyph = Vec::new();
+    glyph.extend_from_slice(&i16::try_from(simple_glyph.end_points.len())?.to_be_bytes());
+    for &x in [x_min, y_min, x_max, y_max].iter() {
+        glyph.extend_from_slice(&i16::try_from(x).map_err(|_| out_of_range())?.to_be_bytes());
+    }
+    for &end_point in simple_glyph.end_points.iter() {
+        glyph.extend_from_slice(&u16::try_from(end_point)?.to_be_bytes());
+    }
+    glyph.extend_from_slice(&u16::try_from(simple_glyph.instructions.len())?.to_be_bytes());
+    glyph.extend_from_slice(&simple_glyph.instructions);
+
+    let mut flags = Vec::with_capacity(num_points);
+    let mut x_data = Vec::with_capacity(num_points * 2);
+    let mut y_data = Vec::with_capacity(num_points * 2);
+    // X_SHORT_VECTOR / Y_SHORT_VECTOR, and X_IS_SAME_OR_POSITIVE_X_SHORT_VECTOR
+    // / Y_IS_SAME_OR_POSITIVE_Y_SHORT_VECTOR
+    let encode = |delta: i32, short_bit: u8, same_bit: u8, data: &mut Vec<u8>| {
+        if delta == 0 {
+            same_bit
+        } else if delta.abs() <= 0xff {
+            data.push(delta.unsigned_abs() as u8);
+            if delta > 0 {
+                short_bit | same_bit
+            } else {
+                short_bit
+            }
+        } else {
+            data.extend_from_slice(&(delta as i16).to_be_bytes());
+            0
+        }
+    };
+    let (mut last_x, mut last_y) = (0, 0);
+    for &(x, y, on_curve) in points.iter() {
+        // ON_CURVE_POINT
+        let mut flag = if on_curve { 0x01 } else { 0x00 };
+        flag |= encode(x - last_x, 0x02, 0x10, &mut x_data);
+        flag |= encode(y - last_y, 0x04, 0x20, &mut y_data);
+        last_x = x;
+        last_y = y;
+        flags.push(flag);
+    }
+    if simple_glyph.overlap {
+        // OVERLAP_SIMPLE
+        flags[0] |= 0x40;
+    }
+
+    // REPEAT_FLAG
+    let mut i = 0;
+    while i < flags.len() {
+        let flag = flags[i];
+        let repeat = flags[i + 1..]
+            .iter()
+            .take(0xff)
+            .take_while(|&&x| x == flag)
+            .count();
+        if repeat > 0 {
+            glyph.extend_from_slice(&[flag | 0x08, repeat as u8]);
+        } else {
+            glyph.push(flag);
+        }
+        i += 1 + repeat;
+    }
+    glyph.extend(x_data);
+    glyph.extend(y_data);
+    Ok(glyph)
+}
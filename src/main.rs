@@ -17,16 +17,31 @@
 extern crate anyhow;
 
 mod arg_parser;
+mod autohint;
+mod bitmap;
+mod cff;
 mod checksum;
+mod cmap;
+mod device_metrics;
+mod embolden;
+mod glyf;
+mod maxp;
 mod modify;
+mod name;
+mod post;
+mod raster;
 mod ttc_reader;
 mod ttc_writer;
 mod types;
+mod variation;
 
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::process;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::Result;
 
@@ -45,18 +60,215 @@ fn main() -> Result<()> {
         let mut input_file = BufReader::new(File::open(args.input_filename)?);
         TTCReader::new(&mut input_file).read_ttc()?
     };
+    modify::fix_glyph_count(&mut ttc);
+    modify::check_maxp(&ttc);
 
+    if let Some(selection) = &args.instances {
+        eprintln!("[ INFO ] Instancing variable fonts.");
+        modify::instantiate_variable_font(&mut ttc, selection);
+    }
+    if args.cff_to_glyf {
+        eprintln!("[ INFO ] Converting CFF outlines to TrueType.");
+        modify::convert_cff_to_glyf(&mut ttc, args.cff_to_glyf_tolerance);
+    }
+    if let Some(strength) = args.embolden {
+        eprintln!("[ INFO ] Emboldening outlines.");
+        modify::embolden_outlines(&mut ttc, strength);
+    }
     if !args.keep_bitmap {
-        eprintln!("[ INFO ] Removing embedded bitmap.");
-        modify::remove_bitmap(&mut ttc);
+        let num_bitmap_only =
+            modify::check_bitmap_only_glyphs(&ttc, args.bitmap_ppem_range.as_ref());
+        if num_bitmap_only != 0 {
+            if args.force {
+                eprintln!(
+                    "[ WARN ] Removing embedded bitmap anyway, {} characters will have no glyph.",
+                    num_bitmap_only
+                );
+            } else {
+                eprintln!(
+                    "[ FAIL ] Removing embedded bitmap would leave {} characters with no glyph, use “--force” to proceed, or “--keep-bitmap” to keep it.",
+                    num_bitmap_only
+                );
+                process::exit(1);
+            }
+        }
+        if let Some(ppem_range) = &args.bitmap_ppem_range {
+            eprintln!(
+                "[ INFO ] Removing embedded bitmap outside {}–{} ppem.",
+                ppem_range.start(),
+                ppem_range.end()
+            );
+            modify::filter_bitmap(&mut ttc, ppem_range);
+        } else {
+            eprintln!("[ INFO ] Removing embedded bitmap.");
+            modify::remove_bitmap(&mut ttc);
+        }
+    }
+    if let Some(ppems) = &args.grayscale_bitmap_ppems {
+        eprintln!(
+            "[ INFO ] Generating {}-bit grayscale bitmap.",
+            args.grayscale_bitmap_depth
+        );
+        modify::generate_grayscale_bitmap(&mut ttc, ppems, args.grayscale_bitmap_depth);
+    }
+    if args.remove_color_bitmap {
+        eprintln!("[ INFO ] Removing color bitmap.");
+        modify::remove_color_bitmap(&mut ttc);
     }
-    if !args.keep_hinting {
+    // Stem darkening replaces the hinting instructions of every glyph
+    let stem_darkening_ppem_range = match &args.stem_darkening_ppem_range {
+        Some(_) if args.keep_hinting || args.hybrid_hinting_ppem.is_some() => {
+            eprintln!("[ WARN ] Stem darkening needs hinting instructions removed, skipping.");
+            None
+        }
+        Some(_) if args.hinting_filter.is_some() => {
+            eprintln!("[ WARN ] Stem darkening cannot be used with hinting filters, skipping.");
+            None
+        }
+        Some(_) if args.autohint => {
+            eprintln!("[ WARN ] Stem darkening cannot be used with “--autohint”, skipping.");
+            None
+        }
+        ppem_range => ppem_range.clone(),
+    };
+    if let Some(threshold_ppem) = args.hybrid_hinting_ppem {
+        if args.hinting_filter.is_some() {
+            eprintln!(
+                "[ WARN ] Hinting filters cannot be used with “--hybrid-hinting”, ignoring them."
+            );
+        }
+        eprintln!(
+            "[ INFO ] Disabling hinting instructions above {} ppem.",
+            threshold_ppem
+        );
+        modify::hybrid_hinting(&mut ttc, threshold_ppem);
+        if args.remove_hdmx {
+            eprintln!("[ INFO ] Removing “hdmx” table.");
+            modify::remove_hdmx(&mut ttc);
+        } else {
+            modify::truncate_hdmx(&mut ttc, threshold_ppem);
+        }
+    } else if !args.keep_hinting {
         eprintln!("[ INFO ] Removing hinting instructions.");
-        modify::remove_hinting(&mut ttc);
+        modify::remove_hinting(&mut ttc, args.hinting_filter.as_ref());
+        if args.autohint {
+            if args.hinting_filter.is_some() {
+                eprintln!("[ WARN ] Autohinting cannot be used with hinting filters, skipping.");
+            } else {
+                eprintln!("[ INFO ] Generating hinting instructions.");
+                modify::autohint(&mut ttc);
+            }
+        }
+        if let Some(ppem_range) = &stem_darkening_ppem_range {
+            eprintln!(
+                "[ INFO ] Adding stem darkening instructions, fading out from {} to {} ppem.",
+                ppem_range.start(),
+                ppem_range.end()
+            );
+            modify::stem_darkening(&mut ttc, ppem_range, args.stem_darkening_pixels);
+        }
+        if args.hinting_filter.is_none() {
+            if args.remove_hdmx {
+                eprintln!("[ INFO ] Removing “hdmx” table.");
+                modify::remove_hdmx(&mut ttc);
+            } else {
+                modify::regenerate_hdmx(&mut ttc, args.hdmx_ppems.as_deref());
+            }
+            if args.remove_vdmx {
+                eprintln!("[ INFO ] Removing “VDMX” table.");
+                modify::remove_vdmx(&mut ttc);
+            } else {
+                modify::regenerate_vdmx(&mut ttc);
+            }
+            if args.remove_ltsh {
+                eprintln!("[ INFO ] Removing “LTSH” table.");
+                modify::remove_ltsh(&mut ttc);
+            } else {
+                modify::regenerate_ltsh(&mut ttc);
+            }
+        }
     }
     if !args.keep_gasp {
-        eprintln!("[ INFO ] Regenerating “gasp” table.");
-        modify::regenerate_gasp(&mut ttc);
+        if let Some(threshold_ppem) = args.merge_gasp_ppem {
+            eprintln!("[ INFO ] Merging “gasp” table.");
+            modify::merge_gasp(&mut ttc, threshold_ppem);
+        } else {
+            eprintln!("[ INFO ] Regenerating “gasp” table.");
+            let gasp_ranges = match (
+                args.gasp_ranges,
+                args.hybrid_hinting_ppem,
+                &stem_darkening_ppem_range,
+            ) {
+                (Some(gasp_ranges), _, _) => gasp_ranges,
+                (None, Some(threshold_ppem), _) => modify::hybrid_gasp_ranges(threshold_ppem),
+                (None, None, Some(ppem_range)) => {
+                    modify::stem_darkening_gasp_ranges(*ppem_range.end())
+                }
+                (None, None, None)
+                    if (args.autohint || args.hinting_filter.is_some()) && !args.keep_hinting =>
+                {
+                    modify::PARTIAL_HINTING_GASP_RANGES.to_vec()
+                }
+                (None, None, None) => modify::DEFAULT_GASP_RANGES.to_vec(),
+            };
+            modify::regenerate_gasp(&mut ttc, &gasp_ranges);
+        }
+    }
+    modify::fix_bounding_boxes(&mut ttc);
+    if let Some(min_ppem) = args.vertical_metrics_ppem {
+        eprintln!("[ INFO ] Fixing vertical metrics from {} ppem.", min_ppem);
+        modify::fix_vertical_metrics(&mut ttc, min_ppem, args.vertical_metrics_cmap_only);
+    }
+    if !args.keep_maxp {
+        eprintln!("[ INFO ] Recalculating “maxp” limits.");
+        modify::recalculate_maxp(&mut ttc);
+    }
+    if let Some(suffix) = &args.rename_suffix {
+        eprintln!("[ INFO ] Renaming fonts with suffix “{}”.", suffix);
+        modify::rename_fonts(&mut ttc, suffix);
+    }
+    if args.provenance {
+        // Long option lists, e.g. of glyphs, are cut short to keep the
+        // version string readable
+        const MAX_MARKER_CHARS: usize = 100;
+        let mut marker = format!("FaithType {}", env!("CARGO_PKG_VERSION"));
+        for option in args.options.iter() {
+            let room = MAX_MARKER_CHARS.saturating_sub(marker.chars().count() + 1);
+            marker.push(' ');
+            if option.chars().count() > room {
+                marker.extend(option.chars().take(room.saturating_sub(1)));
+                marker.push('…');
+                break;
+            }
+            marker.push_str(option);
+        }
+        // SOURCE_DATE_EPOCH gives reproducible output
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() as i64);
+        let timestamp = match env::var("SOURCE_DATE_EPOCH") {
+            Ok(x) => x
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|x| *x >= 0 && x.checked_add(modify::HEAD_EPOCH_OFFSET).is_some())
+                .unwrap_or_else(|| {
+                    eprintln!(
+                        "[ WARN ] Invalid SOURCE_DATE_EPOCH “{}”, using the current time.",
+                        x
+                    );
+                    now
+                }),
+            Err(_) => now,
+        };
+        eprintln!("[ INFO ] Adding “{}” to the version string.", marker);
+        modify::stamp_provenance(
+            &mut ttc,
+            &marker,
+            timestamp,
+            args.stamp_description,
+            args.bump_revision,
+        );
     }
     modify::remove_dsig(&mut ttc);
     modify::patch_head(&mut ttc);
@@ -0,0 +1,580 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::glyf;
+use super::types::SfntHeader;
+
+// Same limit as glyf::glyph_outline
+const MAX_COMPONENT_DEPTH: u16 = 16;
+
+// A "maxp" version 1.0 field that can be derived from the font data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxpField {
+    pub name: &'static str,
+    pub pos: usize,
+    pub value: u16,
+}
+
+// Returns the fields of "maxp" that can be calculated from "glyf", "fpgm"
+// and "prep". Limits that depend on running instructions with control flow
+// or function calls cannot be known, and are left out.
+pub fn calculate_maxp(sfnt_index: usize, sfnt: &SfntHeader) -> Result<Vec<MaxpField>> {
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+    let mut fields = Vec::new();
+    let mut push = |name: &'static str, pos: usize, value: usize| -> Result<()> {
+        let value = u16::try_from(value).map_err(|_| {
+            anyhow!(
+                "sfnt {} table “maxp”: {} exceeds 65535 ({})",
+                sfnt_index,
+                name,
+                value
+            )
+        })?;
+        fields.push(MaxpField { name, pos, value });
+        Ok(())
+    };
+
+    // Outline limits
+    let mut sizes = vec![None; glyphs.len()];
+    let mut max_points = 0;
+    let mut max_contours = 0;
+    let mut max_composite_points = 0;
+    let mut max_composite_contours = 0;
+    let mut max_component_elements = 0;
+    let mut max_component_depth = 0;
+    for (glyph_index, glyph) in glyphs.iter().enumerate() {
+        let size = glyph_size(sfnt_index, glyph_index, &glyphs, &mut sizes, 0)?;
+        if is_composite(glyph) {
+            max_composite_points = max_composite_points.max(size.points);
+            max_composite_contours = max_composite_contours.max(size.contours);
+            max_component_elements = max_component_elements
+                .max(glyf::glyph_components(sfnt_index, glyph_index, glyph)?.len());
+            max_component_depth = max_component_depth.max(size.depth);
+        } else {
+            max_points = max_points.max(size.points);
+            max_contours = max_contours.max(size.contours);
+        }
+    }
+    // byte 6..8: maxPoints
+    // byte 8..10: maxContours
+    // byte 10..12: maxCompositePoints
+    // byte 12..14: maxCompositeContours
+    // byte 28..30: maxComponentElements
+    // byte 30..32: maxComponentDepth
+    push("maxPoints", 6, max_points)?;
+    push("maxContours", 8, max_contours)?;
+    push("maxCompositePoints", 10, max_composite_points)?;
+    push("maxCompositeContours", 12, max_composite_contours)?;
+    push("maxComponentElements", 28, max_component_elements)?;
+    push("maxComponentDepth", 30, usize::from(max_component_depth))?;
+
+    // Instruction limits
+    let raw_table = |table_tag: [u8; 4]| {
+        sfnt.table_records
+            .get(&table_tag.into())
+            .map_or(&[][..], |x| x.raw_data.as_ref())
+    };
+    let mut programs = vec![
+        analyze_program(raw_table(*b"fpgm")),
+        analyze_program(raw_table(*b"prep")),
+    ];
+    let mut max_size_of_instructions = 0;
+    for (glyph_index, glyph) in glyphs.iter().enumerate() {
+        let instructions = glyph_instructions(sfnt_index, glyph_index, glyph)?;
+        max_size_of_instructions = max_size_of_instructions.max(instructions.len());
+        programs.push(analyze_program(instructions));
+    }
+    // byte 20..22: maxFunctionDefs
+    // byte 22..24: maxInstructionDefs
+    // byte 26..28: maxSizeOfInstructions
+    if let Some(function_defs) = programs
+        .iter()
+        .map(|x| x.function_defs)
+        .try_fold(0, |acc, x| x.map(|x| acc.max(x)))
+    {
+        push("maxFunctionDefs", 20, function_defs)?;
+    }
+    push(
+        "maxInstructionDefs",
+        22,
+        programs.iter().map(|x| x.instruction_defs).sum(),
+    )?;
+    push("maxSizeOfInstructions", 26, max_size_of_instructions)?;
+
+    // byte 14..16: maxZones
+    // byte 16..18: maxTwilightPoints
+    // byte 18..20: maxStorage
+    // byte 24..26: maxStackElements
+    if programs.iter().all(|x| x.straight_line) {
+        if programs.iter().all(|x| !x.uses_twilight_zone) {
+            push("maxZones", 14, 1)?;
+            push("maxTwilightPoints", 16, 0)?;
+        }
+        if let Some(max_storage) = programs
+            .iter()
+            .map(|x| x.max_storage)
+            .try_fold(0, |acc, x| x.map(|x| acc.max(x)))
+        {
+            push("maxStorage", 18, max_storage)?;
+        }
+        push(
+            "maxStackElements",
+            24,
+            programs
+                .iter()
+                .map(|x| x.max_stack_elements)
+                .max()
+                .unwrap_or(0),
+        )?;
+    }
+    Ok(fields)
+}
+
+fn is_composite(glyph: &[u8]) -> bool {
+    glyph.len() >= 2 && i16::from_be_bytes([glyph[0], glyph[1]]) < 0
+}
+
+#[derive(Clone, Copy)]
+struct GlyphSize {
+    points: usize,
+    contours: usize,
+    // Levels of composite glyphs, 0 for simple glyphs
+    depth: u16,
+}
+
+// Counts the points and contours of a glyph, including all its components.
+fn glyph_size(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyphs: &[&[u8]],
+    sizes: &mut [Option<GlyphSize>],
+    depth: u16,
+) -> Result<GlyphSize> {
+    if let Some(size) = sizes.get(glyph_index).copied().flatten() {
+        return Ok(size);
+    }
+    if depth > MAX_COMPONENT_DEPTH {
+        bail!(
+            "sfnt {} table “glyf”: glyph {} has components nested too deep",
+            sfnt_index,
+            glyph_index
+        );
+    }
+    let glyph = glyphs.get(glyph_index).copied().unwrap_or_default();
+    let size = if glyph.len() < 10 {
+        GlyphSize {
+            points: 0,
+            contours: 0,
+            depth: 0,
+        }
+    } else if is_composite(glyph) {
+        let mut size = GlyphSize {
+            points: 0,
+            contours: 0,
+            depth: 0,
+        };
+        for component in glyf::glyph_components(sfnt_index, glyph_index, glyph)? {
+            let component_size =
+                glyph_size(sfnt_index, usize::from(component), glyphs, sizes, depth + 1)?;
+            size.points += component_size.points;
+            size.contours += component_size.contours;
+            size.depth = size.depth.max(component_size.depth);
+        }
+        size.depth += 1;
+        size
+    } else {
+        // byte 0..2: numberOfContours
+        // byte 10..: endPtsOfContours
+        let contours = usize::from(u16::from_be_bytes([glyph[0], glyph[1]]));
+        let points = match contours.checked_sub(1) {
+            Some(last) => glyph
+                .get(10 + last * 2..12 + last * 2)
+                .map(|x| usize::from(u16::from_be_bytes([x[0], x[1]])) + 1)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                        sfnt_index,
+                        glyph_index,
+                        10 + last * 2,
+                        12 + last * 2
+                    )
+                })?,
+            None => 0,
+        };
+        GlyphSize {
+            points,
+            contours,
+            depth: 0,
+        }
+    };
+    if let Some(x) = sizes.get_mut(glyph_index) {
+        *x = Some(size);
+    }
+    Ok(size)
+}
+
+// Returns the instructions of a glyph.
+fn glyph_instructions<'a>(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyph: &'a [u8],
+) -> Result<&'a [u8]> {
+    let truncated = |from: usize, to: usize| {
+        anyhow!(
+            "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+            sfnt_index,
+            glyph_index,
+            from,
+            to
+        )
+    };
+    let instructions = |pos: usize| -> Result<&'a [u8]> {
+        let len = glyph
+            .get(pos..pos + 2)
+            .map(|x| usize::from(u16::from_be_bytes([x[0], x[1]])))
+            .ok_or_else(|| truncated(pos, pos + 2))?;
+        glyph
+            .get(pos + 2..pos + 2 + len)
+            .ok_or_else(|| truncated(pos + 2, pos + 2 + len))
+    };
+    if glyph.len() < 10 {
+        return Ok(&[]);
+    }
+    if !is_composite(glyph) {
+        let contours = usize::from(u16::from_be_bytes([glyph[0], glyph[1]]));
+        return instructions(10 + contours * 2);
+    }
+
+    let mut pos = 10;
+    loop {
+        let flags = glyph
+            .get(pos..pos + 2)
+            .map(|x| u16::from_be_bytes([x[0], x[1]]))
+            .ok_or_else(|| truncated(pos, pos + 2))?;
+        pos += 4;
+        // ARG_1_AND_2_ARE_WORDS
+        pos += if flags & 0x0001 != 0 { 4 } else { 2 };
+        // WE_HAVE_A_SCALE, WE_HAVE_AN_X_AND_Y_SCALE, WE_HAVE_A_TWO_BY_TWO
+        pos += if flags & 0x0008 != 0 {
+            2
+        } else if flags & 0x0040 != 0 {
+            4
+        } else if flags & 0x0080 != 0 {
+            8
+        } else {
+            0
+        };
+        // MORE_COMPONENTS
+        if flags & 0x0020 == 0 {
+            // WE_HAVE_INSTRUCTIONS
+            return if flags & 0x0100 != 0 {
+                instructions(pos)
+            } else {
+                Ok(&[])
+            };
+        }
+    }
+}
+
+// What is known about a program without running it
+struct ProgramLimits {
+    // No branches, jumps or function calls, so the limits below are exact
+    straight_line: bool,
+    max_stack_elements: usize,
+    // None if a storage location is not a constant
+    max_storage: Option<usize>,
+    uses_twilight_zone: bool,
+    // Highest function number plus one, None if not a constant
+    function_defs: Option<usize>,
+    instruction_defs: usize,
+}
+
+// Follows a program, keeping track of the stack as far as the values are
+// pushed as constants. The bodies of functions and instruction definitions
+// only run when called, and are treated as branches.
+fn analyze_program(code: &[u8]) -> ProgramLimits {
+    let mut limits = ProgramLimits {
+        straight_line: true,
+        max_stack_elements: 0,
+        max_storage: Some(0),
+        uses_twilight_zone: false,
+        function_defs: Some(0),
+        instruction_defs: 0,
+    };
+    // Constant values, or None if computed
+    let mut stack = Vec::<Option<i32>>::new();
+    let mut loop_count = Some(1);
+    let mut pos = 0;
+    while let Some(&opcode) = code.get(pos) {
+        pos += 1;
+        match opcode {
+            // NPUSHB, NPUSHW, PUSHB, PUSHW
+            0x40 | 0x41 | 0xb0..=0xbf => {
+                let (count, word) = match opcode {
+                    0x40 | 0x41 => match code.get(pos) {
+                        Some(&n) => {
+                            pos += 1;
+                            (usize::from(n), opcode == 0x41)
+                        }
+                        None => break,
+                    },
+                    _ => (usize::from(opcode & 0x07) + 1, opcode >= 0xb8),
+                };
+                let size = if word { 2 } else { 1 };
+                let data = match code.get(pos..pos + count * size) {
+                    Some(data) => data,
+                    None => break,
+                };
+                stack.extend(data.chunks_exact(size).map(|x| {
+                    Some(if word {
+                        i32::from(i16::from_be_bytes([x[0], x[1]]))
+                    } else {
+                        i32::from(x[0])
+                    })
+                }));
+                pos += count * size;
+            }
+            // FDEF, IDEF
+            0x2c | 0x89 => {
+                let number = stack.pop().flatten();
+                if opcode == 0x2c {
+                    limits.function_defs = match (limits.function_defs, number) {
+                        (Some(defs), Some(number)) if number >= 0 => {
+                            Some(defs.max(number as usize + 1))
+                        }
+                        _ => None,
+                    };
+                } else {
+                    limits.instruction_defs += 1;
+                }
+                limits.straight_line = false;
+                // Skip the body up to ENDF
+                while let Some(&opcode) = code.get(pos) {
+                    pos += 1 + push_data_len(opcode, code.get(pos + 1).copied());
+                    if opcode == 0x2d {
+                        break;
+                    }
+                }
+                continue;
+            }
+            // SLOOP
+            0x17 => loop_count = stack.pop().flatten(),
+            // WS
+            0x42 => {
+                let _value = stack.pop();
+                note_storage(&mut limits, stack.pop().flatten());
+            }
+            // RS
+            0x43 => {
+                note_storage(&mut limits, stack.pop().flatten());
+                stack.push(None);
+            }
+            // SZP0, SZP1, SZP2, SZPS
+            0x13..=0x16 => {
+                if stack.pop().flatten() != Some(1) {
+                    limits.uses_twilight_zone = true;
+                }
+            }
+            // DUP
+            0x20 => {
+                let value = stack.last().copied().flatten();
+                stack.push(value);
+            }
+            // CLEAR
+            0x22 => stack.clear(),
+            // SWAP
+            0x23 => {
+                let len = stack.len();
+                if len >= 2 {
+                    stack.swap(len - 1, len - 2);
+                }
+            }
+            // DEPTH
+            0x24 => stack.push(i32::try_from(stack.len()).ok()),
+            // ROLL
+            0x8a => {
+                let len = stack.len();
+                if len >= 3 {
+                    stack[len - 3..].rotate_left(1);
+                }
+            }
+            // CINDEX, MINDEX, and the DELTA instructions with a variable
+            // number of arguments
+            0x25 | 0x26 | 0x5d | 0x71..=0x75 => {
+                let n = stack.pop().flatten();
+                match (opcode, n) {
+                    (0x25, _) => stack.push(None),
+                    (0x26, Some(n)) if n >= 1 && n as usize <= stack.len() => {
+                        let value = stack.remove(stack.len() - n as usize);
+                        stack.push(value);
+                    }
+                    (0x5d | 0x71..=0x75, Some(n)) if n >= 0 => {
+                        stack.truncate(stack.len().saturating_sub(n as usize * 2));
+                    }
+                    _ => limits.straight_line = false,
+                }
+            }
+            // SHP, SHPIX, IP, ALIGNRP, FLIPPT use the loop counter
+            0x32 | 0x33 | 0x38 | 0x39 | 0x3c | 0x80 => {
+                if opcode == 0x38 {
+                    stack.pop();
+                }
+                match loop_count {
+                    Some(n) if n >= 0 => stack.truncate(stack.len().saturating_sub(n as usize)),
+                    _ => limits.straight_line = false,
+                }
+                loop_count = Some(1);
+            }
+            _ => match stack_effect(opcode) {
+                Some((pops, pushes)) => {
+                    stack.truncate(stack.len().saturating_sub(pops));
+                    stack.extend(std::iter::repeat_n(None, pushes));
+                }
+                // Branches, jumps and calls
+                None => limits.straight_line = false,
+            },
+        }
+        limits.max_stack_elements = limits.max_stack_elements.max(stack.len());
+    }
+    limits
+}
+
+fn note_storage(limits: &mut ProgramLimits, location: Option<i32>) {
+    limits.max_storage = match (limits.max_storage, location) {
+        (Some(max_storage), Some(location)) if location >= 0 => {
+            Some(max_storage.max(location as usize + 1))
+        }
+        _ => None,
+    };
+}
+
+// Bytes of data following a push instruction.
+fn push_data_len(opcode: u8, next: Option<u8>) -> usize {
+    match opcode {
+        0x40 => 1 + usize::from(next.unwrap_or(0)),
+        0x41 => 1 + usize::from(next.unwrap_or(0)) * 2,
+        0xb0..=0xb7 => usize::from(opcode - 0xb0) + 1,
+        0xb8..=0xbf => (usize::from(opcode - 0xb8) + 1) * 2,
+        _ => 0,
+    }
+}
+
+// Values popped and pushed by instructions with a fixed number of arguments,
+// None for control flow.
+fn stack_effect(opcode: u8) -> Option<(usize, usize)> {
+    Some(match opcode {
+        // SVTCA, SPVTCA, SFVTCA
+        0x00..=0x05 => (0, 0),
+        // SPVTL, SFVTL, SPVFS, SFVFS
+        0x06..=0x0b => (2, 0),
+        // GPV, GFV
+        0x0c | 0x0d => (0, 2),
+        // SFVTPV
+        0x0e => (0, 0),
+        // ISECT
+        0x0f => (5, 0),
+        // SRP0, SRP1, SRP2
+        0x10..=0x12 => (1, 0),
+        // RTG, RTHG
+        0x18 | 0x19 => (0, 0),
+        // SMD
+        0x1a => (1, 0),
+        // SCVTCI, SSWCI, SSW
+        0x1d..=0x1f => (1, 0),
+        // POP
+        0x21 => (1, 0),
+        // ALIGNPTS
+        0x27 => (2, 0),
+        // UTP
+        0x29 => (1, 0),
+        // MDAP
+        0x2e | 0x2f => (1, 0),
+        // IUP
+        0x30 | 0x31 => (0, 0),
+        // SHC, SHZ
+        0x34..=0x37 => (1, 0),
+        // MSIRP
+        0x3a | 0x3b => (2, 0),
+        // RTDG
+        0x3d => (0, 0),
+        // MIAP
+        0x3e | 0x3f => (2, 0),
+        // WCVTP
+        0x44 => (2, 0),
+        // RCVT, GC
+        0x45..=0x47 => (1, 1),
+        // SCFS
+        0x48 => (2, 0),
+        // MD
+        0x49 | 0x4a => (2, 1),
+        // MPPEM, MPS
+        0x4b | 0x4c => (0, 1),
+        // FLIPON, FLIPOFF
+        0x4d | 0x4e => (0, 0),
+        // DEBUG
+        0x4f => (1, 0),
+        // LT, LTEQ, GT, GTEQ, EQ, NEQ
+        0x50..=0x55 => (2, 1),
+        // ODD, EVEN
+        0x56 | 0x57 => (1, 1),
+        // AND, OR
+        0x5a | 0x5b => (2, 1),
+        // NOT
+        0x5c => (1, 1),
+        // SDB, SDS
+        0x5e | 0x5f => (1, 0),
+        // ADD, SUB, DIV, MUL
+        0x60..=0x63 => (2, 1),
+        // ABS, NEG, FLOOR, CEILING, ROUND, NROUND
+        0x64..=0x6f => (1, 1),
+        // WCVTF
+        0x70 => (2, 0),
+        // SROUND, S45ROUND
+        0x76 | 0x77 => (1, 0),
+        // ROFF, RUTG, RDTG
+        0x7a | 0x7c | 0x7d => (0, 0),
+        // SANGW, AA
+        0x7e | 0x7f => (1, 0),
+        // FLIPRGON, FLIPRGOFF
+        0x81 | 0x82 => (2, 0),
+        // SCANCTRL
+        0x85 => (1, 0),
+        // SDPVTL
+        0x86 | 0x87 => (2, 0),
+        // GETINFO
+        0x88 => (1, 1),
+        // MAX, MIN
+        0x8b | 0x8c => (2, 1),
+        // SCANTYPE
+        0x8d => (1, 0),
+        // INSTCTRL
+        0x8e => (2, 0),
+        // MDRP
+        0xc0..=0xdf => (1, 0),
+        // MIRP
+        0xe0..=0xff => (2, 0),
+        // ELSE, JMPR, LOOPCALL, CALL, ENDF, IF, EIF, JROT, JROF,
+        // GETVARIATION, and undefined instructions
+        _ => return None,
+    })
+}
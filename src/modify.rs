@@ -15,16 +15,43 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
 use anyhow::anyhow;
 use anyhow::bail;
+use anyhow::Result;
 
+use super::autohint;
+use super::bitmap;
+use super::cff;
+use super::cmap;
+use super::device_metrics;
+use super::embolden;
+use super::glyf;
+use super::maxp;
+use super::name;
+use super::name::NameTable;
+use super::post;
+use super::raster;
+use super::types::EmboldenStrength;
 use super::types::FourCC;
+use super::types::GaspRange;
+use super::types::GlyphSpec;
+use super::types::HintingFilter;
+use super::types::InstanceSelection;
+use super::types::SfntHeader;
 use super::types::TTCHeader;
 use super::types::TableRecord;
+use super::types::GASP_DOGRAY;
+use super::types::GASP_GRIDFIT;
+use super::types::GASP_SYMMETRIC_GRIDFIT;
+use super::types::GASP_SYMMETRIC_SMOOTHING;
+use super::variation;
 
 pub fn remove_dsig(ttc: &mut TTCHeader) {
     const PATCHED_DSIG: [u8; 8] = [
@@ -59,8 +86,570 @@ pub fn remove_dsig(ttc: &mut TTCHeader) {
     }
 }
 
+// Replaces variable fonts with static instances, either all the named
+// instances or the given locations. Several instances of one font are written
+// as a TTC.
+pub fn instantiate_variable_font(ttc: &mut TTCHeader, selection: &InstanceSelection) {
+    let mut table_directories = Vec::with_capacity(ttc.table_directories.len());
+    for (sfnt_index, sfnt) in ttc.table_directories.iter().enumerate() {
+        let raw_fvar = match sfnt.table_records.get(&b"fvar".into()) {
+            Some(fvar) => &fvar.raw_data,
+            None => {
+                table_directories.push(sfnt.clone());
+                continue;
+            }
+        };
+        if sfnt.table_records.contains_key(&b"CFF2".into()) {
+            eprintln!(
+                "[ WARN ] sfnt {} table “CFF2”: instancing variable CFF outlines is not supported, keeping the variable font.",
+                sfnt_index
+            );
+            table_directories.push(sfnt.clone());
+            continue;
+        }
+        let fvar = match variation::read_fvar(sfnt_index, raw_fvar) {
+            Ok(fvar) => fvar,
+            Err(e) => {
+                eprintln!("[ WARN ] {}", e);
+                table_directories.push(sfnt.clone());
+                continue;
+            }
+        };
+        let locations = match selection {
+            InstanceSelection::Named => fvar
+                .instances
+                .iter()
+                .map(|x| x.coordinates.clone())
+                .collect::<Vec<_>>(),
+            InstanceSelection::Locations(locations) => locations
+                .iter()
+                .map(|x| variation::user_location(sfnt_index, &fvar, x))
+                .collect(),
+        };
+        if locations.is_empty() {
+            eprintln!(
+                "[ WARN ] sfnt {} table “fvar”: no named instances, keeping the variable font.",
+                sfnt_index
+            );
+            table_directories.push(sfnt.clone());
+            continue;
+        }
+
+        let num_table_directories = table_directories.len();
+        for location in locations {
+            eprintln!(
+                "[ INFO ] sfnt {}: creating instance {}.",
+                sfnt_index,
+                variation::format_location(&fvar, &location)
+            );
+            match variation::instantiate(sfnt_index, sfnt, &fvar, &location) {
+                Ok(instance) => table_directories.push(instance),
+                Err(e) => eprintln!("[ WARN ] {}", e),
+            }
+        }
+        if table_directories.len() == num_table_directories {
+            eprintln!(
+                "[ WARN ] sfnt {}: no instance created, keeping the variable font.",
+                sfnt_index
+            );
+            table_directories.push(sfnt.clone());
+        }
+    }
+    ttc.table_directories = table_directories;
+}
+
+// Faces in a TTC often point to the same table data, which TTCReader has
+// already merged into a single Rc. Groups the faces by the identity of their
+// input tables, given by key, so that each unique set of tables is only parsed
+// and rebuilt once. Faces without a key are left out.
+fn group_sfnts<K: PartialEq>(
+    ttc: &TTCHeader,
+    mut key: impl FnMut(usize, &SfntHeader) -> Option<K>,
+) -> Vec<(K, Vec<usize>)> {
+    let mut groups = Vec::<(K, Vec<usize>)>::new();
+    for (sfnt_index, sfnt) in ttc.table_directories.iter().enumerate() {
+        let key = match key(sfnt_index, sfnt) {
+            Some(key) => key,
+            None => continue,
+        };
+        match groups.iter_mut().find(|(x, _)| *x == key) {
+            Some((_, sfnt_indices)) => sfnt_indices.push(sfnt_index),
+            None => groups.push((key, vec![sfnt_index])),
+        }
+    }
+    groups
+}
+
+// The addresses of the table data, for `group_sfnts`
+fn table_identity<const N: usize>(
+    sfnt: &SfntHeader,
+    table_tags: [&'static [u8; 4]; N],
+) -> [Option<*const u8>; N] {
+    table_tags.map(|tag| {
+        sfnt.table_records
+            .get(&tag.into())
+            .map(|x| Rc::as_ptr(&x.raw_data) as *const u8)
+    })
+}
+
+fn format_sfnt_indices(sfnt_indices: &[usize]) -> String {
+    sfnt_indices
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Reads a u16 value from a table, for `group_sfnts` keys.
+fn peek_u16(sfnt: &SfntHeader, table_tag: &'static [u8; 4], pos: usize) -> Option<u16> {
+    sfnt.table_records
+        .get(&table_tag.into())
+        .and_then(|x| x.raw_data.get(pos..pos + 2))
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+}
+
+// Reads unitsPerEm from "head".
+fn read_units_per_em(sfnt_index: usize, sfnt: &SfntHeader) -> Result<u16> {
+    // "head" table:
+    // byte 18..20: unitsPerEm
+    let units_per_em = sfnt
+        .table_records
+        .get(&b"head".into())
+        .and_then(|x| x.raw_data.get(18..20))
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .ok_or_else(|| anyhow!("sfnt {} table “head”: data truncated (18..20)", sfnt_index))?;
+    if units_per_em == 0 {
+        bail!("sfnt {} table “head”: unitsPerEm is zero", sfnt_index);
+    }
+    Ok(units_per_em)
+}
+
+// Transforms of "glyf" outlines skip fonts with CFF outlines, suggesting to
+// convert them first.
+fn has_glyf_outlines(sfnt_index: usize, sfnt: &SfntHeader, action: &str) -> bool {
+    if sfnt.table_records.contains_key(&b"glyf".into()) {
+        return true;
+    }
+    for table_tag in [b"CFF ", b"CFF2"] {
+        if sfnt.table_records.contains_key(&table_tag.into()) {
+            eprintln!(
+                "[ WARN ] sfnt {} table {}: {} needs TrueType outlines, use “--cff-to-glyf” first.",
+                sfnt_index,
+                FourCC(*table_tag),
+                action
+            );
+        }
+    }
+    false
+}
+
+// Converts "CFF " outlines into "glyf", so that the font is rendered by the
+// TrueType rasterizer and "gasp" takes effect.
+pub fn convert_cff_to_glyf(ttc: &mut TTCHeader, tolerance: f32) {
+    // Faces in an OTC usually share the same outlines, only convert them once.
+    // "head", "hhea" and "maxp" differ between faces, only their values count:
+    // unitsPerEm, numberOfHMetrics and numGlyphs.
+    let groups = group_sfnts(ttc, |sfnt_index, sfnt| {
+        if sfnt.table_records.contains_key(&b"CFF2".into()) {
+            eprintln!(
+                "[ WARN ] sfnt {} table “CFF2”: converting variable outlines is not supported, keeping “CFF2”.",
+                sfnt_index
+            );
+            return None;
+        }
+        if !sfnt.table_records.contains_key(&b"CFF ".into()) {
+            return None;
+        }
+        Some((
+            table_identity(sfnt, [b"CFF ", b"hmtx", b"post"]),
+            peek_u16(sfnt, b"head", 18),
+            peek_u16(sfnt, b"hhea", 34),
+            peek_u16(sfnt, b"maxp", 4),
+        ))
+    });
+
+    for (_, sfnt_indices) in groups {
+        let result = match convert_cff(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+            tolerance,
+        ) {
+            Ok(result) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “CFF ”: converted to “glyf”.",
+                    format_sfnt_indices(&sfnt_indices)
+                );
+                result
+            }
+            Err(e) => {
+                eprintln!("[ WARN ] {}", e);
+                continue;
+            }
+        };
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            sfnt.table_records.remove(&b"CFF ".into());
+            // Vertical origins only apply to CFF outlines
+            sfnt.table_records.remove(&b"VORG".into());
+            glyf::set_glyf(
+                sfnt,
+                result.glyf.clone(),
+                result.loca.clone(),
+                result.loca_format,
+            );
+            let mut tables = vec![
+                (b"hmtx", result.hmtx.clone()),
+                (b"maxp", result.maxp.clone()),
+            ];
+            if let Some(post) = &result.post {
+                tables.push((b"post", post.clone()));
+            }
+            for (table_tag, raw_data) in tables {
+                sfnt.table_records.insert(
+                    table_tag.into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data,
+                    },
+                );
+            }
+            sfnt.sfnt_version = [0x00, 0x01, 0x00, 0x00].into();
+        }
+    }
+}
+
+// New tables replacing "CFF "
+struct ConvertedGlyf {
+    glyf: Rc<[u8]>,
+    loca: Rc<[u8]>,
+    loca_format: [u8; 2],
+    hmtx: Rc<[u8]>,
+    maxp: Rc<[u8]>,
+    post: Option<Rc<[u8]>>,
+}
+
+fn convert_cff(sfnt_index: usize, sfnt: &SfntHeader, tolerance: f32) -> Result<ConvertedGlyf> {
+    let raw_table = |table_tag: &'static [u8; 4]| {
+        sfnt.table_records
+            .get(&table_tag.into())
+            .map(|x| x.raw_data.as_ref())
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {}: table {} not found",
+                    sfnt_index,
+                    FourCC(*table_tag)
+                )
+            })
+    };
+    let read_u16 = |table_tag: &'static [u8; 4], pos: usize| -> Result<u16> {
+        raw_table(table_tag)?
+            .get(pos..pos + 2)
+            .map(|x| u16::from_be_bytes(x.try_into().unwrap()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table {}: data truncated ({}..{})",
+                    sfnt_index,
+                    FourCC(*table_tag),
+                    pos,
+                    pos + 2
+                )
+            })
+    };
+    // "head" table:
+    // byte 18..20: unitsPerEm
+    let units_per_em = read_u16(b"head", 18)?;
+    if units_per_em == 0 {
+        bail!("sfnt {} table “head”: unitsPerEm is zero", sfnt_index);
+    }
+    // "maxp" table:
+    // byte 4..6: numGlyphs
+    let num_glyphs = read_u16(b"maxp", 4)?;
+    // "hhea" table:
+    // byte 34..36: numberOfHMetrics
+    let num_h_metrics = usize::from(read_u16(b"hhea", 34)?);
+
+    let outlines = cff::read_cff_outlines(sfnt_index, raw_table(b"CFF ")?, units_per_em)?;
+    if outlines.glyphs.len() != usize::from(num_glyphs) {
+        bail!(
+            "sfnt {} table “CFF ”: {} glyphs, but “maxp” says {}",
+            sfnt_index,
+            outlines.glyphs.len(),
+            num_glyphs
+        );
+    }
+
+    let mut glyphs = Vec::with_capacity(outlines.glyphs.len());
+    let mut new_hmtx = raw_table(b"hmtx")?.to_vec();
+    let (mut max_points, mut max_contours) = (0, 0);
+    for (glyph_index, contours) in outlines.glyphs.iter().enumerate() {
+        let contours = contours
+            .iter()
+            .map(|x| glyf::cubic_to_quadratic(x, tolerance))
+            .collect::<Vec<_>>();
+        let glyph = glyf::build_simple_glyph(sfnt_index, glyph_index, &contours)?;
+        if glyph.len() >= 10 {
+            // Simple glyph:
+            // byte 0..2: numberOfContours
+            // byte 2..4: xMin
+            // byte 10..: endPtsOfContours
+            let num_contours = u16::from_be_bytes(glyph[0..2].try_into().unwrap());
+            let end_pt_pos = 10 + usize::from(num_contours) * 2;
+            let num_points =
+                u16::from_be_bytes(glyph[end_pt_pos - 2..end_pt_pos].try_into().unwrap()) + 1;
+            max_contours = max_contours.max(num_contours);
+            max_points = max_points.max(num_points);
+
+            // The left side bearing of TrueType glyphs must equal xMin
+            let lsb_pos = if glyph_index < num_h_metrics {
+                glyph_index * 4 + 2
+            } else {
+                num_h_metrics * 4 + (glyph_index - num_h_metrics) * 2
+            };
+            match new_hmtx.get_mut(lsb_pos..lsb_pos + 2) {
+                Some(x) => x.clone_from_slice(&glyph[2..4]),
+                None => bail!(
+                    "sfnt {} table “hmtx”: data truncated ({}..{})",
+                    sfnt_index,
+                    lsb_pos,
+                    lsb_pos + 2
+                ),
+            }
+        }
+        glyphs.push(glyph);
+    }
+    let (new_glyf, new_loca, loca_format) = glyf::build_glyf(sfnt_index, &glyphs)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: failed to build", sfnt_index))?;
+
+    // "maxp" version 1.0
+    let mut new_maxp = Vec::with_capacity(32);
+    new_maxp.extend_from_slice(&[0x00, 0x01, 0x00, 0x00]); // version
+    new_maxp.extend_from_slice(&num_glyphs.to_be_bytes());
+    new_maxp.extend_from_slice(&max_points.to_be_bytes());
+    new_maxp.extend_from_slice(&max_contours.to_be_bytes());
+    new_maxp.extend_from_slice(&[0, 0]); // maxCompositePoints
+    new_maxp.extend_from_slice(&[0, 0]); // maxCompositeContours
+    new_maxp.extend_from_slice(&[0, 1]); // maxZones, no twilight zone
+    new_maxp.resize(32, 0); // No instructions
+
+    // CFF fonts may use "post" version 3.0, taking glyph names from the charset.
+    // CID-keyed fonts have no glyph names, so they always get version 3.0.
+    let raw_post = sfnt
+        .table_records
+        .get(&b"post".into())
+        .map(|x| x.raw_data.as_ref());
+    let new_post = if outlines.cid_keyed {
+        Some(Rc::from(post::build_post_without_names(raw_post)))
+    } else if outlines.glyph_names.iter().any(|x| !x.is_empty()) {
+        Some(Rc::from(post::build_post(
+            sfnt_index,
+            raw_post,
+            &outlines.glyph_names,
+        )))
+    } else {
+        None
+    };
+
+    Ok(ConvertedGlyf {
+        glyf: Rc::from(new_glyf),
+        loca: Rc::from(new_loca),
+        loca_format,
+        hmtx: Rc::from(new_hmtx),
+        maxp: Rc::from(new_maxp),
+        post: new_post,
+    })
+}
+
+// Widens the strokes of "glyf" outlines, and the advance widths with them.
+pub fn embolden_outlines(ttc: &mut TTCHeader, strength: EmboldenStrength) {
+    let groups = group_sfnts(ttc, |sfnt_index, sfnt| {
+        has_glyf_outlines(sfnt_index, sfnt, "emboldening").then(|| {
+            (
+                glyf_identity(sfnt),
+                table_identity(sfnt, [b"hmtx"]),
+                peek_u16(sfnt, b"head", 18),
+                peek_u16(sfnt, b"hhea", 34),
+            )
+        })
+    });
+
+    for (_, sfnt_indices) in groups {
+        let tables = match embolden_sfnt(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+            strength,
+        ) {
+            Ok(tables) => tables,
+            Err(e) => {
+                eprintln!("[ WARN ] {}, skipping emboldening", e);
+                continue;
+            }
+        };
+        eprintln!(
+            "[ INFO ] sfnt {} table “glyf”: emboldening by {} units.",
+            format_sfnt_indices(&sfnt_indices),
+            tables.strength
+        );
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            let (glyf, loca, loca_format) = tables.glyf.clone();
+            glyf::set_glyf(sfnt, glyf, loca, loca_format);
+            sfnt.table_records.insert(
+                b"hmtx".into(),
+                TableRecord {
+                    checksum: 0,
+                    offset: 0,
+                    raw_data: tables.hmtx.clone(),
+                },
+            );
+            // "hhea" table:
+            // byte 10..12: advanceWidthMax
+            if let Some(hhea) = sfnt.table_records.get_mut(&b"hhea".into()) {
+                let mut new_hhea = hhea.raw_data.to_vec();
+                if let Some(x) = new_hhea.get_mut(10..12) {
+                    x.clone_from_slice(&tables.advance_width_max.to_be_bytes());
+                }
+                hhea.raw_data = Rc::from(new_hhea);
+            }
+            // "head" table:
+            // byte 36..44: xMin, yMin, xMax, yMax
+            if let (Some(head), Some(bbox)) =
+                (sfnt.table_records.get_mut(&b"head".into()), tables.bbox)
+            {
+                let mut new_head = head.raw_data.to_vec();
+                if let Some(x) = new_head.get_mut(36..44) {
+                    x.clone_from_slice(&bbox.map(i16::to_be_bytes).concat());
+                }
+                head.raw_data = Rc::from(new_head);
+            }
+            // "OS/2" table:
+            // byte 2..4: xAvgCharWidth, the average of non-zero advance widths
+            if let Some(os2) = sfnt.table_records.get_mut(&b"OS/2".into()) {
+                let mut new_os2 = os2.raw_data.to_vec();
+                if let Some(x) = new_os2.get_mut(2..4) {
+                    let average =
+                        i32::from(i16::from_be_bytes([x[0], x[1]])) + tables.advance_delta;
+                    x.clone_from_slice(&(average.clamp(0, i16::MAX.into()) as i16).to_be_bytes());
+                }
+                os2.raw_data = Rc::from(new_os2);
+            }
+        }
+    }
+}
+
+// New tables and metrics of an emboldened font
+struct EmboldenedTables {
+    glyf: GlyfTables,
+    hmtx: Rc<[u8]>,
+    strength: f32,
+    advance_delta: i32,
+    advance_width_max: u16,
+    // Bounding box of the whole font
+    bbox: Option<[i16; 4]>,
+}
+
+fn embolden_sfnt(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    strength: EmboldenStrength,
+) -> Result<EmboldenedTables> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    let strength = match strength {
+        EmboldenStrength::Units(x) => x,
+        EmboldenStrength::Percent(x) => x * f32::from(units_per_em) / 100.0,
+    };
+    let advance_delta = strength.round() as i32;
+
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+    let mut new_glyphs = glyf::map_simple_glyphs(sfnt_index, &glyphs, |_, simple_glyph| {
+        embolden::embolden_glyph(simple_glyph, strength);
+        Ok(true)
+    })?;
+    // Composite glyphs take the bounding box of their emboldened components
+    let new_glyph_refs = new_glyphs.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+    let bboxes = (0..new_glyphs.len())
+        .map(|glyph_index| glyf::glyph_bbox(sfnt_index, glyph_index, &new_glyph_refs))
+        .collect::<Result<Vec<_>>>()?;
+    for (glyph, bbox) in new_glyphs.iter_mut().zip(bboxes.iter()) {
+        if let (Some(bbox), true) = (bbox, glyph.len() >= 10) {
+            for (i, value) in bbox.iter().enumerate() {
+                glyph[2 + i * 2..4 + i * 2].clone_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+
+    // "hhea" table:
+    // byte 34..36: numberOfHMetrics
+    let num_h_metrics = peek_u16(sfnt, b"hhea", 34)
+        .map(usize::from)
+        .ok_or_else(|| anyhow!("sfnt {} table “hhea”: data truncated (34..36)", sfnt_index))?;
+    let mut new_hmtx = sfnt
+        .table_records
+        .get(&b"hmtx".into())
+        .map(|x| x.raw_data.to_vec())
+        .ok_or_else(|| anyhow!("sfnt {}: table “hmtx” not found", sfnt_index))?;
+    let mut advance_width_max = 0;
+    for glyph_index in 0..glyphs.len() {
+        // Zero-width glyphs, such as combining marks, stay so
+        if glyph_index < num_h_metrics {
+            if let Some(x) = new_hmtx.get_mut(glyph_index * 4..glyph_index * 4 + 2) {
+                let advance = i32::from(u16::from_be_bytes([x[0], x[1]]));
+                let new_advance = if advance == 0 {
+                    0
+                } else {
+                    (advance + advance_delta).clamp(0, 0xffff) as u16
+                };
+                advance_width_max = advance_width_max.max(new_advance);
+                x.clone_from_slice(&new_advance.to_be_bytes());
+            }
+        }
+        // The side bearing follows xMin, so that the glyph stays in place
+        let pos = if glyph_index < num_h_metrics {
+            glyph_index * 4 + 2
+        } else {
+            num_h_metrics * 4 + (glyph_index - num_h_metrics) * 2
+        };
+        let x_min_delta = match (
+            glyphs[glyph_index].get(2..4),
+            new_glyphs[glyph_index].get(2..4),
+        ) {
+            (Some(old), Some(new)) => {
+                i32::from(i16::from_be_bytes([new[0], new[1]]))
+                    - i32::from(i16::from_be_bytes([old[0], old[1]]))
+            }
+            _ => 0,
+        };
+        if let Some(x) = new_hmtx.get_mut(pos..pos + 2) {
+            let lsb = i32::from(i16::from_be_bytes([x[0], x[1]])) + x_min_delta;
+            x.clone_from_slice(&(lsb.clamp(i16::MIN.into(), i16::MAX.into()) as i16).to_be_bytes());
+        }
+    }
+
+    let bbox = bboxes.iter().flatten().copied().reduce(|acc, x| {
+        [
+            acc[0].min(x[0]),
+            acc[1].min(x[1]),
+            acc[2].max(x[2]),
+            acc[3].max(x[3]),
+        ]
+    });
+    let (glyf, loca, loca_format) = glyf::build_glyf(sfnt_index, &new_glyphs)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: failed to build", sfnt_index))?;
+    Ok(EmboldenedTables {
+        glyf: (Rc::from(glyf), Rc::from(loca), loca_format),
+        hmtx: Rc::from(new_hmtx),
+        strength,
+        advance_delta,
+        advance_width_max,
+        bbox,
+    })
+}
+
 pub fn remove_bitmap(ttc: &mut TTCHeader) {
-    for sfnt in ttc.table_directories.iter_mut() {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
         // Bitmap data (Apple format)
         sfnt.table_records.remove(&b"bdat".into());
         // Bitmap index (Apple format)
@@ -71,10 +660,486 @@ pub fn remove_bitmap(ttc: &mut TTCHeader) {
         sfnt.table_records.remove(&b"EBLC".into());
         // Bitmap scaling data (Microsoft format)
         sfnt.table_records.remove(&b"EBSC".into());
+        remove_bitmap_header(sfnt_index, sfnt);
+    }
+}
+
+// Color bitmaps are often the only glyph data of emoji fonts, so they are
+// handled separately from monochrome and grayscale bitmaps.
+pub fn remove_color_bitmap(ttc: &mut TTCHeader) {
+    const COLOR_BITMAP_TABLES: [FourCC; 3] = [FourCC(*b"CBDT"), FourCC(*b"CBLC"), FourCC(*b"sbix")];
+
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let has_color_bitmap = COLOR_BITMAP_TABLES
+            .iter()
+            .any(|x| sfnt.table_records.contains_key(x));
+        if has_color_bitmap && !has_outlines(sfnt_index, sfnt) {
+            eprintln!(
+                "[ WARN ] sfnt {}: font has no outlines, no glyph data will remain after removing color bitmap.",
+                sfnt_index
+            );
+        }
+        // Color bitmap data (Google format)
+        sfnt.table_records.remove(&b"CBDT".into());
+        // Color bitmap location (Google format)
+        sfnt.table_records.remove(&b"CBLC".into());
+        // Standard bitmap graphics (Apple format)
+        sfnt.table_records.remove(&b"sbix".into());
+    }
+}
+
+fn has_outlines(sfnt_index: usize, sfnt: &SfntHeader) -> bool {
+    if sfnt.table_records.contains_key(&b"CFF ".into())
+        || sfnt.table_records.contains_key(&b"CFF2".into())
+    {
+        return true;
+    }
+    // If "glyf" uses an unknown format, assume it has outlines
+    glyf::split_glyf(sfnt_index, sfnt).is_none_or(|x| x.iter().any(|x| !x.is_empty()))
+}
+
+// Apple bitmap-only fonts use "bhed" in place of "head".
+// Without "bloc" and "bdat", it either becomes "head" or goes away.
+fn remove_bitmap_header(sfnt_index: usize, sfnt: &mut SfntHeader) {
+    if sfnt.table_records.contains_key(&b"bloc".into()) {
+        return;
+    }
+    if let Some(bhed) = sfnt.table_records.remove(&b"bhed".into()) {
+        sfnt.table_records.entry(b"head".into()).or_insert_with(|| {
+            eprintln!(
+                "[ INFO ] sfnt {} table “bhed”: converting to “head”.",
+                sfnt_index
+            );
+            bhed
+        });
+    }
+}
+
+pub fn filter_bitmap(ttc: &mut TTCHeader, ppem_range: &RangeInclusive<u8>) {
+    // Bitmap location and data tables, Microsoft format and Apple format
+    const BITMAP_TABLES: [(FourCC, FourCC); 2] = [
+        (FourCC(*b"EBLC"), FourCC(*b"EBDT")),
+        (FourCC(*b"bloc"), FourCC(*b"bdat")),
+    ];
+
+    // Faces in a TTC usually share the same bitmap data, only filter it once
+    let groups = group_sfnts(ttc, |_, sfnt| {
+        Some(table_identity(sfnt, [b"EBLC", b"EBDT", b"bloc", b"bdat"]))
+    });
+
+    for (_, sfnt_indices) in groups {
+        let mut results = Vec::with_capacity(BITMAP_TABLES.len());
+        for &(loc_tag, dat_tag) in BITMAP_TABLES.iter() {
+            let sfnt = &ttc.table_directories[sfnt_indices[0]];
+            let result = match (
+                sfnt.table_records.get(&loc_tag),
+                sfnt.table_records.get(&dat_tag),
+            ) {
+                (Some(loc), Some(dat)) => filter_bitmap_strikes(
+                    sfnt_indices[0],
+                    loc_tag,
+                    &loc.raw_data,
+                    &dat.raw_data,
+                    ppem_range,
+                )
+                .unwrap_or_else(|e| {
+                    eprintln!("[ WARN ] {}", e);
+                    eprintln!(
+                        "[ WARN ] sfnt {} table {}: removing all strikes",
+                        format_sfnt_indices(&sfnt_indices),
+                        loc_tag
+                    );
+                    None
+                }),
+                _ => None,
+            };
+            results.push(result);
+        }
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            let mut kept_ppems = Vec::new();
+            for (&(loc_tag, dat_tag), result) in BITMAP_TABLES.iter().zip(results.iter()) {
+                match result {
+                    Some((new_loc, new_dat, ppems)) => {
+                        sfnt.table_records.insert(
+                            loc_tag,
+                            TableRecord {
+                                checksum: 0,
+                                offset: 0,
+                                raw_data: new_loc.clone(),
+                            },
+                        );
+                        sfnt.table_records.insert(
+                            dat_tag,
+                            TableRecord {
+                                checksum: 0,
+                                offset: 0,
+                                raw_data: new_dat.clone(),
+                            },
+                        );
+                        kept_ppems.extend(ppems.iter().copied());
+                    }
+                    None => {
+                        sfnt.table_records.remove(&loc_tag);
+                        sfnt.table_records.remove(&dat_tag);
+                    }
+                }
+            }
+            remove_bitmap_header(sfnt_index, sfnt);
+
+            // Bitmap scaling data (Microsoft format)
+            // Keep scaled sizes that are in range and refer to a remaining strike.
+            if let Some(ebsc) = sfnt.table_records.get(&b"EBSC".into()) {
+                let num_sizes = ebsc
+                    .raw_data
+                    .get(4..8)
+                    .map_or(0, |x| u32::from_be_bytes(x.try_into().unwrap()));
+                let records = ebsc
+                    .raw_data
+                    .get(8..)
+                    .unwrap_or_default()
+                    .chunks_exact(28)
+                    .take(usize::try_from(num_sizes).unwrap())
+                    .filter(|x| ppem_range.contains(&x[25]) && kept_ppems.contains(&(x[26], x[27])))
+                    .collect::<Vec<_>>();
+                if records.is_empty() {
+                    sfnt.table_records.remove(&b"EBSC".into());
+                } else {
+                    let mut new_ebsc = ebsc.raw_data[..4].to_vec();
+                    new_ebsc
+                        .extend_from_slice(&u32::try_from(records.len()).unwrap().to_be_bytes());
+                    for record in records {
+                        new_ebsc.extend_from_slice(record);
+                    }
+                    sfnt.table_records.insert(
+                        b"EBSC".into(),
+                        TableRecord {
+                            checksum: 0,
+                            offset: 0,
+                            raw_data: Rc::from(new_ebsc),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+// New location and data tables, and the remaining (ppemX, ppemY) pairs
+type FilteredBitmap = (Rc<[u8]>, Rc<[u8]>, Vec<(u8, u8)>);
+
+// Returns None if no strike remains.
+fn filter_bitmap_strikes(
+    sfnt_index: usize,
+    loc_tag: FourCC,
+    raw_loc: &[u8],
+    raw_dat: &[u8],
+    ppem_range: &RangeInclusive<u8>,
+) -> Result<Option<FilteredBitmap>> {
+    let (version, strikes) = bitmap::read_bitmap_location(sfnt_index, loc_tag, raw_loc)?;
+    let (kept, removed): (Vec<_>, Vec<_>) = strikes
+        .into_iter()
+        .partition(|x| ppem_range.contains(&x.ppem_y()));
+    eprintln!(
+        "[ INFO ] sfnt {} table {}: keeping strikes [{}], removing strikes [{}].",
+        sfnt_index,
+        loc_tag,
+        kept.iter()
+            .map(|x| x.ppem_y().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        removed
+            .iter()
+            .map(|x| x.ppem_y().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if kept.is_empty() {
+        return Ok(None);
+    }
+    let (new_loc, new_dat) =
+        bitmap::build_bitmap_tables(sfnt_index, loc_tag, version, &kept, raw_dat)?;
+    Ok(Some((
+        Rc::from(new_loc),
+        Rc::from(new_dat),
+        kept.iter().map(|x| (x.ppem_x(), x.ppem_y())).collect(),
+    )))
+}
+
+// Renders the outlines into new anti-aliased "EBLC" and "EBDT" strikes,
+// replacing any existing ones.
+pub fn generate_grayscale_bitmap(ttc: &mut TTCHeader, ppems: &[u8], bit_depth: u8) {
+    // Faces in a TTC usually share the same outlines, only render them once
+    let groups = group_sfnts(ttc, |_, sfnt| {
+        Some((
+            glyf_identity(sfnt),
+            table_identity(sfnt, [b"hmtx"]),
+            peek_u16(sfnt, b"head", 18),
+            [4, 6, 34].map(|pos| peek_u16(sfnt, b"hhea", pos)),
+        ))
+    });
+
+    for (_, sfnt_indices) in groups {
+        let result = render_grayscale_strikes(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+            ppems,
+            bit_depth,
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("[ WARN ] {}", e);
+            None
+        });
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            // Scaled sizes refer to the strikes being replaced
+            sfnt.table_records.remove(&b"EBSC".into());
+            if let Some((eblc, ebdt)) = &result {
+                sfnt.table_records.insert(
+                    b"EBLC".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: eblc.clone(),
+                    },
+                );
+                sfnt.table_records.insert(
+                    b"EBDT".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: ebdt.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+// New "EBLC" and "EBDT" tables
+type GrayscaleBitmap = (Rc<[u8]>, Rc<[u8]>);
+
+// Returns None if there is nothing to render.
+fn render_grayscale_strikes(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    ppems: &[u8],
+    bit_depth: u8,
+) -> Result<Option<GrayscaleBitmap>> {
+    let glyphs = match glyf::split_glyf(sfnt_index, sfnt) {
+        Some(glyphs) if !glyphs.is_empty() => glyphs,
+        _ => {
+            eprintln!(
+                "[ WARN ] sfnt {}: no supported TrueType outlines, skipping grayscale bitmap",
+                sfnt_index
+            );
+            return Ok(None);
+        }
+    };
+    let read_u16 = |table_tag: &'static [u8; 4], pos: usize| -> Result<u16> {
+        sfnt.table_records
+            .get(&table_tag.into())
+            .and_then(|x| x.raw_data.get(pos..pos + 2))
+            .map(|x| u16::from_be_bytes(x.try_into().unwrap()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table {}: data truncated ({}..{})",
+                    sfnt_index,
+                    FourCC(*table_tag),
+                    pos,
+                    pos + 2
+                )
+            })
+    };
+    // "head" table:
+    // byte 18..20: unitsPerEm
+    let units_per_em = read_u16(b"head", 18)?;
+    if units_per_em == 0 {
+        bail!("sfnt {} table “head”: unitsPerEm is zero", sfnt_index);
+    }
+    // "hhea" table:
+    // byte 4..6: ascender
+    // byte 6..8: descender
+    // byte 34..36: numberOfHMetrics
+    let ascender = read_u16(b"hhea", 4)? as i16;
+    let descender = read_u16(b"hhea", 6)? as i16;
+    let num_h_metrics = usize::from(read_u16(b"hhea", 34)?);
+    let advance_width = |glyph_index: usize| {
+        let i = glyph_index.min(num_h_metrics.saturating_sub(1));
+        read_u16(b"hmtx", i * 4).unwrap_or(0)
+    };
+
+    let outlines = (0..glyphs.len())
+        .map(|glyph_index| {
+            glyf::glyph_outline(sfnt_index, glyph_index, &glyphs).unwrap_or_else(|e| {
+                eprintln!("[ WARN ] {}", e);
+                Vec::new()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Data table header: version 2.0
+    let mut raw_dat = vec![0, 2, 0, 0];
+    let mut strikes = Vec::new();
+    for &ppem in ppems {
+        let scale = f32::from(ppem) / f32::from(units_per_em);
+        let rendered = outlines
+            .iter()
+            .enumerate()
+            .filter_map(|(glyph_index, contours)| {
+                Some(bitmap::GrayscaleGlyph {
+                    glyph_index: u16::try_from(glyph_index).ok()?,
+                    advance: (f32::from(advance_width(glyph_index)) * scale).round() as i32,
+                    image: raster::rasterize(contours, scale)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        let line_metrics = (
+            (f32::from(ascender) * scale).round() as i32,
+            (f32::from(descender) * scale).round() as i32,
+        );
+        if let Some(strike) = bitmap::build_grayscale_strike(
+            sfnt_index,
+            ppem,
+            bit_depth,
+            line_metrics,
+            &rendered,
+            &mut raw_dat,
+        ) {
+            strikes.push(strike);
+        }
+    }
+    if strikes.is_empty() {
+        return Ok(None);
+    }
+    eprintln!(
+        "[ INFO ] sfnt {} table “EBLC”: generated {}-bit strikes [{}].",
+        sfnt_index,
+        bit_depth,
+        strikes
+            .iter()
+            .map(|x| x.ppem_y().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let (eblc, ebdt) =
+        bitmap::build_bitmap_tables(sfnt_index, b"EBLC".into(), 0x0002_0000, &strikes, &raw_dat)?;
+    Ok(Some((Rc::from(eblc), Rc::from(ebdt))))
+}
+
+// Some older CJK fonts have glyphs that only exist as embedded bitmaps.
+// Warns about mapped glyphs that would have neither an outline nor a bitmap
+// after removing the strikes outside ppem_range, or all strikes if None.
+// Returns the number of affected code points.
+pub fn check_bitmap_only_glyphs(ttc: &TTCHeader, ppem_range: Option<&RangeInclusive<u8>>) -> usize {
+    const MAX_LISTED_CODE_POINTS: usize = 32;
+
+    let mut num_affected = 0;
+    for (sfnt_index, sfnt) in ttc.table_directories.iter().enumerate() {
+        let code_points = match bitmap_only_code_points(sfnt_index, sfnt, ppem_range) {
+            Ok(code_points) => code_points,
+            Err(e) => {
+                eprintln!("[ WARN ] {}", e);
+                continue;
+            }
+        };
+        if code_points.is_empty() {
+            continue;
+        }
+        let mut listed = code_points
+            .iter()
+            .take(MAX_LISTED_CODE_POINTS)
+            .map(|x| format!("U+{:04X}", x))
+            .collect::<Vec<_>>();
+        if code_points.len() > MAX_LISTED_CODE_POINTS {
+            listed.push(format!(
+                "and {} more",
+                code_points.len() - MAX_LISTED_CODE_POINTS
+            ));
+        }
+        eprintln!(
+            "[ WARN ] sfnt {}: {} characters only have embedded bitmap and no outline: {}.",
+            sfnt_index,
+            code_points.len(),
+            listed.join(", ")
+        );
+        num_affected += code_points.len();
+    }
+    num_affected
+}
+
+fn bitmap_only_code_points(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    ppem_range: Option<&RangeInclusive<u8>>,
+) -> Result<Vec<u32>> {
+    // Outlines are in "CFF " or "CFF2", empty "glyf" entries don't matter
+    if sfnt.table_records.contains_key(&b"CFF ".into())
+        || sfnt.table_records.contains_key(&b"CFF2".into())
+    {
+        return Ok(Vec::new());
+    }
+
+    let mut removed_glyphs = BTreeSet::new();
+    let mut kept_glyphs = BTreeSet::new();
+    for &loc_tag in [FourCC(*b"EBLC"), FourCC(*b"bloc")].iter() {
+        let raw_loc = match sfnt.table_records.get(&loc_tag) {
+            Some(loc) => &loc.raw_data,
+            None => continue,
+        };
+        let (_, strikes) = bitmap::read_bitmap_location(sfnt_index, loc_tag, raw_loc)?;
+        for strike in strikes {
+            let glyphs = if ppem_range.is_some_and(|x| x.contains(&strike.ppem_y())) {
+                &mut kept_glyphs
+            } else {
+                &mut removed_glyphs
+            };
+            for subtable in strike.subtables.iter() {
+                glyphs.extend(subtable.glyph_images().into_iter().map(|x| x.0));
+            }
+        }
+    }
+    if removed_glyphs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // If "glyf" uses an unknown format, we can't tell which glyphs are empty
+    let outlines = match glyf::split_glyf(sfnt_index, sfnt) {
+        Some(outlines) => outlines,
+        None => return Ok(Vec::new()),
+    };
+    let is_lost = |glyph_index: u16| {
+        removed_glyphs.contains(&glyph_index)
+            && !kept_glyphs.contains(&glyph_index)
+            && outlines
+                .get(usize::from(glyph_index))
+                .is_none_or(|x| x.is_empty())
+    };
+    Ok(cmap::read_unicode_cmap(sfnt_index, sfnt)?
+        .into_iter()
+        .filter(|&(code_point, glyph_index)| !is_invisible(code_point) && is_lost(glyph_index))
+        .map(|(code_point, _)| code_point)
+        .collect())
+}
+
+// Whitespace and format characters normally have empty outlines.
+fn is_invisible(code_point: u32) -> bool {
+    match code_point {
+        // Soft hyphen, combining grapheme joiner, Mongolian vowel separator
+        0xad | 0x34f | 0x180e => true,
+        // Zero width characters, directional marks and invisible operators
+        0x200b..=0x200f | 0x202a..=0x202e | 0x2060..=0x206f | 0xfeff => true,
+        // Variation selectors
+        0xfe00..=0xfe0f | 0xe0100..=0xe01ef => true,
+        // Tags
+        0xe0000..=0xe007f => true,
+        _ => char::from_u32(code_point).is_none_or(|x| x.is_whitespace() || x.is_control()),
     }
 }
 
-pub fn remove_hinting(ttc: &mut TTCHeader) {
+pub fn remove_hinting(ttc: &mut TTCHeader, filter: Option<&HintingFilter>) {
     const PATCHED_PREP: [u8; 15] = [
         0xb1, // PUSHB[1]
         0x04, // value = 4
@@ -95,15 +1160,31 @@ pub fn remove_hinting(ttc: &mut TTCHeader) {
         0x8e, // INSTRCTRL[], turn grid-fitting off
     ];
 
-    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+    for sfnt in ttc.table_directories.iter_mut() {
+        // When only some glyphs are stripped, the remaining hinted glyphs
+        // still need the font program, the CVT and the CVT program.
+        if filter.is_some() {
+            // Horizontal and vertical device metrics, and Linear Threshold
+            // data, which hinted glyphs may still affect. Otherwise they are
+            // left to regenerate_hdmx, regenerate_vdmx and regenerate_ltsh.
+            sfnt.table_records.remove(&b"hdmx".into());
+            sfnt.table_records.remove(&b"VDMX".into());
+            sfnt.table_records.remove(&b"LTSH".into());
+            continue;
+        }
+        // CFF outlines are not affected by TrueType instructions
+        if sfnt.table_records.contains_key(&b"CFF ".into())
+            || sfnt.table_records.contains_key(&b"CFF2".into())
+        {
+            continue;
+        }
+
         // CVT variations
         sfnt.table_records.remove(&b"cvar".into());
         // Control value table
         sfnt.table_records.remove(&b"cvt ".into());
         // Font program (only run once)
         sfnt.table_records.remove(&b"fpgm".into());
-        // Horizontal device metrics
-        sfnt.table_records.remove(&b"hdmx".into());
         // CVT Program (run whenever transform matrix changes)
         sfnt.table_records.insert(
             b"prep".into(),
@@ -118,496 +1199,2073 @@ pub fn remove_hinting(ttc: &mut TTCHeader) {
             let mut raw_data_copy = maxp.raw_data.to_vec();
 
             // Byte 14..16: maxZones, 1 if instructions do not use the twilight zone (Z0)
-            raw_data_copy
-                .get_mut(14..16)
-                .map(|x| x.clone_from_slice(&[0, 1]));
+            if let Some(x) = raw_data_copy.get_mut(14..16) {
+                x.clone_from_slice(&[0, 1]);
+            }
             // Byte 16..18: maxTwilightPoints
             // Byte 18..20: maxStorage
             // Byte 20..22: maxFunctionDefs
             // Byte 22..24: maxInstructionDefs
-            raw_data_copy.get_mut(16..24).map(|x| x.fill(0));
+            if let Some(x) = raw_data_copy.get_mut(16..24) {
+                x.fill(0);
+            }
             // Byte 24..26: maxStackElements
-            raw_data_copy
-                .get_mut(24..26)
-                .map(|x| x.clone_from_slice(&[0, 2]));
+            if let Some(x) = raw_data_copy.get_mut(24..26) {
+                x.clone_from_slice(&[0, 2]);
+            }
             // Byte 26..28: maxSizeOfInstructions
-            raw_data_copy
-                .get_mut(26..28)
-                .map(|x| x.clone_from_slice(&[0, PATCHED_PREP.len().try_into().unwrap()]));
+            if let Some(x) = raw_data_copy.get_mut(26..28) {
+                x.clone_from_slice(&[0, PATCHED_PREP.len().try_into().unwrap()]);
+            }
 
             maxp.raw_data = Rc::from(raw_data_copy);
         }
-        // Linear Threshold data
-        sfnt.table_records.remove(&b"LTSH".into());
-        // Vertical Device Metrics
-        sfnt.table_records.remove(&b"VDMX".into());
+    }
 
-        // "head" table:
-        // byte 50..52: indexToLocFormat
-        // byte 52..54: glyphDataFormat
-        let (loca_format, glyf_format) =
-            sfnt.table_records
-                .get(&b"head".into())
-                .map_or((None, None), |head| {
-                    (
-                        head.raw_data
-                            .get(50..52)
-                            .and_then(|x| <[_; 2]>::try_from(x).ok()),
-                        head.raw_data
-                            .get(52..54)
-                            .and_then(|x| <[_; 2]>::try_from(x).ok()),
-                    )
-                });
+    // Group the faces by their "glyf" tables, so that each unique one is
+    // only parsed and rebuilt once
+    let mut glyf_groups = Vec::<(Option<Vec<bool>>, Vec<usize>)>::new();
+    for (_, sfnt_indices) in group_sfnts(ttc, |_, sfnt| Some(glyf_identity(sfnt))) {
+        let filter = match filter {
+            Some(filter) => filter,
+            None => {
+                glyf_groups.push((None, sfnt_indices));
+                continue;
+            }
+        };
+        let components =
+            read_glyph_components(sfnt_indices[0], &ttc.table_directories[sfnt_indices[0]]);
+        // Faces sharing "glyf" may still map different characters to it
+        let selections = group_sfnts(ttc, |sfnt_index, sfnt| {
+            sfnt_indices
+                .contains(&sfnt_index)
+                .then(|| select_hinting_glyphs(sfnt_index, sfnt, &components, filter))
+        });
+        glyf_groups.extend(
+            selections
+                .into_iter()
+                .map(|(strip_glyphs, sfnt_indices)| (Some(strip_glyphs), sfnt_indices)),
+        );
+    }
 
-        // If there is a "loca" table
-        let loca = sfnt
-            .table_records
-            .get(&b"loca".into())
-            .map_or(Vec::new(), |loca| match loca_format {
-                Some([0, 0]) => {
-                    if loca.raw_data.len() % 2 != 0 {
-                        eprintln!(
-                            "[ WARN ] sfnt {} table “loca”: length {} is not multiples of 2",
-                            sfnt_index,
-                            loca.raw_data.len()
-                        );
-                    }
-                    loca.raw_data
-                        .chunks_exact(2)
-                        .map(|x| {
-                            usize::try_from(u16::from_be_bytes(x.try_into().unwrap())).unwrap() * 2
-                        })
-                        .collect::<Vec<_>>()
-                }
-                Some([0, 1]) => {
-                    if loca.raw_data.len() % 4 != 0 {
-                        eprintln!(
-                            "[ WARN ] sfnt {} table “loca”: length {} is not multiples of 4",
-                            sfnt_index,
-                            loca.raw_data.len()
-                        );
-                    }
-                    loca.raw_data
-                        .chunks_exact(4)
-                        .map(|x| {
-                            usize::try_from(u32::from_be_bytes(x.try_into().unwrap())).unwrap()
-                        })
-                        .collect::<Vec<_>>()
+    for (strip_glyphs, sfnt_indices) in glyf_groups {
+        let rewrite = rewrite_glyf(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+            strip_glyphs.as_deref(),
+        );
+        if let GlyfRewrite::Modified { num_modified, .. } = rewrite {
+            let sfnt_indices = format_sfnt_indices(&sfnt_indices);
+            if filter.is_some() {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “glyf”: modified to remove per-glyph hinting from {} glyphs.",
+                    sfnt_indices, num_modified
+                );
+            } else {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “glyf”: modified to remove per-glyph hinting.",
+                    sfnt_indices
+                );
+            }
+        }
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            match &rewrite {
+                GlyfRewrite::Unsupported => continue,
+                GlyfRewrite::Unmodified => (),
+                GlyfRewrite::Modified {
+                    glyf: new_glyf,
+                    loca: new_loca,
+                    loca_format,
+                    ..
+                } => glyf::set_glyf(sfnt, new_glyf.clone(), new_loca.clone(), *loca_format),
+            }
+
+            // Partially hinted fonts still depend on the point size
+            if filter.is_some() {
+                continue;
+            }
+            if let Some(head) = sfnt.table_records.get_mut(&b"head".into()) {
+                let mut new_head = head.raw_data.to_vec();
+                // Byte 17: flags
+                // flags[bit 2]: instructions may depend on point size
+                // flags[bit 3]: force ppem to integer values
+                // flags[bit 4]: instructions may alter advance width
+                if let Some(x) = new_head.get_mut(17) {
+                    *x &= 0xe3;
                 }
-                Some(loca_format) => {
+                head.raw_data = Rc::from(new_head);
+            }
+        }
+    }
+
+    // Faces in an OTC usually share the same "CFF " or "CFF2" data, only
+    // rewrite it once
+    let cff_groups = group_sfnts(ttc, |_, sfnt| {
+        Some(table_identity(sfnt, [b"CFF ", b"CFF2"]))
+    });
+    for (_, sfnt_indices) in cff_groups {
+        for &table_tag in [b"CFF ", b"CFF2"].iter() {
+            let table_tag = FourCC::from(table_tag);
+            let raw_cff = match ttc.table_directories[sfnt_indices[0]]
+                .table_records
+                .get(&table_tag)
+            {
+                Some(cff) => cff.raw_data.clone(),
+                None => continue,
+            };
+            if filter.is_some() {
+                eprintln!(
+                    "[ WARN ] sfnt {} table {}: removing hinting from selected glyphs is not supported, keeping all hinting",
+                    format_sfnt_indices(&sfnt_indices),
+                    table_tag
+                );
+                continue;
+            }
+            let new_cff = match cff::remove_cff_hinting(sfnt_indices[0], table_tag, &raw_cff) {
+                Ok(new_cff) => {
                     eprintln!(
-                        "[ WARN ] sfnt {} table “head”: unsupported indexToLocFormat: {}",
-                        sfnt_index,
-                        u16::from_be_bytes(loca_format)
+                        "[ INFO ] sfnt {} table {}: modified to remove hinting.",
+                        format_sfnt_indices(&sfnt_indices),
+                        table_tag
                     );
-                    Vec::new()
+                    Rc::<[u8]>::from(new_cff)
                 }
-                _ => {
-                    eprintln!(
-                        "[ WARN ] sfnt {} table “head”: unspecified indexToLocFormat value",
-                        sfnt_index
-                    );
-                    Vec::new()
+                Err(e) => {
+                    eprintln!("[ WARN ] {}", e);
+                    continue;
                 }
-            });
+            };
+            for &sfnt_index in sfnt_indices.iter() {
+                ttc.table_directories[sfnt_index].table_records.insert(
+                    table_tag,
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: new_cff.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
 
-        // If there is a "glyf" table
-        let raw_glyf = sfnt
-            .table_records
-            .get(&b"glyf".into())
-            .map_or([].as_ref(), |glyf| &glyf.raw_data);
+// Identity of the input tables that determine the glyph data: the addresses
+// of the "loca" and "glyf" data, plus indexToLocFormat and glyphDataFormat
+// from "head".
+type GlyfIdentity = ([Option<*const u8>; 2], Option<[u8; 2]>, Option<[u8; 2]>);
 
-        // Extract each glyph from the "glyf" table
-        let glyf = loca
-            .windows(2)
-            .enumerate()
-            .map(|(glyph_index, glyph_offset)| {
-                let glyph_offset_from = glyph_offset[0];
-                let glyph_offset_to = glyph_offset[1];
-                if glyph_offset_from > glyph_offset_to {
-                    eprintln!(
-                        "[ WARN ] sfnt {} table “loca”: glyph {} has a negative length ({} > {})",
-                        sfnt_index, glyph_index, glyph_offset_from, glyph_offset_to
-                    );
-                    [].as_ref()
-                } else {
-                    &raw_glyf[glyph_offset_from..glyph_offset_to]
-                }
+enum GlyfRewrite {
+    // The glyph data format is not supported, leave the face untouched
+    Unsupported,
+    // No glyph contains instructions, only the "head" flags need updating
+    Unmodified,
+    // New "loca" and "glyf" tables with instructions removed
+    Modified {
+        glyf: Rc<[u8]>,
+        loca: Rc<[u8]>,
+        loca_format: [u8; 2],
+        num_modified: usize,
+    },
+}
+
+fn glyf_identity(sfnt: &SfntHeader) -> GlyfIdentity {
+    let (loca_format, glyf_format) = glyf::get_glyf_formats(sfnt);
+    (
+        table_identity(sfnt, [b"loca", b"glyf"]),
+        loca_format,
+        glyf_format,
+    )
+}
+
+// Returns the glyph indices referenced by each glyph, which are the same for
+// all faces sharing "glyf".
+fn read_glyph_components(sfnt_index: usize, sfnt: &SfntHeader) -> Vec<Vec<u16>> {
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt).unwrap_or_default();
+    glyphs
+        .iter()
+        .enumerate()
+        .map(|(glyph_index, glyph)| {
+            glyf::glyph_components(sfnt_index, glyph_index, glyph).unwrap_or_else(|e| {
+                eprintln!("[ WARN ] {}", e);
+                Vec::new()
             })
-            .collect::<Vec<_>>();
+        })
+        .collect()
+}
 
-        // Make sure we don't run into unknown formats
-        if !glyf.is_empty() {
-            match glyf_format {
-                Some([0, 0]) => (),
-                Some(glyf_format) => {
-                    eprintln!(
-                        "[ WARN ] sfnt {} table “head”: unsupported glyphDataFormat: {}",
-                        sfnt_index,
-                        u16::from_be_bytes(glyf_format)
-                    );
-                    continue;
+// Resolves the filter into a list of glyphs, true if the glyph should lose its instructions.
+fn select_hinting_glyphs(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    components: &[Vec<u16>],
+    filter: &HintingFilter,
+) -> Vec<bool> {
+    const MAX_LISTED_GLYPHS: usize = 32;
+
+    let num_glyphs = components.len();
+    let mut strip_glyphs = vec![false; num_glyphs];
+
+    if filter.strip_unicode.is_empty() && filter.strip_glyphs.is_empty() {
+        // Only exceptions are given, strip every other glyph
+        strip_glyphs.fill(true);
+    }
+    if !filter.strip_unicode.is_empty() {
+        match cmap::read_unicode_cmap(sfnt_index, sfnt) {
+            Ok(cmap) => {
+                for (code_point, glyph_index) in cmap {
+                    if filter
+                        .strip_unicode
+                        .iter()
+                        .any(|range| range.contains(&code_point))
+                    {
+                        if let Some(x) = strip_glyphs.get_mut(usize::from(glyph_index)) {
+                            *x = true;
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("[ WARN ] {}", e),
+        }
+    }
+    for glyph_index in resolve_glyph_specs(sfnt_index, sfnt, &filter.strip_glyphs, num_glyphs) {
+        strip_glyphs[glyph_index] = true;
+    }
+
+    // Composite glyphs built from selected glyphs, e.g. unencoded variants
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (glyph_index, components) in components.iter().enumerate() {
+            if !strip_glyphs[glyph_index]
+                && components
+                    .iter()
+                    .any(|&x| strip_glyphs.get(usize::from(x)) == Some(&true))
+            {
+                strip_glyphs[glyph_index] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // Components run their own instructions, so they must be stripped too
+    let mut added_glyphs = Vec::new();
+    let mut stack = (0..num_glyphs)
+        .filter(|&x| strip_glyphs[x])
+        .collect::<Vec<_>>();
+    while let Some(glyph_index) = stack.pop() {
+        for &component in components[glyph_index].iter() {
+            if let Some(x) = strip_glyphs.get_mut(usize::from(component)) {
+                if !*x {
+                    *x = true;
+                    added_glyphs.push(usize::from(component));
+                    stack.push(usize::from(component));
                 }
-                _ => {
+            }
+        }
+    }
+
+    // Explicitly kept glyphs win, along with their components
+    let mut keep_glyphs = vec![false; num_glyphs];
+    let mut stack = resolve_glyph_specs(sfnt_index, sfnt, &filter.keep_glyphs, num_glyphs);
+    while let Some(glyph_index) = stack.pop() {
+        if keep_glyphs[glyph_index] {
+            continue;
+        }
+        keep_glyphs[glyph_index] = true;
+        strip_glyphs[glyph_index] = false;
+        stack.extend(
+            components[glyph_index]
+                .iter()
+                .map(|&x| usize::from(x))
+                .filter(|&x| x < num_glyphs),
+        );
+    }
+
+    // Components outside the selection, e.g. Latin letters reused by CJK
+    // composites, lose their hinting everywhere they are used
+    added_glyphs.retain(|&x| strip_glyphs[x]);
+    if !added_glyphs.is_empty() {
+        added_glyphs.sort_unstable();
+        let mut listed = added_glyphs
+            .iter()
+            .take(MAX_LISTED_GLYPHS)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        if added_glyphs.len() > MAX_LISTED_GLYPHS {
+            listed.push(format!(
+                "and {} more",
+                added_glyphs.len() - MAX_LISTED_GLYPHS
+            ));
+        }
+        eprintln!(
+            "[ WARN ] sfnt {} table “glyf”: also removing hinting from {} glyphs used as components of selected glyphs: {}. Use “--hint-keep-glyphs” to keep their hinting.",
+            sfnt_index,
+            added_glyphs.len(),
+            listed.join(", ")
+        );
+    }
+
+    strip_glyphs
+}
+
+// Resolves glyph ID ranges and glyph names into glyph indices.
+fn resolve_glyph_specs(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    specs: &[GlyphSpec],
+    num_glyphs: usize,
+) -> Vec<usize> {
+    let glyph_names = if specs.iter().any(|x| matches!(x, GlyphSpec::Name(_))) {
+        post::read_glyph_names(sfnt_index, sfnt).unwrap_or_else(|e| {
+            eprintln!("[ WARN ] {}", e);
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let mut glyph_indices = Vec::new();
+    for spec in specs {
+        match spec {
+            GlyphSpec::Range(range) => {
+                let from = usize::from(*range.start());
+                let to = usize::from(*range.end()).min(num_glyphs.saturating_sub(1));
+                if from >= num_glyphs {
                     eprintln!(
-                        "[ WARN ] sfnt {} table “head”: unspecified glyphDataFormat value",
-                        sfnt_index
+                        "[ WARN ] sfnt {}: glyph {} does not exist, the font has {} glyphs",
+                        sfnt_index, from, num_glyphs
                     );
                     continue;
                 }
+                glyph_indices.extend(from..=to);
             }
+            GlyphSpec::Name(name) => match glyph_names.iter().position(|x| x == name) {
+                Some(glyph_index) if glyph_index < num_glyphs => glyph_indices.push(glyph_index),
+                _ => eprintln!(
+                    "[ WARN ] sfnt {} table “post”: glyph name “{}” not found",
+                    sfnt_index, name
+                ),
+            },
         }
+    }
+    glyph_indices
+}
 
-        // Generate the new "glyf" table
-        let mut glyf_modified = false;
-        let new_glyf = glyf
-            .into_iter()
-            .enumerate()
-            .map(|(glyph_index, glyph)| {
-                if glyph.is_empty() {
-                    return Cow::Borrowed(glyph);
+fn rewrite_glyf(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    strip_glyphs: Option<&[bool]>,
+) -> GlyfRewrite {
+    let glyphs = match glyf::split_glyf(sfnt_index, sfnt) {
+        Some(glyphs) => glyphs,
+        None => return GlyfRewrite::Unsupported,
+    };
+
+    // Generate the new "glyf" table
+    let mut num_modified = 0;
+    let new_glyphs = glyphs
+        .into_iter()
+        .enumerate()
+        .map(|(glyph_index, glyph)| {
+            if glyph.is_empty() || strip_glyphs.is_some_and(|x| x.get(glyph_index) != Some(&true)) {
+                return Cow::Borrowed(glyph);
+            }
+            match remove_glyph_hinting(sfnt_index, glyph_index, glyph) {
+                Ok((new_glyph, has_instructions)) => {
+                    if has_instructions {
+                        num_modified += 1;
+                    }
+                    Cow::Owned(new_glyph)
                 }
-                (|| {
-                    let num_of_contours = u16::from_be_bytes(
-                        glyph
-                            .get(..2)
-                            .ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated (0..2)",
-                                    sfnt_index,
-                                    glyph_index,
-                                )
-                            })?
-                            .try_into()?,
+                Err(e) => {
+                    eprintln!("[ FAIL ] {}", e);
+                    Cow::Borrowed(glyph)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if num_modified == 0 {
+        // "glyf" does not need modification, simply update "head" and skip.
+        return GlyfRewrite::Unmodified;
+    }
+
+    match glyf::build_glyf(sfnt_index, &new_glyphs) {
+        Some((new_raw_glyf, new_raw_loca, loca_format)) => GlyfRewrite::Modified {
+            glyf: Rc::from(new_raw_glyf),
+            loca: Rc::from(new_raw_loca),
+            loca_format,
+            num_modified,
+        },
+        None => GlyfRewrite::Unsupported,
+    }
+}
+
+// Returns the glyph with its instructions removed, and whether the original
+// glyph contained any instructions.
+fn remove_glyph_hinting(
+    sfnt_index: usize,
+    glyph_index: usize,
+    glyph: &[u8],
+) -> Result<(Vec<u8>, bool)> {
+    let has_instructions;
+    let num_of_contours = u16::from_be_bytes(
+        glyph
+            .get(..2)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} data truncated (0..2)",
+                    sfnt_index,
+                    glyph_index,
+                )
+            })?
+            .try_into()?,
+    );
+    let new_glyph = if num_of_contours < 0x8000 {
+        // Simple glyph
+        let num_of_contours = usize::from(num_of_contours);
+        let num_of_points = if num_of_contours == 0 {
+            0
+        } else {
+            1 + usize::from(u16::from_be_bytes(
+                glyph
+                    .get(8 + num_of_contours * 2..10 + num_of_contours * 2)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                            sfnt_index,
+                            glyph_index,
+                            8 + num_of_contours * 2,
+                            10 + num_of_contours * 2
+                        )
+                    })?
+                    .try_into()?,
+            ))
+        };
+        let instruction_len = usize::from(u16::from_be_bytes(
+            glyph
+                .get(10 + num_of_contours * 2..12 + num_of_contours * 2)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                        sfnt_index,
+                        glyph_index,
+                        10 + num_of_contours * 2,
+                        12 + num_of_contours * 2
+                    )
+                })?
+                .try_into()?,
+        ));
+
+        let mut flags_pos = 12 + num_of_contours * 2 + instruction_len;
+        let mut glyph_len = 0;
+        let mut i = 0;
+        while i < num_of_points {
+            let flags = glyph.get(flags_pos).ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} data truncated ({})",
+                    sfnt_index,
+                    glyph_index,
+                    flags_pos
+                )
+            })?;
+            let coordinate_len = match flags & 0x12 {
+                0x00 => 2,        // !X_SHORT_VECTOR | !X_IS_SAME
+                0x02 | 0x12 => 1, // X_SHORT_VECTOR
+                0x10 => 0,        // !X_SHORT_VECTOR | X_IS_SAME
+                _ => unreachable!(),
+            } + match flags & 0x24 {
+                0x00 => 2,        // !Y_SHORT_VECTOR | !Y_IS_SAME
+                0x04 | 0x24 => 1, // Y_SHORT_VECTOR
+                0x20 => 0,        // !Y_SHORT_VECTOR | Y_IS_SAME
+                _ => unreachable!(),
+            };
+            // REPEAT_FLAG
+            if flags & 0x08 != 0 {
+                flags_pos += 1;
+                let repeat_count = 1 + usize::from(*glyph.get(flags_pos).ok_or_else(|| {
+                    anyhow!(
+                        "sfnt {} table “glyf”: glyph {} data truncated ({})",
+                        sfnt_index,
+                        glyph_index,
+                        flags_pos
+                    )
+                })?);
+                flags_pos += 1;
+                glyph_len += coordinate_len * repeat_count;
+                i += repeat_count;
+                if i > num_of_points {
+                    bail!(
+                        "sfnt {} table “glyf”: glyph {} data truncated",
+                        sfnt_index,
+                        glyph_index
                     );
-                    let new_glyph = if num_of_contours < 0x8000 {
-                        // Simple glyph
-                        let num_of_contours = usize::try_from(num_of_contours).unwrap();
-                        let num_of_points = if num_of_contours == 0 {
-                            0
-                        } else {
-                            1 + usize::try_from(u16::from_be_bytes(
-                                glyph
-                                    .get(8 + num_of_contours * 2..10 + num_of_contours * 2)
-                                    .ok_or_else(|| {
-                                        anyhow!(
-                                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
-                                        sfnt_index,
-                                        glyph_index,
-                                        8 + num_of_contours * 2,
-                                        10 + num_of_contours * 2
-                                    )
-                                    })?
-                                    .try_into()?,
-                            ))
-                            .unwrap()
-                        };
-                        let instruction_len = usize::try_from(u16::from_be_bytes(
-                            glyph
-                                .get(10 + num_of_contours * 2..12 + num_of_contours * 2)
-                                .ok_or_else(|| {
-                                    anyhow!(
-                                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
-                                        sfnt_index,
-                                        glyph_index,
-                                        10 + num_of_contours * 2,
-                                        12 + num_of_contours * 2
-                                    )
-                                })?
-                                .try_into()?,
-                        ))
-                        .unwrap();
-
-                        let mut flags_pos = 12 + num_of_contours * 2 + instruction_len;
-                        let mut glyph_len = 0;
-                        let mut i = 0;
-                        while i < num_of_points {
-                            let flags = glyph.get(flags_pos).ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated ({})",
-                                    sfnt_index,
-                                    glyph_index,
-                                    flags_pos
-                                )
-                            })?;
-                            let coordinate_len = match flags & 0x12 {
-                                0x00 => 2,        // !X_SHORT_VECTOR | !X_IS_SAME
-                                0x02 | 0x12 => 1, // X_SHORT_VECTOR
-                                0x10 => 0,        // !X_SHORT_VECTOR | X_IS_SAME
-                                _ => unreachable!(),
-                            } + match flags & 0x24 {
-                                0x00 => 2,        // !Y_SHORT_VECTOR | !Y_IS_SAME
-                                0x04 | 0x24 => 1, // Y_SHORT_VECTOR
-                                0x20 => 0,        // !Y_SHORT_VECTOR | Y_IS_SAME
-                                _ => unreachable!(),
-                            };
-                            // REPEAT_FLAG
-                            if flags & 0x08 != 0 {
-                                flags_pos += 1;
-                                let repeat_count = 1 + usize::try_from(
-                                    *glyph.get(flags_pos).ok_or_else(|| {
-                                        anyhow!(
-                                            "sfnt {} table “glyf”: glyph {} data truncated ({})",
-                                            sfnt_index,
-                                            glyph_index,
-                                            flags_pos
-                                        )
-                                    })?,
-                                )
-                                .unwrap();
-                                flags_pos += 1;
-                                glyph_len += coordinate_len * repeat_count;
-                                i += repeat_count;
-                                if i > num_of_points {
-                                    bail!(
-                                        "sfnt {} table “glyf”: glyph {} data truncated",
-                                        sfnt_index,
-                                        glyph_index
-                                    );
-                                }
-                            } else {
-                                flags_pos += 1;
-                                glyph_len += coordinate_len;
-                                i += 1;
-                            }
-                        }
+                }
+            } else {
+                flags_pos += 1;
+                glyph_len += coordinate_len;
+                i += 1;
+            }
+        }
 
-                        glyf_modified = glyf_modified || instruction_len != 0;
-                        let mut new_glyph = glyph
-                            .get(..10 + num_of_contours * 2)
-                            .ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
-                                    sfnt_index,
-                                    glyph_index,
-                                    10 + num_of_contours * 2
-                                )
-                            })?
-                            .to_vec();
-                        new_glyph.extend_from_slice(&[0; 2]);
-                        new_glyph.extend_from_slice(
-                            glyph
-                                .get(
-                                    12 + num_of_contours * 2 + instruction_len
-                                        ..flags_pos + glyph_len,
-                                )
-                                .ok_or_else(|| {
-                                    anyhow!(
-                                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
-                                        sfnt_index,
-                                        glyph_index,
-                                        12 + num_of_contours * 2 + instruction_len,
-                                        flags_pos + glyph_len
-                                    )
-                                })?,
-                        );
-                        new_glyph
-                    } else {
-                        // Composite glyph
-                        let mut next_glyph = 0;
-                        let mut glyph_len = 0;
-                        let mut flags = [0; 2].as_ref();
-                        let mut more_components = true;
-                        while more_components {
-                            next_glyph = glyph_len;
-                            flags = glyph.get(next_glyph..next_glyph + 2).ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
-                                    sfnt_index,
-                                    glyph_index,
-                                    glyph_len,
-                                    glyph_len + 2
-                                )
-                            })?;
-                            glyph_len = next_glyph + 4;
-                            if flags[1] & 0x01 != 0 {
-                                // ARG_1_AND_2_ARE_WORDS
-                                glyph_len += 4;
-                            } else {
-                                glyph_len += 2;
-                            }
-                            if flags[1] & 0x08 != 0 {
-                                // WE_HAVE_A_SCALE
-                                glyph_len += 2;
-                            } else if flags[1] & 0x40 != 0 {
-                                // WE_HAVE_AN_X_AND_Y_SCALE
-                                glyph_len += 4;
-                            } else if flags[1] & 0x80 != 0 {
-                                // WE_HAVE_A_TWO_BY_TWO
-                                glyph_len += 8;
-                            }
-                            // MORE_COMPONENTS
-                            more_components = flags[1] & 0x20 != 0;
-                        }
+        has_instructions = instruction_len != 0;
+        let mut new_glyph = glyph
+            .get(..10 + num_of_contours * 2)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
+                    sfnt_index,
+                    glyph_index,
+                    10 + num_of_contours * 2
+                )
+            })?
+            .to_vec();
+        new_glyph.extend_from_slice(&[0; 2]);
+        new_glyph.extend_from_slice(
+            glyph
+                .get(12 + num_of_contours * 2 + instruction_len..flags_pos + glyph_len)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                        sfnt_index,
+                        glyph_index,
+                        12 + num_of_contours * 2 + instruction_len,
+                        flags_pos + glyph_len
+                    )
+                })?,
+        );
+        new_glyph
+    } else {
+        // Composite glyph
+        let mut next_glyph = 0;
+        let mut glyph_len = 0;
+        let mut flags = [0; 2].as_ref();
+        let mut more_components = true;
+        while more_components {
+            next_glyph = glyph_len;
+            flags = glyph.get(next_glyph..next_glyph + 2).ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} data truncated ({}..{})",
+                    sfnt_index,
+                    glyph_index,
+                    glyph_len,
+                    glyph_len + 2
+                )
+            })?;
+            glyph_len = next_glyph + 4;
+            if flags[1] & 0x01 != 0 {
+                // ARG_1_AND_2_ARE_WORDS
+                glyph_len += 4;
+            } else {
+                glyph_len += 2;
+            }
+            if flags[1] & 0x08 != 0 {
+                // WE_HAVE_A_SCALE
+                glyph_len += 2;
+            } else if flags[1] & 0x40 != 0 {
+                // WE_HAVE_AN_X_AND_Y_SCALE
+                glyph_len += 4;
+            } else if flags[1] & 0x80 != 0 {
+                // WE_HAVE_A_TWO_BY_TWO
+                glyph_len += 8;
+            }
+            // MORE_COMPONENTS
+            more_components = flags[1] & 0x20 != 0;
+        }
 
-                        // WE_HAVE_INSTRUCTIONS
-                        let we_have_instructions = flags[0] & 0x01 != 0;
-                        glyf_modified = glyf_modified || we_have_instructions;
-                        let mut new_glyph = glyph
-                            .get(..next_glyph)
-                            .ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
-                                    sfnt_index,
-                                    glyph_index,
-                                    glyph_len
-                                )
-                            })?
-                            .to_vec();
-                        new_glyph.push(flags[0] & 0xfe);
-                        new_glyph.push(flags[1]);
-                        new_glyph.extend_from_slice(
-                            glyph.get(next_glyph + 2..glyph_len).ok_or_else(|| {
-                                anyhow!(
-                                    "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
-                                    sfnt_index,
-                                    glyph_index,
-                                    glyph_len
-                                )
-                            })?,
-                        );
-                        new_glyph
-                    };
-                    Ok(Cow::Owned(new_glyph))
-                })()
-                .unwrap_or_else(|e: anyhow::Error| {
-                    eprintln!("[ FAIL ] {}", e);
-                    Cow::Borrowed(glyph)
-                })
-            })
-            .collect::<Vec<_>>();
+        // WE_HAVE_INSTRUCTIONS
+        let we_have_instructions = flags[0] & 0x01 != 0;
+        has_instructions = we_have_instructions;
+        let mut new_glyph = glyph
+            .get(..next_glyph)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
+                    sfnt_index,
+                    glyph_index,
+                    glyph_len
+                )
+            })?
+            .to_vec();
+        new_glyph.push(flags[0] & 0xfe);
+        new_glyph.push(flags[1]);
+        new_glyph.extend_from_slice(glyph.get(next_glyph + 2..glyph_len).ok_or_else(|| {
+            anyhow!(
+                "sfnt {} table “glyf”: glyph {} data truncated (0..{})",
+                sfnt_index,
+                glyph_index,
+                glyph_len
+            )
+        })?);
+        new_glyph
+    };
+    Ok((new_glyph, has_instructions))
+}
 
-        if !glyf_modified {
-            // "glyf" does not need modification, simply update "head" and skip.
-            let head = sfnt.table_records.get_mut(&b"head".into()).unwrap();
+// Replaces "hdmx" with the device advance widths of unhinted glyphs. Without
+// a ppem list, only fonts that had "hdmx" get a new one, at the same sizes.
+pub fn regenerate_hdmx(ttc: &mut TTCHeader, ppems: Option<&[u8]>) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let ppems = match (ppems, sfnt.table_records.get(&b"hdmx".into())) {
+            (Some(ppems), _) => Ok(ppems.to_vec()),
+            (None, Some(hdmx)) => device_metrics::read_hdmx_ppems(sfnt_index, &hdmx.raw_data),
+            (None, None) => continue,
+        };
+        let new_hdmx = ppems.and_then(|x| device_metrics::build_hdmx(sfnt_index, sfnt, &x));
+        match new_hdmx {
+            Ok(Some(new_hdmx)) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “hdmx”: regenerated with linear advance widths.",
+                    sfnt_index
+                );
+                sfnt.table_records.insert(
+                    b"hdmx".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: Rc::from(new_hdmx),
+                    },
+                );
+            }
+            Ok(None) => {
+                sfnt.table_records.remove(&b"hdmx".into());
+                continue;
+            }
+            Err(e) => {
+                eprintln!("[ WARN ] {}, removing “hdmx”", e);
+                sfnt.table_records.remove(&b"hdmx".into());
+                continue;
+            }
+        }
+        if let Some(head) = sfnt.table_records.get_mut(&b"head".into()) {
             let mut new_head = head.raw_data.to_vec();
             // Byte 17: flags
-            // flags[bit 2]: instructions may depend on point size
-            // flags[bit 3]: force ppem to integer values
-            // flags[bit 4]: instructions may alter advance width
-            new_head.get_mut(17).map(|x| *x &= 0xf1);
+            // flags[bit 4]: instructions may alter advance width, the device
+            // widths in "hdmx" are not linearly scaled
+            if let Some(x) = new_head.get_mut(17) {
+                *x &= !0x10;
+            }
             head.raw_data = Rc::from(new_head);
+        }
+    }
+}
+
+// Drops the "hdmx" records above max_ppem, where glyphs are no longer hinted
+// and their hinted device widths would be wrong.
+pub fn truncate_hdmx(ttc: &mut TTCHeader, max_ppem: u16) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let hdmx = match sfnt.table_records.get_mut(&b"hdmx".into()) {
+            Some(hdmx) => hdmx,
+            None => continue,
+        };
+        match device_metrics::truncate_hdmx(sfnt_index, &hdmx.raw_data, max_ppem) {
+            Ok(Some(new_hdmx)) if new_hdmx.len() == hdmx.raw_data.len() => (),
+            Ok(Some(new_hdmx)) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “hdmx”: removed records above {} ppem.",
+                    sfnt_index, max_ppem
+                );
+                hdmx.raw_data = Rc::from(new_hdmx);
+            }
+            Ok(None) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “hdmx”: no records up to {} ppem, removing.",
+                    sfnt_index, max_ppem
+                );
+                sfnt.table_records.remove(&b"hdmx".into());
+            }
+            Err(e) => {
+                eprintln!("[ WARN ] {}, removing “hdmx”", e);
+                sfnt.table_records.remove(&b"hdmx".into());
+            }
+        }
+    }
+}
+
+pub fn remove_hdmx(ttc: &mut TTCHeader) {
+    for sfnt in ttc.table_directories.iter_mut() {
+        sfnt.table_records.remove(&b"hdmx".into());
+    }
+}
+
+// Replaces "VDMX" with the extents of unhinted glyphs, at the same sizes.
+pub fn regenerate_vdmx(ttc: &mut TTCHeader) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let raw_vdmx = match sfnt.table_records.get(&b"VDMX".into()) {
+            Some(vdmx) => vdmx.raw_data.clone(),
+            None => continue,
+        };
+        let new_vdmx = device_metrics::read_vdmx_ppem_range(sfnt_index, &raw_vdmx)
+            .and_then(|x| device_metrics::build_vdmx(sfnt_index, sfnt, x));
+        match new_vdmx {
+            Ok(new_vdmx) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “VDMX”: regenerated with unhinted glyph extents.",
+                    sfnt_index
+                );
+                sfnt.table_records.insert(
+                    b"VDMX".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: Rc::from(new_vdmx),
+                    },
+                );
+            }
+            Err(e) => {
+                eprintln!("[ WARN ] {}, removing “VDMX”", e);
+                sfnt.table_records.remove(&b"VDMX".into());
+            }
+        }
+    }
+}
+
+pub fn remove_vdmx(ttc: &mut TTCHeader) {
+    for sfnt in ttc.table_directories.iter_mut() {
+        sfnt.table_records.remove(&b"VDMX".into());
+    }
+}
+
+// Replaces "LTSH" with the linear thresholds of unhinted glyphs.
+pub fn regenerate_ltsh(ttc: &mut TTCHeader) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        if !sfnt.table_records.contains_key(&b"LTSH".into()) {
             continue;
         }
-        eprintln!(
-            "[ INFO ] sfnt {} table “glyf”: modified to remove per-glyph hinting.",
-            sfnt_index
-        );
+        match device_metrics::build_ltsh(sfnt_index, sfnt) {
+            Ok(new_ltsh) => {
+                eprintln!(
+                    "[ INFO ] sfnt {} table “LTSH”: regenerated with linear thresholds.",
+                    sfnt_index
+                );
+                sfnt.table_records.insert(
+                    b"LTSH".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: Rc::from(new_ltsh),
+                    },
+                );
+            }
+            Err(e) => {
+                eprintln!("[ WARN ] {}, removing “LTSH”", e);
+                sfnt.table_records.remove(&b"LTSH".into());
+            }
+        }
+    }
+}
+
+pub fn remove_ltsh(ttc: &mut TTCHeader) {
+    for sfnt in ttc.table_directories.iter_mut() {
+        sfnt.table_records.remove(&b"LTSH".into());
+    }
+}
+
+// Reports the limits in "maxp" that do not match the glyph data.
+pub fn check_maxp(ttc: &TTCHeader) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter().enumerate() {
+        let raw_maxp = match sfnt.table_records.get(&b"maxp".into()) {
+            // "maxp" version 0.5 has no limits
+            Some(maxp) if maxp.raw_data.len() >= 32 => &maxp.raw_data,
+            _ => continue,
+        };
+        let fields = match maxp::calculate_maxp(sfnt_index, sfnt) {
+            Ok(fields) => fields,
+            Err(e) => {
+                eprintln!("[ WARN ] {}", e);
+                continue;
+            }
+        };
+        for field in fields {
+            let value = u16::from_be_bytes([raw_maxp[field.pos], raw_maxp[field.pos + 1]]);
+            if value != field.value {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “maxp”: {} is {}, should be {}.",
+                    sfnt_index, field.name, value, field.value
+                );
+            }
+        }
+    }
+}
 
-        let mut new_raw_glyf =
-            Vec::with_capacity(new_glyf.iter().map(|x| x.as_ref().len() + 1).sum());
-        let mut new_loca = Vec::with_capacity(new_glyf.len() + 1);
-        for glyf in new_glyf {
-            if new_raw_glyf.len() % 2 != 0 {
-                new_raw_glyf.push(0);
+// Writes the limits calculated from the glyph data into "maxp".
+pub fn recalculate_maxp(ttc: &mut TTCHeader) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        match sfnt.table_records.get(&b"maxp".into()) {
+            Some(maxp) if maxp.raw_data.len() >= 32 => (),
+            _ => continue,
+        }
+        let fields = match maxp::calculate_maxp(sfnt_index, sfnt) {
+            Ok(fields) => fields,
+            Err(e) => {
+                eprintln!("[ WARN ] {}, keeping “maxp”", e);
+                continue;
+            }
+        };
+        if let Some(maxp) = sfnt.table_records.get_mut(&b"maxp".into()) {
+            let mut raw_data_copy = maxp.raw_data.to_vec();
+            for field in fields {
+                raw_data_copy[field.pos..field.pos + 2]
+                    .clone_from_slice(&field.value.to_be_bytes());
             }
-            new_loca.push(new_raw_glyf.len());
-            new_raw_glyf.extend(glyf.as_ref());
+            maxp.raw_data = Rc::from(raw_data_copy);
         }
-        if new_raw_glyf.len() < 131072 && new_raw_glyf.len() % 2 != 0 {
-            new_raw_glyf.push(0);
+    }
+}
+
+// The threshold is pushed as a signed word, so it must be below 32768.
+pub fn hybrid_hinting(ttc: &mut TTCHeader, threshold_ppem: u16) {
+    debug_assert!((1..=0x7fff).contains(&threshold_ppem));
+    let [threshold_hi, threshold_lo] = threshold_ppem.to_be_bytes();
+    let prep_prefix: [u8; 13] = [
+        0x00, // SVTCA[0], ppem along the y-axis, as in stem darkening
+        0x4b, // MPPEM[]
+        0x01, // SVTCA[1], back to the default vectors for the original program
+        0xb8, // PUSHW[0]
+        threshold_hi,
+        threshold_lo, // n = threshold_ppem
+        0x52,         // GT[], MPPEM > threshold_ppem
+        0x58,         // IF[]
+        //
+        0xb1, // PUSHB[1]
+        0x01, // value = 1
+        0x01, // s = 1
+        0x8e, // INSTRCTRL[], turn grid-fitting off
+        //
+        0x59, // EIF[]
+    ];
+
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        if !sfnt.table_records.contains_key(&b"glyf".into()) {
+            eprintln!(
+                "[ WARN ] sfnt {} table “glyf”: not found, skipping TrueType hinting",
+                sfnt_index
+            );
+            continue;
         }
-        new_loca.push(new_raw_glyf.len());
 
-        if new_raw_glyf.len() < 131072 {
-            let mut new_raw_loca = Vec::with_capacity(new_loca.len() * 2);
-            for x in new_loca {
-                assert_eq!(x % 2, 0);
-                new_raw_loca.extend_from_slice(&u16::try_from(x / 2).unwrap().to_be_bytes());
+        // CVT Program (run whenever transform matrix changes)
+        // The original program still runs afterwards, so the font keeps its
+        // own settings, e.g. dropout control, at every size.
+        let mut new_prep = Vec::new();
+        new_prep.extend_from_slice(&prep_prefix);
+        if let Some(prep) = sfnt.table_records.get(&b"prep".into()) {
+            new_prep.extend_from_slice(&prep.raw_data);
+        }
+        sfnt.table_records.insert(
+            b"prep".into(),
+            TableRecord {
+                checksum: 0,
+                offset: 0,
+                raw_data: Rc::from(new_prep),
+            },
+        );
+
+        // Maximum profile
+        if let Some(maxp) = sfnt.table_records.get_mut(&b"maxp".into()) {
+            let mut raw_data_copy = maxp.raw_data.to_vec();
+
+            // Byte 24..26: maxStackElements, at least 2 for the code above
+            if let Some(x) = raw_data_copy.get_mut(24..26) {
+                if u16::from_be_bytes([x[0], x[1]]) < 2 {
+                    x.clone_from_slice(&[0, 2]);
+                }
             }
+
+            maxp.raw_data = Rc::from(raw_data_copy);
+        }
+    }
+}
+
+pub fn hybrid_gasp_ranges(threshold_ppem: u16) -> Vec<GaspRange> {
+    vec![
+        GaspRange {
+            range_max_ppem: threshold_ppem,
+            range_gasp_behavior: GASP_GRIDFIT | GASP_DOGRAY | GASP_SYMMETRIC_GRIDFIT,
+        },
+        GaspRange {
+            range_max_ppem: 0xffff,
+            range_gasp_behavior: GASP_DOGRAY | GASP_SYMMETRIC_SMOOTHING,
+        },
+    ]
+}
+
+// Darkens stems at small sizes with generated glyph programs, once hinting
+// instructions are removed. Strokes get pixels wider at from_ppem and below,
+// fading to the designed weight at to_ppem.
+pub fn stem_darkening(ttc: &mut TTCHeader, ppem_range: &RangeInclusive<u8>, pixels: f32) {
+    let (from_ppem, to_ppem) = (*ppem_range.start(), *ppem_range.end());
+    let groups = group_sfnts(ttc, |sfnt_index, sfnt| {
+        has_glyf_outlines(sfnt_index, sfnt, "stem darkening")
+            .then(|| (glyf_identity(sfnt), peek_u16(sfnt, b"head", 18)))
+    });
+
+    for (_, sfnt_indices) in groups {
+        let tables = match darken_sfnt(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+            from_ppem,
+            pixels,
+        ) {
+            Ok(tables) => tables,
+            Err(e) => {
+                eprintln!("[ WARN ] {}, skipping stem darkening", e);
+                continue;
+            }
+        };
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            let (glyf, loca, loca_format) = tables.glyf.clone();
+            glyf::set_glyf(sfnt, glyf, loca, loca_format);
+
+            // Font program (only run once)
             sfnt.table_records.insert(
-                b"loca".into(),
+                b"fpgm".into(),
                 TableRecord {
                     checksum: 0,
                     offset: 0,
-                    raw_data: Rc::from(new_raw_loca),
+                    raw_data: Rc::from(embolden::stem_darkening_fpgm()),
                 },
             );
+            // CVT Program (run whenever transform matrix changes)
+            let mut new_prep = sfnt
+                .table_records
+                .get(&b"prep".into())
+                .map_or_else(Vec::new, |x| x.raw_data.to_vec());
+            new_prep.extend_from_slice(&embolden::stem_darkening_prep(from_ppem, to_ppem, pixels));
+            let prep_len = new_prep.len();
             sfnt.table_records.insert(
-                b"glyf".into(),
+                b"prep".into(),
                 TableRecord {
                     checksum: 0,
                     offset: 0,
-                    raw_data: Rc::from(new_raw_glyf),
+                    raw_data: Rc::from(new_prep),
                 },
             );
 
-            let head = sfnt.table_records.get_mut(&b"head".into()).unwrap();
-            let mut new_head = head.raw_data.to_vec();
-            // Byte 17: flags
-            // flags[bit 2]: instructions may depend on point size
-            // flags[bit 3]: force ppem to integer values
-            // flags[bit 4]: instructions may alter advance width
-            new_head[17] &= 0xf1;
-            // byte 50..52: indexToLocFormat
-            new_head[50..52].fill(0);
-            head.raw_data = Rc::from(new_head);
-        } else {
-            let mut new_raw_loca = Vec::with_capacity(new_loca.len() * 4);
-            for x in new_loca {
-                if let Ok(x) = u32::try_from(x) {
-                    new_raw_loca.extend_from_slice(&x.to_be_bytes());
+            // Maximum profile
+            if let Some(maxp) = sfnt.table_records.get_mut(&b"maxp".into()) {
+                let mut raw_data_copy = maxp.raw_data.to_vec();
+                let mut raise = |pos: usize, value: usize| {
+                    if let Some(x) = raw_data_copy.get_mut(pos..pos + 2) {
+                        let value = value.max(u16::from_be_bytes([x[0], x[1]]).into());
+                        x.clone_from_slice(&(value.min(0xffff) as u16).to_be_bytes());
+                    }
+                };
+                // Byte 18..20: maxStorage
+                raise(18, 1);
+                // Byte 20..22: maxFunctionDefs
+                raise(20, 1);
+                // Byte 24..26: maxStackElements, at least 4 for the CVT program
+                raise(24, tables.max_stack_elements.max(4));
+                // Byte 26..28: maxSizeOfInstructions
+                raise(26, tables.max_size_of_instructions.max(prep_len));
+                maxp.raw_data = Rc::from(raw_data_copy);
+            }
+        }
+    }
+}
+
+// New tables of a font with stem darkening
+struct DarkenedTables {
+    glyf: GlyfTables,
+    max_stack_elements: usize,
+    max_size_of_instructions: usize,
+}
+
+fn darken_sfnt(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    from_ppem: u8,
+    pixels: f32,
+) -> Result<DarkenedTables> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    // Corners are limited for the largest amount at from_ppem, smaller sizes
+    // are rare enough not to matter
+    let max_units = pixels / 2.0 * f32::from(units_per_em) / f32::from(from_ppem.max(1));
+
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+    let mut max_stack_elements = 0;
+    let mut max_size_of_instructions = 0;
+    let new_glyphs = glyf::map_simple_glyphs(sfnt_index, &glyphs, |_, simple_glyph| {
+        if !simple_glyph.instructions.is_empty() {
+            return Ok(false);
+        }
+        let program = embolden::stem_darkening_program(simple_glyph, max_units);
+        // The first NPUSHW pushes the most arguments
+        match program.get(1) {
+            Some(&num_words) => {
+                max_stack_elements = max_stack_elements.max(usize::from(num_words));
+            }
+            None => return Ok(false),
+        }
+        max_size_of_instructions = max_size_of_instructions.max(program.len());
+        simple_glyph.instructions = program;
+        Ok(true)
+    })?;
+
+    let (glyf, loca, loca_format) = glyf::build_glyf(sfnt_index, &new_glyphs)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: failed to build", sfnt_index))?;
+    Ok(DarkenedTables {
+        glyf: (Rc::from(glyf), Rc::from(loca), loca_format),
+        max_stack_elements,
+        max_size_of_instructions,
+    })
+}
+
+// Replaces the removed hinting instructions with generated ones, fitting
+// horizontal edges to the pixel grid in the y-direction only.
+pub fn autohint(ttc: &mut TTCHeader) {
+    let groups = group_sfnts(ttc, |sfnt_index, sfnt| {
+        has_glyf_outlines(sfnt_index, sfnt, "autohinting").then(|| {
+            (
+                glyf_identity(sfnt),
+                table_identity(sfnt, [b"cmap"]),
+                peek_u16(sfnt, b"head", 18),
+            )
+        })
+    });
+
+    for (_, sfnt_indices) in groups {
+        let tables = match autohint_sfnt(sfnt_indices[0], &ttc.table_directories[sfnt_indices[0]]) {
+            Ok(tables) => tables,
+            Err(e) => {
+                eprintln!("[ WARN ] {}, skipping autohinting", e);
+                continue;
+            }
+        };
+
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            let (glyf, loca, loca_format) = tables.glyf.clone();
+            glyf::set_glyf(sfnt, glyf, loca, loca_format);
+
+            for (table_tag, raw_data) in [
+                // Control value table
+                (b"cvt ", &tables.cvt),
+                // Font program (only run once)
+                (b"fpgm", &tables.fpgm),
+                // CVT Program (run whenever transform matrix changes)
+                (b"prep", &tables.prep),
+            ] {
+                sfnt.table_records.insert(
+                    table_tag.into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: raw_data.clone(),
+                    },
+                );
+            }
+
+            // Maximum profile
+            if let Some(maxp) = sfnt.table_records.get_mut(&b"maxp".into()) {
+                let mut raw_data_copy = maxp.raw_data.to_vec();
+                let mut set = |pos: usize, value: usize| {
+                    if let Some(x) = raw_data_copy.get_mut(pos..pos + 2) {
+                        x.clone_from_slice(&(value.min(0xffff) as u16).to_be_bytes());
+                    }
+                };
+                // Byte 14..16: maxZones, the twilight zone (Z0) is not used
+                set(14, 1);
+                // Byte 16..18: maxTwilightPoints
+                set(16, 0);
+                // Byte 18..20: maxStorage
+                set(18, 0);
+                // Byte 20..22: maxFunctionDefs
+                set(20, 1);
+                // Byte 22..24: maxInstructionDefs
+                set(22, 0);
+                // Byte 24..26: maxStackElements, the zone function needs 4
+                // more than the number of zones
+                set(24, tables.max_stack_elements.max(tables.num_zones + 4));
+                // Byte 26..28: maxSizeOfInstructions
+                set(26, tables.max_size_of_instructions.max(tables.prep.len()));
+                maxp.raw_data = Rc::from(raw_data_copy);
+            }
+        }
+    }
+}
+
+// New tables of an autohinted font
+struct AutohintedTables {
+    glyf: GlyfTables,
+    cvt: Rc<[u8]>,
+    fpgm: Rc<[u8]>,
+    prep: Rc<[u8]>,
+    num_zones: usize,
+    max_stack_elements: usize,
+    max_size_of_instructions: usize,
+}
+
+fn autohint_sfnt(sfnt_index: usize, sfnt: &SfntHeader) -> Result<AutohintedTables> {
+    let units_per_em = read_units_per_em(sfnt_index, sfnt)?;
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+
+    // Blue zones are measured from the reference characters
+    let mut reference_glyphs = BTreeMap::new();
+    for (code_point, glyph_index) in cmap::read_unicode_cmap(sfnt_index, sfnt)? {
+        let glyph_index = usize::from(glyph_index);
+        if let (Some(c), Some(glyph)) = (char::from_u32(code_point), glyphs.get(glyph_index)) {
+            if c.is_ascii_alphabetic() && glyf::is_simple_glyph(glyph) {
+                reference_glyphs.insert(
+                    c,
+                    glyf::decode_simple_glyph(sfnt_index, glyph_index, glyph)?,
+                );
+            }
+        }
+    }
+    let zones = autohint::find_blue_zones(&reference_glyphs, units_per_em);
+    eprintln!(
+        "[ INFO ] sfnt {} table “cvt ”: {} blue zones, {}.",
+        sfnt_index,
+        zones.len(),
+        zones
+            .iter()
+            .map(|x| format!("{} (overshoot {})", x.reference, x.overshoot))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut max_stack_elements = 0;
+    let mut max_size_of_instructions = 0;
+    let new_glyphs = glyf::map_simple_glyphs(sfnt_index, &glyphs, |_, simple_glyph| {
+        let (program, stack_elements) =
+            autohint::autohint_glyph(simple_glyph, &zones, units_per_em);
+        if program.is_empty() {
+            return Ok(false);
+        }
+        max_stack_elements = max_stack_elements.max(stack_elements);
+        max_size_of_instructions = max_size_of_instructions.max(program.len());
+        simple_glyph.instructions = program;
+        Ok(true)
+    })?;
+
+    let (glyf, loca, loca_format) = glyf::build_glyf(sfnt_index, &new_glyphs)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: failed to build", sfnt_index))?;
+    Ok(AutohintedTables {
+        glyf: (Rc::from(glyf), Rc::from(loca), loca_format),
+        cvt: Rc::from(autohint::autohint_cvt(&zones)),
+        fpgm: Rc::from(autohint::autohint_fpgm()),
+        prep: Rc::from(autohint::autohint_prep(zones.len())),
+        num_zones: zones.len(),
+        max_stack_elements,
+        max_size_of_instructions,
+    })
+}
+
+// Grid-fitting has to be on for glyph programs to run, up to the size where
+// the darkening fades out.
+pub fn stem_darkening_gasp_ranges(to_ppem: u8) -> Vec<GaspRange> {
+    let mut gasp_ranges = Vec::with_capacity(2);
+    if to_ppem > 1 {
+        gasp_ranges.push(GaspRange {
+            range_max_ppem: u16::from(to_ppem) - 1,
+            range_gasp_behavior: GASP_GRIDFIT
+                | GASP_DOGRAY
+                | GASP_SYMMETRIC_GRIDFIT
+                | GASP_SYMMETRIC_SMOOTHING,
+        });
+    }
+    gasp_ranges.extend_from_slice(&DEFAULT_GASP_RANGES);
+    gasp_ranges
+}
+
+pub const DEFAULT_GASP_RANGES: [GaspRange; 1] = [GaspRange {
+    range_max_ppem: 0xffff,
+    range_gasp_behavior: GASP_DOGRAY | GASP_SYMMETRIC_SMOOTHING,
+}];
+
+// Rasterizers grid-fit and smooth every size of fonts without "gasp"
+const NO_GASP_RANGES: [GaspRange; 1] = [GaspRange {
+    range_max_ppem: 0xffff,
+    range_gasp_behavior: GASP_GRIDFIT | GASP_DOGRAY,
+}];
+
+// Grid-fitting stays on for the glyphs that are still hinted
+pub const PARTIAL_HINTING_GASP_RANGES: [GaspRange; 1] = [GaspRange {
+    range_max_ppem: 0xffff,
+    range_gasp_behavior: GASP_GRIDFIT
+        | GASP_DOGRAY
+        | GASP_SYMMETRIC_GRIDFIT
+        | GASP_SYMMETRIC_SMOOTHING,
+}];
+
+pub fn regenerate_gasp(ttc: &mut TTCHeader, gasp_ranges: &[GaspRange]) {
+    let new_gasp = Rc::<[u8]>::from(build_gasp(gasp_ranges));
+
+    for sfnt in ttc.table_directories.iter_mut() {
+        // Grid-fitting/scan-conversion
+        sfnt.table_records.insert(
+            b"gasp".into(),
+            TableRecord {
+                checksum: 0,
+                offset: 0,
+                raw_data: new_gasp.clone(),
+            },
+        );
+    }
+}
+
+pub fn merge_gasp(ttc: &mut TTCHeader, threshold_ppem: u16) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let old_gasp_ranges = match sfnt.table_records.get(&b"gasp".into()) {
+            Some(gasp) => match parse_gasp(sfnt_index, &gasp.raw_data) {
+                Ok(gasp_ranges) => gasp_ranges,
+                Err(e) => {
+                    eprintln!("[ WARN ] {}", e);
+                    NO_GASP_RANGES.to_vec()
+                }
+            },
+            None => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “gasp”: not found, nothing to merge",
+                    sfnt_index
+                );
+                NO_GASP_RANGES.to_vec()
+            }
+        };
+
+        // Ranges above the threshold get smoothing, the range containing the
+        // threshold is split in two so that the sizes below are kept intact.
+        let mut new_gasp_ranges = Vec::with_capacity(old_gasp_ranges.len() + 2);
+        let mut prev_max_ppem = Option::<u16>::None;
+        for range in old_gasp_ranges.iter() {
+            if range.range_max_ppem <= threshold_ppem {
+                new_gasp_ranges.push(*range);
+            } else {
+                if prev_max_ppem.is_none_or(|x| x < threshold_ppem) {
+                    new_gasp_ranges.push(GaspRange {
+                        range_max_ppem: threshold_ppem,
+                        range_gasp_behavior: range.range_gasp_behavior,
+                    });
+                }
+                new_gasp_ranges.push(GaspRange {
+                    range_max_ppem: range.range_max_ppem,
+                    range_gasp_behavior: range.range_gasp_behavior
+                        | GASP_DOGRAY
+                        | GASP_SYMMETRIC_SMOOTHING,
+                });
+            }
+            prev_max_ppem = Some(range.range_max_ppem);
+        }
+        // The last range should always end at 65535, fix broken tables
+        if prev_max_ppem != Some(0xffff) {
+            if prev_max_ppem.is_none_or(|x| x < threshold_ppem) {
+                new_gasp_ranges.push(GaspRange {
+                    range_max_ppem: threshold_ppem,
+                    range_gasp_behavior: 0,
+                });
+            }
+            new_gasp_ranges.push(GaspRange {
+                range_max_ppem: 0xffff,
+                range_gasp_behavior: GASP_DOGRAY | GASP_SYMMETRIC_SMOOTHING,
+            });
+        }
+
+        eprintln!(
+            "[ INFO ] sfnt {} table “gasp”: {} → {}",
+            sfnt_index,
+            format_gasp_ranges(&old_gasp_ranges),
+            format_gasp_ranges(&new_gasp_ranges)
+        );
+        sfnt.table_records.insert(
+            b"gasp".into(),
+            TableRecord {
+                checksum: 0,
+                offset: 0,
+                raw_data: Rc::from(build_gasp(&new_gasp_ranges)),
+            },
+        );
+    }
+}
+
+fn parse_gasp(sfnt_index: usize, raw_gasp: &[u8]) -> Result<Vec<GaspRange>> {
+    let header = raw_gasp
+        .get(..4)
+        .ok_or_else(|| anyhow!("sfnt {} table “gasp”: data truncated (0..4)", sfnt_index))?;
+    let version = u16::from_be_bytes([header[0], header[1]]);
+    let num_ranges = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    if version > 1 {
+        bail!(
+            "sfnt {} table “gasp”: unsupported version: {}",
+            sfnt_index,
+            version
+        );
+    }
+    let raw_ranges = raw_gasp.get(4..4 + num_ranges * 4).ok_or_else(|| {
+        anyhow!(
+            "sfnt {} table “gasp”: data truncated (4..{})",
+            sfnt_index,
+            4 + num_ranges * 4
+        )
+    })?;
+    let gasp_ranges = raw_ranges
+        .chunks_exact(4)
+        .map(|x| GaspRange {
+            range_max_ppem: u16::from_be_bytes([x[0], x[1]]),
+            range_gasp_behavior: u16::from_be_bytes([x[2], x[3]]),
+        })
+        .collect::<Vec<_>>();
+    if let Some(x) = gasp_ranges
+        .windows(2)
+        .find(|x| x[1].range_max_ppem <= x[0].range_max_ppem)
+    {
+        bail!(
+            "sfnt {} table “gasp”: ppem values not in ascending order ({} after {})",
+            sfnt_index,
+            x[1].range_max_ppem,
+            x[0].range_max_ppem
+        );
+    }
+    Ok(gasp_ranges)
+}
+
+fn format_gasp_ranges(gasp_ranges: &[GaspRange]) -> String {
+    gasp_ranges
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn build_gasp(gasp_ranges: &[GaspRange]) -> Vec<u8> {
+    // Version 0 does not define the symmetric flags, only use version 1 if needed
+    let version: u16 = if gasp_ranges
+        .iter()
+        .any(|x| x.range_gasp_behavior & (GASP_SYMMETRIC_GRIDFIT | GASP_SYMMETRIC_SMOOTHING) != 0)
+    {
+        1
+    } else {
+        0
+    };
+
+    let mut gasp = Vec::with_capacity(4 + gasp_ranges.len() * 4);
+    gasp.extend_from_slice(&version.to_be_bytes());
+    gasp.extend_from_slice(&u16::try_from(gasp_ranges.len()).unwrap().to_be_bytes());
+    for range in gasp_ranges {
+        gasp.extend_from_slice(&range.range_max_ppem.to_be_bytes());
+        gasp.extend_from_slice(&range.range_gasp_behavior.to_be_bytes());
+    }
+    gasp
+}
+
+// Makes "loca" and "hmtx" agree with the number of glyphs in "maxp", which
+// the other tables are also sized by.
+pub fn fix_glyph_count(ttc: &mut TTCHeader) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        // "maxp" table:
+        // byte 4..6: numGlyphs
+        let num_glyphs = match sfnt
+            .table_records
+            .get(&b"maxp".into())
+            .and_then(|maxp| maxp.raw_data.get(4..6))
+        {
+            Some(x) => usize::from(u16::from_be_bytes([x[0], x[1]])),
+            None => continue,
+        };
+
+        let loca_entry_size = match glyf::get_glyf_formats(sfnt).0 {
+            Some([0, 0]) => Some(2),
+            Some([0, 1]) => Some(4),
+            _ => None,
+        };
+        if let (Some(loca), Some(entry_size)) =
+            (sfnt.table_records.get_mut(&b"loca".into()), loca_entry_size)
+        {
+            let num_entries = loca.raw_data.len() / entry_size;
+            if loca.raw_data.len() != (num_glyphs + 1) * entry_size {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “loca”: {} entries for {} glyphs, resized to {}.",
+                    sfnt_index,
+                    num_entries,
+                    num_glyphs,
+                    num_glyphs + 1
+                );
+                // Missing glyphs are empty
+                let last_entry = match num_entries.checked_sub(1) {
+                    Some(last) => {
+                        loca.raw_data[last * entry_size..num_entries * entry_size].to_vec()
+                    }
+                    None => vec![0; entry_size],
+                };
+                let mut new_loca =
+                    loca.raw_data[..num_entries.min(num_glyphs + 1) * entry_size].to_vec();
+                while new_loca.len() < (num_glyphs + 1) * entry_size {
+                    new_loca.extend_from_slice(&last_entry);
+                }
+                loca.raw_data = Rc::from(new_loca);
+            }
+        }
+
+        // Fonts without glyphs have no metrics to fix
+        if num_glyphs == 0 {
+            continue;
+        }
+
+        // "hhea" table:
+        // byte 34..36: numberOfHMetrics
+        let num_h_metrics = match sfnt
+            .table_records
+            .get(&b"hhea".into())
+            .and_then(|hhea| hhea.raw_data.get(34..36))
+        {
+            Some(x) => usize::from(u16::from_be_bytes([x[0], x[1]])),
+            None => continue,
+        };
+        let new_num_h_metrics = num_h_metrics.clamp(1, num_glyphs.max(1));
+        if new_num_h_metrics != num_h_metrics {
+            eprintln!(
+                "[ WARN ] sfnt {} table “hhea”: numberOfHMetrics is {} for {} glyphs, changed to {}.",
+                sfnt_index, num_h_metrics, num_glyphs, new_num_h_metrics
+            );
+            if let Some(hhea) = sfnt.table_records.get_mut(&b"hhea".into()) {
+                let mut new_hhea = hhea.raw_data.to_vec();
+                new_hhea[34..36]
+                    .clone_from_slice(&u16::try_from(new_num_h_metrics).unwrap().to_be_bytes());
+                hhea.raw_data = Rc::from(new_hhea);
+            }
+        }
+        if let Some(hmtx) = sfnt.table_records.get(&b"hmtx".into()) {
+            let expected_len =
+                new_num_h_metrics * 4 + num_glyphs.saturating_sub(new_num_h_metrics) * 2;
+            if hmtx.raw_data.len() == expected_len && new_num_h_metrics == num_h_metrics {
+                continue;
+            }
+            if hmtx.raw_data.len()
+                != num_h_metrics * 4 + num_glyphs.saturating_sub(num_h_metrics) * 2
+            {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “hmtx”: length is {}, should be {}, fixed.",
+                    sfnt_index,
+                    hmtx.raw_data.len(),
+                    expected_len
+                );
+            }
+            // Missing advance widths repeat the last one, missing side
+            // bearings take xMin of the glyph
+            let x_mins = glyf::split_glyf(sfnt_index, sfnt).map_or(Vec::new(), |glyphs| {
+                glyphs
+                    .iter()
+                    .map(|x| x.get(2..4).map(|x| [x[0], x[1]]))
+                    .collect()
+            });
+            let read = |pos: usize| hmtx.raw_data.get(pos..pos + 2).map(|x| [x[0], x[1]]);
+            let mut new_hmtx = Vec::with_capacity(expected_len);
+            let mut last_advance = [0; 2];
+            for glyph_index in 0..num_glyphs {
+                let (advance, lsb) = if glyph_index < num_h_metrics {
+                    (read(glyph_index * 4), read(glyph_index * 4 + 2))
                 } else {
-                    eprintln!(
-                        "[ WARN ] sfnt {} table “glyf”: size exceeds 2 GiB",
-                        sfnt_index
-                    );
-                    continue;
+                    (
+                        None,
+                        read(num_h_metrics * 4 + (glyph_index - num_h_metrics) * 2),
+                    )
+                };
+                last_advance = advance.unwrap_or(last_advance);
+                if glyph_index < new_num_h_metrics {
+                    new_hmtx.extend_from_slice(&last_advance);
                 }
+                new_hmtx.extend_from_slice(
+                    &lsb.or_else(|| x_mins.get(glyph_index).copied().flatten())
+                        .unwrap_or([0; 2]),
+                );
             }
             sfnt.table_records.insert(
-                b"loca".into(),
+                b"hmtx".into(),
                 TableRecord {
                     checksum: 0,
                     offset: 0,
-                    raw_data: Rc::from(new_raw_loca),
+                    raw_data: Rc::from(new_hmtx),
                 },
             );
-            sfnt.table_records.insert(
-                b"glyf".into(),
-                TableRecord {
-                    checksum: 0,
-                    offset: 0,
-                    raw_data: Rc::from(new_raw_glyf),
-                },
+        }
+    }
+}
+
+// Fixes the bounding boxes of glyphs in "glyf" that do not enclose their
+// points, and recalculates the one of the whole font in "head".
+pub fn fix_bounding_boxes(ttc: &mut TTCHeader) {
+    let groups = group_sfnts(ttc, |_, sfnt| {
+        sfnt.table_records.contains_key(&b"glyf".into()).then(|| {
+            (
+                glyf_identity(sfnt),
+                table_identity(sfnt, [b"hmtx"]),
+                peek_u16(sfnt, b"hhea", 34),
+            )
+        })
+    });
+
+    for (_, sfnt_indices) in groups {
+        let fixed = match fix_glyph_bounding_boxes(
+            sfnt_indices[0],
+            &ttc.table_directories[sfnt_indices[0]],
+        ) {
+            Some(fixed) => fixed,
+            None => continue,
+        };
+        if fixed.glyf.is_some() {
+            eprintln!(
+                "[ WARN ] sfnt {} table “glyf”: fixed bounding boxes of {} glyphs.",
+                format_sfnt_indices(&sfnt_indices),
+                fixed.num_fixed
             );
+        }
 
-            let head = sfnt.table_records.get_mut(&b"head".into()).unwrap();
-            let mut new_head = head.raw_data.to_vec();
-            // Byte 17: flags
-            // flags[bit 2]: instructions may depend on point size
-            // flags[bit 3]: force ppem to integer values
-            // flags[bit 4]: instructions may alter advance width
-            new_head[17] &= 0xf1;
-            // byte 50..52: indexToLocFormat
-            new_head[50..52].clone_from_slice(&[0, 1]);
-            head.raw_data = Rc::from(new_head);
+        for sfnt_index in sfnt_indices {
+            let sfnt = &mut ttc.table_directories[sfnt_index];
+            if let Some((new_glyf, new_loca, loca_format)) = &fixed.glyf {
+                glyf::set_glyf(sfnt, new_glyf.clone(), new_loca.clone(), *loca_format);
+                if let (Some(hmtx), Some(new_hmtx)) =
+                    (sfnt.table_records.get_mut(&b"hmtx".into()), &fixed.hmtx)
+                {
+                    hmtx.raw_data = new_hmtx.clone();
+                }
+            }
+
+            if let Some(head) = sfnt.table_records.get_mut(&b"head".into()) {
+                let mut new_head = head.raw_data.to_vec();
+                // byte 36..44: xMin, yMin, xMax, yMax
+                if let Some(x) = new_head.get_mut(36..44) {
+                    let new_bbox = fixed.bbox.map(i16::to_be_bytes).concat();
+                    if *x != *new_bbox {
+                        let old_bbox = [0, 2, 4, 6].map(|i| i16::from_be_bytes([x[i], x[i + 1]]));
+                        eprintln!(
+                            "[ WARN ] sfnt {} table “head”: bounding box is {:?}, should be {:?}.",
+                            sfnt_index, old_bbox, fixed.bbox
+                        );
+                        x.clone_from_slice(&new_bbox);
+                    }
+                }
+                // byte 16..18: flags
+                // flags[bit 1]: left side bearing point at x = 0
+                if let Some(x) = new_head.get_mut(17) {
+                    if *x & 0x02 != 0 && fixed.num_offset_glyphs != 0 {
+                        eprintln!(
+                            "[ WARN ] sfnt {} table “head”: {} glyphs have a left side bearing other than xMin, clearing flags bit 1.",
+                            sfnt_index, fixed.num_offset_glyphs
+                        );
+                        *x &= !0x02;
+                    }
+                }
+                if *new_head != *head.raw_data {
+                    head.raw_data = Rc::from(new_head);
+                }
+            }
         }
     }
 }
 
-pub fn regenerate_gasp(ttc: &mut TTCHeader) {
-    const PATCHED_GASP: [u8; 8] = [
-        0x00, 0x01, // version
-        0x00, 0x01, // numRanges
-        0xff, 0xff, // gaspRanges[0].rangeMaxPPEM = 65535
-        0x00,
-        0x0a, // gaspRanges[0].rangeGaspBehavior = GASP_DO_GRAY | GASP_SYMMETRIC_SMOOTHING
-    ];
+// New "glyf" and "loca" tables, and indexToLocFormat
+type GlyfTables = (Rc<[u8]>, Rc<[u8]>, [u8; 2]);
 
-    for sfnt in ttc.table_directories.iter_mut() {
-        // Grid-fitting/scan-conversion
+// Glyph bounding boxes of faces sharing "glyf" and "hmtx"
+struct FixedBoundingBoxes {
+    // New "glyf" and "loca", if any glyph was off
+    glyf: Option<GlyfTables>,
+    num_fixed: usize,
+    hmtx: Option<Rc<[u8]>>,
+    // Bounding box of the whole font
+    bbox: [i16; 4],
+    num_offset_glyphs: usize,
+}
+
+// Returns None if "glyf" uses an unknown format, or fails to build.
+fn fix_glyph_bounding_boxes(sfnt_index: usize, sfnt: &SfntHeader) -> Option<FixedBoundingBoxes> {
+    let (glyphs, bboxes) = {
+        let glyphs = glyf::split_glyf(sfnt_index, sfnt)?;
+        let bboxes = (0..glyphs.len())
+            .map(|glyph_index| {
+                glyf::glyph_bbox(sfnt_index, glyph_index, &glyphs).unwrap_or_else(|e| {
+                    // Keep the bounding box in the glyph header
+                    eprintln!("[ WARN ] {}", e);
+                    glyphs[glyph_index]
+                        .get(2..10)
+                        .map(|x| [0, 2, 4, 6].map(|i| i16::from_be_bytes([x[i], x[i + 1]])))
+                })
+            })
+            .collect::<Vec<_>>();
+        let glyphs = glyphs.into_iter().map(|x| x.to_vec()).collect::<Vec<_>>();
+        (glyphs, bboxes)
+    };
+
+    // "hhea" table:
+    // byte 34..36: numberOfHMetrics
+    let num_h_metrics = peek_u16(sfnt, b"hhea", 34).map_or(0, usize::from);
+    let lsb_pos = |glyph_index: usize| {
+        if glyph_index < num_h_metrics {
+            glyph_index * 4 + 2
+        } else {
+            num_h_metrics * 4 + (glyph_index - num_h_metrics) * 2
+        }
+    };
+    let mut new_hmtx = sfnt
+        .table_records
+        .get(&b"hmtx".into())
+        .map(|hmtx| hmtx.raw_data.to_vec());
+
+    // byte 2..10: xMin, yMin, xMax, yMax
+    // Some fonts round the extents of curves outwards, so boxes that
+    // enclose all points are kept
+    let mut new_glyphs = glyphs.clone();
+    let mut header_bboxes = bboxes.clone();
+    let mut num_fixed = 0;
+    for ((glyph, bbox), header_bbox) in new_glyphs
+        .iter_mut()
+        .zip(bboxes.iter())
+        .zip(header_bboxes.iter_mut())
+    {
+        if let (Some(bbox), true) = (bbox, glyph.len() >= 10) {
+            let old_bbox = [2, 4, 6, 8].map(|i| i16::from_be_bytes([glyph[i], glyph[i + 1]]));
+            if old_bbox[0] <= bbox[0]
+                && old_bbox[1] <= bbox[1]
+                && old_bbox[2] >= bbox[2]
+                && old_bbox[3] >= bbox[3]
+            {
+                *header_bbox = Some(old_bbox);
+                continue;
+            }
+            for (i, value) in bbox.iter().enumerate() {
+                glyph[2 + i * 2..4 + i * 2].clone_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+    for (glyph_index, (glyph, new_glyph)) in glyphs.iter().zip(new_glyphs.iter()).enumerate() {
+        if glyph == new_glyph {
+            continue;
+        }
+        num_fixed += 1;
+        // Keep the side bearing equal to xMin if it was, so that the
+        // glyph stays in place with rasterizers that use the header
+        let old_x_min = [glyph[2], glyph[3]];
+        if let Some(lsb) = new_hmtx
+            .as_mut()
+            .and_then(|x| x.get_mut(lsb_pos(glyph_index)..lsb_pos(glyph_index) + 2))
+        {
+            if *lsb == old_x_min {
+                lsb.clone_from_slice(&new_glyph[2..4]);
+            }
+        }
+    }
+    let new_glyf = if num_fixed != 0 {
+        let (new_glyf, new_loca, loca_format) = glyf::build_glyf(sfnt_index, &new_glyphs)?;
+        Some((Rc::from(new_glyf), Rc::from(new_loca), loca_format))
+    } else {
+        None
+    };
+
+    let bbox = header_bboxes
+        .iter()
+        .flatten()
+        .fold(None, |acc: Option<[i16; 4]>, x| {
+            Some(acc.map_or(*x, |acc| {
+                [
+                    acc[0].min(x[0]),
+                    acc[1].min(x[1]),
+                    acc[2].max(x[2]),
+                    acc[3].max(x[3]),
+                ]
+            }))
+        })
+        .unwrap_or_default();
+    // Glyphs whose side bearing is neither xMin in the header nor the
+    // smallest x of points
+    let num_offset_glyphs = bboxes
+        .iter()
+        .zip(header_bboxes.iter())
+        .enumerate()
+        .filter(|&(glyph_index, (bbox, header_bbox))| {
+            let lsb = new_hmtx
+                .as_ref()
+                .and_then(|x| x.get(lsb_pos(glyph_index)..lsb_pos(glyph_index) + 2));
+            match (bbox, header_bbox, lsb) {
+                (Some(bbox), Some(header_bbox), Some(lsb)) => {
+                    bbox[0].to_be_bytes() != lsb && header_bbox[0].to_be_bytes() != lsb
+                }
+                _ => false,
+            }
+        })
+        .count();
+
+    Some(FixedBoundingBoxes {
+        glyf: new_glyf,
+        num_fixed,
+        hmtx: new_hmtx.map(Rc::from),
+        bbox,
+        num_offset_glyphs,
+    })
+}
+
+// Enlarges the Windows and "hhea" ascent and descent to hold the outlines
+// with one pixel of anti-aliasing to spare from min_ppem up, as GDI clips
+// whatever falls outside "usWinAscent" and "usWinDescent", and other renderers
+// clip to the "hhea" extents. The typographic metrics in "OS/2" are left
+// untouched, so fonts with USE_TYPO_METRICS keep their line spacing.
+pub fn fix_vertical_metrics(ttc: &mut TTCHeader, min_ppem: u8, cmap_only: bool) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let glyph_ids = if cmap_only {
+            match cmap::read_unicode_cmap(sfnt_index, sfnt) {
+                Ok(cmap) => Some(cmap.into_values().collect::<BTreeSet<_>>()),
+                Err(e) => {
+                    eprintln!("[ WARN ] {}, skipping vertical metrics", e);
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        let (ascent, descent) = match device_metrics::read_clipping_extents(
+            sfnt_index,
+            sfnt,
+            min_ppem,
+            glyph_ids.as_ref(),
+        ) {
+            Ok(extents) => extents,
+            Err(e) => {
+                eprintln!("[ WARN ] {}, skipping vertical metrics", e);
+                continue;
+            }
+        };
+
+        // "OS/2" table:
+        // byte 74..76: usWinAscent
+        // byte 76..78: usWinDescent
+        if let Some(os2) = sfnt.table_records.get_mut(&b"OS/2".into()) {
+            let mut new_os2 = os2.raw_data.to_vec();
+            for (name, pos, value) in [("usWinAscent", 74, ascent), ("usWinDescent", 76, descent)] {
+                if let Some(x) = new_os2.get_mut(pos..pos + 2) {
+                    let old_value = u16::from_be_bytes([x[0], x[1]]);
+                    let new_value = u16::try_from(value).unwrap_or(u16::MAX);
+                    if old_value < new_value {
+                        eprintln!(
+                            "[ INFO ] sfnt {} table “OS/2”: {} enlarged from {} to {}.",
+                            sfnt_index, name, old_value, new_value
+                        );
+                        x.clone_from_slice(&new_value.to_be_bytes());
+                    }
+                }
+            }
+            if *new_os2 != *os2.raw_data {
+                os2.raw_data = Rc::from(new_os2);
+            }
+        }
+
+        // "hhea" table:
+        // byte 4..6: ascender
+        // byte 6..8: descender, negative below the baseline
+        if let Some(hhea) = sfnt.table_records.get_mut(&b"hhea".into()) {
+            let mut new_hhea = hhea.raw_data.to_vec();
+            for (name, pos, value) in [("ascender", 4, ascent), ("descender", 6, -descent)] {
+                if let Some(x) = new_hhea.get_mut(pos..pos + 2) {
+                    let old_value = i16::from_be_bytes([x[0], x[1]]);
+                    let new_value = value.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+                    if old_value.unsigned_abs() < new_value.unsigned_abs() {
+                        eprintln!(
+                            "[ INFO ] sfnt {} table “hhea”: {} enlarged from {} to {}.",
+                            sfnt_index, name, old_value, new_value
+                        );
+                        x.clone_from_slice(&new_value.to_be_bytes());
+                    }
+                }
+            }
+            if *new_hhea != *hhea.raw_data {
+                hhea.raw_data = Rc::from(new_hhea);
+            }
+        }
+    }
+}
+
+// Appends a suffix to the family names of every face, so that the modified
+// fonts can be installed next to the original ones.
+pub fn rename_fonts(ttc: &mut TTCHeader, suffix: &str) {
+    let mut post_script_names = BTreeSet::<String>::new();
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        let raw_name = match sfnt.table_records.get(&b"name".into()) {
+            Some(name) => name.raw_data.clone(),
+            None => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “name”: not found, skipping renaming",
+                    sfnt_index
+                );
+                continue;
+            }
+        };
+        let (new_name, post_script_name) =
+            match rename_sfnt(sfnt_index, &raw_name, suffix, &post_script_names) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("[ WARN ] {}, skipping renaming", e);
+                    continue;
+                }
+            };
         sfnt.table_records.insert(
-            b"gasp".into(),
+            b"name".into(),
             TableRecord {
                 checksum: 0,
                 offset: 0,
-                raw_data: Rc::from(PATCHED_GASP),
+                raw_data: Rc::from(new_name),
             },
         );
+
+        // The FontName of CFF outlines is the PostScript name
+        if let Some(cff) = sfnt.table_records.get_mut(&b"CFF ".into()) {
+            match cff::rename_cff(sfnt_index, &cff.raw_data, &post_script_name) {
+                Ok(new_cff) => cff.raw_data = Rc::from(new_cff),
+                Err(e) => eprintln!("[ WARN ] {}, keeping the CFF FontName", e),
+            }
+        }
+        post_script_names.insert(post_script_name);
     }
 }
 
+// Returns the new "name" table and PostScript name. The PostScript name is
+// made different from those in used_post_script_names.
+fn rename_sfnt(
+    sfnt_index: usize,
+    raw_name: &[u8],
+    suffix: &str,
+    used_post_script_names: &BTreeSet<String>,
+) -> Result<(Vec<u8>, String)> {
+    let mut name = NameTable::parse(sfnt_index, raw_name)?;
+    let old_post_script_name = name.get(6);
+    let old_post_script_family = name
+        .get(16)
+        .or_else(|| name.get(1))
+        .map(|x| name::sanitize_post_script_name(&x));
+
+    // Family name, full name, typographic and WWS family names
+    let family_name_ids: &[u16] = &[1, 4, 16, 21];
+    for key in name.keys(family_name_ids) {
+        let family = name.get_by_key(key, 16).or_else(|| name.get_by_key(key, 1));
+        // Full names and legacy family names usually start with the family
+        // name, the suffix goes right after it
+        let insert_suffix = |value: &str| match family
+            .as_deref()
+            .and_then(|family| value.strip_prefix(family).map(|rest| (family, rest)))
+        {
+            Some((family, rest)) => format!("{}{}{}", family, suffix, rest),
+            None => format!("{}{}", value, suffix),
+        };
+        let mut ok = true;
+        for &name_id in family_name_ids {
+            match name.get_by_key(key, name_id) {
+                Some(value) => ok &= name.set_by_key(key, name_id, &insert_suffix(&value)),
+                // Names in encodings that cannot be decoded would keep the old
+                // family name
+                None => ok &= !name.contains_key(key, name_id),
+            }
+        }
+        // Each typographic or WWS family name has its subfamily name
+        for (family_name_id, subfamily_name_id) in [(16, 17), (21, 22)] {
+            if name.get_by_key(key, family_name_id).is_some()
+                && name.get_by_key(key, subfamily_name_id).is_none()
+            {
+                if let Some(subfamily) = name.get_by_key(key, 2) {
+                    ok &= name.set_by_key(key, subfamily_name_id, &subfamily);
+                }
+            }
+        }
+        // Languages keep either all the new names or none of them
+        if !ok {
+            eprintln!(
+                "[ WARN ] sfnt {} table “name”: cannot encode “{}” for platform {} encoding {} language 0x{:04x}, removing its names",
+                sfnt_index, suffix, key.0, key.1, key.2
+            );
+            for &name_id in [1, 4, 16, 17, 21, 22].iter() {
+                name.remove_by_key(key, name_id);
+            }
+        }
+    }
+
+    let post_script_suffix = name::sanitize_post_script_name(suffix);
+    let post_script_name = match &old_post_script_name {
+        // Family and style are separated by a hyphen, unless the hyphen is
+        // part of the family name
+        Some(old) => match old.split_once('-') {
+            Some((family, style)) if old_post_script_family.as_deref() == Some(family) => {
+                format!("{}{}-{}", family, post_script_suffix, style)
+            }
+            _ => format!("{}{}", old, post_script_suffix),
+        },
+        None => {
+            let family = name.get(16).or_else(|| name.get(1)).unwrap_or_default();
+            let subfamily = name.get(17).or_else(|| name.get(2)).unwrap_or_default();
+            format!("{}-{}", family, subfamily)
+        }
+    };
+    let mut post_script_name = name::sanitize_post_script_name(&post_script_name);
+    // Faces of a TTC need different PostScript names
+    if used_post_script_names.contains(&post_script_name) {
+        let new_post_script_name = (1..)
+            .map(|number| {
+                let tag = format!("_{}", number);
+                let prefix = post_script_name.chars().take(63 - tag.len());
+                prefix.chain(tag.chars()).collect::<String>()
+            })
+            .find(|x| !used_post_script_names.contains(x))
+            .unwrap();
+        eprintln!(
+            "[ WARN ] sfnt {} table “name”: PostScript name “{}” is used by another face, changed to “{}”.",
+            sfnt_index, post_script_name, new_post_script_name
+        );
+        post_script_name = new_post_script_name;
+    }
+
+    // The unique ID usually contains the PostScript name, otherwise the
+    // renamed font is told apart by appending it
+    if let Some(unique_id) = name.get(3) {
+        let new_unique_id = match &old_post_script_name {
+            Some(x) if unique_id.contains(x.as_str()) => {
+                unique_id.replace(x.as_str(), &post_script_name)
+            }
+            _ => format!("{};{}", unique_id, post_script_name),
+        };
+        name.set(3, &new_unique_id);
+    }
+    name.set(6, &post_script_name);
+    eprintln!(
+        "[ INFO ] sfnt {} table “name”: renamed to “{}”.",
+        sfnt_index,
+        name.get(4).unwrap_or_else(|| post_script_name.clone())
+    );
+    Ok((name.build(), post_script_name))
+}
+
+// Seconds from 1904-01-01, the epoch of "head", to 1970-01-01
+pub const HEAD_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+// Marks the fonts as modified by FaithType. The marker goes at the end of the
+// version string, and optionally the description, replacing that of an
+// earlier run. The modification time is set to timestamp, in seconds since
+// 1970-01-01.
+pub fn stamp_provenance(
+    ttc: &mut TTCHeader,
+    marker: &str,
+    timestamp: i64,
+    stamp_description: bool,
+    bump_revision: bool,
+) {
+    for (sfnt_index, sfnt) in ttc.table_directories.iter_mut().enumerate() {
+        // "head" table:
+        // byte 4..8: fontRevision
+        // byte 28..36: modified
+        let mut new_revision = None;
+        if let Some(head) = sfnt.table_records.get_mut(&b"head".into()) {
+            let mut raw_data_copy = head.raw_data.to_vec();
+            if let (Some(x), Some(modified)) = (
+                raw_data_copy.get_mut(28..36),
+                timestamp.checked_add(HEAD_EPOCH_OFFSET),
+            ) {
+                x.clone_from_slice(&modified.to_be_bytes());
+            }
+            if let (true, Some(x)) = (bump_revision, raw_data_copy.get_mut(4..8)) {
+                let revision = f64::from(i32::from_be_bytes([x[0], x[1], x[2], x[3]])) / 65536.0;
+                let revision_thousandths = (revision * 1000.0).round() + 1.0;
+                let fixed = (revision_thousandths / 1000.0 * 65536.0).round() as i32;
+                x.clone_from_slice(&fixed.to_be_bytes());
+                eprintln!(
+                    "[ INFO ] sfnt {} table “head”: fontRevision {:.3} changed to {:.3}.",
+                    sfnt_index,
+                    revision,
+                    revision_thousandths / 1000.0
+                );
+                new_revision = Some(revision_thousandths / 1000.0);
+            }
+            head.raw_data = Rc::from(raw_data_copy);
+        }
+
+        let raw_name = match sfnt.table_records.get(&b"name".into()) {
+            Some(name) => name.raw_data.clone(),
+            None => {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “name”: not found, skipping provenance",
+                    sfnt_index
+                );
+                continue;
+            }
+        };
+        match stamp_name(
+            sfnt_index,
+            &raw_name,
+            marker,
+            stamp_description,
+            new_revision,
+        ) {
+            Ok(new_name) => {
+                sfnt.table_records.insert(
+                    b"name".into(),
+                    TableRecord {
+                        checksum: 0,
+                        offset: 0,
+                        raw_data: Rc::from(new_name),
+                    },
+                );
+            }
+            Err(e) => eprintln!("[ WARN ] {}, skipping provenance", e),
+        }
+    }
+}
+
+// Returns the new "name" table, with the marker in the version string (ID 5),
+// and optionally the description (ID 10).
+fn stamp_name(
+    sfnt_index: usize,
+    raw_name: &[u8],
+    marker: &str,
+    stamp_description: bool,
+    new_revision: Option<f64>,
+) -> Result<Vec<u8>> {
+    let mut name = NameTable::parse(sfnt_index, raw_name)?;
+    // Markers of earlier runs are replaced, not appended to
+    let stamp = |value: &str| {
+        if value.starts_with("FaithType ") {
+            return marker.to_string();
+        }
+        match value.find("; FaithType ") {
+            Some(pos) => format!("{}; {}", &value[..pos], marker),
+            None => format!("{}; {}", value, marker),
+        }
+    };
+
+    let mut name_ids = vec![5];
+    if stamp_description {
+        name_ids.push(10);
+    }
+    for name_id in name_ids {
+        let keys = name.keys(&[name_id]);
+        if keys.is_empty() {
+            name.set(name_id, marker);
+            continue;
+        }
+        for key in keys {
+            let mut value = name.get_by_key(key, name_id).unwrap_or_default();
+            // The version string starts with "Version <major>.<minor>"
+            if let (5, Some(revision), Some(rest)) =
+                (name_id, new_revision, value.strip_prefix("Version "))
+            {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .unwrap_or(rest.len());
+                if len != 0 {
+                    value = format!("Version {:.3}{}", revision, &rest[len..]);
+                }
+            }
+            if !name.set_by_key(key, name_id, &stamp(&value)) {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “name”: cannot encode “{}” for platform {} encoding {} language 0x{:04x}",
+                    sfnt_index, marker, key.0, key.1, key.2
+                );
+            }
+        }
+    }
+    Ok(name.build())
+}
+
 pub fn patch_head(ttc: &mut TTCHeader) {
     for sfnt in ttc.table_directories.iter_mut() {
         if sfnt.sfnt_version == b"true".into() {
@@ -619,9 +3277,13 @@ pub fn patch_head(ttc: &mut TTCHeader) {
             // Byte 16: flags
             // flags[bit 11]: font data is lossless converted
             // flags[bit 13]: font optimized for Microsoft ClearType
-            raw_data_copy.get_mut(16).map(|x| *x |= 0x28);
+            if let Some(x) = raw_data_copy.get_mut(16) {
+                *x |= 0x28;
+            }
             // Byte 46..48: smallest readable size in pixels
-            raw_data_copy.get_mut(46..48).map(|x| x.fill(0));
+            if let Some(x) = raw_data_copy.get_mut(46..48) {
+                x.fill(0);
+            }
 
             head.raw_data = Rc::from(raw_data_copy);
         }
@@ -0,0 +1,302 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+// Characters 0x80..=0xff of the Mac OS Roman encoding
+const MAC_ROMAN_HIGH: &str = "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø¿¡¬√ƒ≈∆«»…\u{00A0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{F8FF}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+// Windows, Unicode BMP, English (United States)
+const DEFAULT_KEY: NameKey = (3, 1, 0x0409);
+
+// Platform ID, encoding ID and language ID shared by the strings of one
+// language
+pub type NameKey = (u16, u16, u16);
+
+#[derive(Clone, Debug)]
+pub struct NameTable {
+    pub records: Vec<NameRecord>,
+    // Language tags of "name" version 1
+    pub lang_tags: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NameRecord {
+    pub key: NameKey,
+    pub name_id: u16,
+    pub raw_string: Vec<u8>,
+}
+
+impl NameTable {
+    pub fn parse(sfnt_index: usize, raw_name: &[u8]) -> Result<Self> {
+        let truncated = |from: usize, to: usize| {
+            anyhow!(
+                "sfnt {} table “name”: data truncated ({}..{})",
+                sfnt_index,
+                from,
+                to
+            )
+        };
+        let u16_at = |pos: usize| -> Result<u16> {
+            Ok(u16::from_be_bytes(
+                raw_name
+                    .get(pos..pos + 2)
+                    .ok_or_else(|| truncated(pos, pos + 2))?
+                    .try_into()?,
+            ))
+        };
+        let string_at = |storage_offset: usize, length: u16, offset: u16| -> Result<Vec<u8>> {
+            let from = storage_offset + usize::from(offset);
+            let to = from + usize::from(length);
+            Ok(raw_name
+                .get(from..to)
+                .ok_or_else(|| truncated(from, to))?
+                .to_vec())
+        };
+
+        // byte 0..2: version
+        // byte 2..4: count
+        // byte 4..6: storageOffset
+        let version = u16_at(0)?;
+        if version > 1 {
+            bail!(
+                "sfnt {} table “name”: unsupported version {}",
+                sfnt_index,
+                version
+            );
+        }
+        let count = usize::from(u16_at(2)?);
+        let storage_offset = usize::from(u16_at(4)?);
+        let mut records = Vec::with_capacity(count);
+        for i in 0..count {
+            let pos = 6 + i * 12;
+            records.push(NameRecord {
+                key: (u16_at(pos)?, u16_at(pos + 2)?, u16_at(pos + 4)?),
+                name_id: u16_at(pos + 6)?,
+                raw_string: string_at(storage_offset, u16_at(pos + 8)?, u16_at(pos + 10)?)?,
+            });
+        }
+        let mut lang_tags = Vec::new();
+        if version == 1 {
+            let pos = 6 + count * 12;
+            for i in 0..usize::from(u16_at(pos)?) {
+                let pos = pos + 2 + i * 4;
+                lang_tags.push(string_at(storage_offset, u16_at(pos)?, u16_at(pos + 2)?)?);
+            }
+        }
+        Ok(Self { records, lang_tags })
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut records = self.records.iter().collect::<Vec<_>>();
+        records.sort_by_key(|x| (x.key, x.name_id));
+
+        let version: u16 = if self.lang_tags.is_empty() { 0 } else { 1 };
+        let header_len = 6
+            + records.len() * 12
+            + if self.lang_tags.is_empty() {
+                0
+            } else {
+                2 + self.lang_tags.len() * 4
+            };
+        let mut header = Vec::with_capacity(header_len);
+        let mut storage = Vec::<u8>::new();
+        // Identical strings, common across languages, are stored once
+        let mut store = |string: &[u8]| {
+            let offset = storage
+                .windows(string.len().max(1))
+                .position(|x| x == string)
+                .filter(|_| !string.is_empty())
+                .unwrap_or_else(|| {
+                    storage.extend_from_slice(string);
+                    storage.len() - string.len()
+                });
+            (
+                u16::try_from(string.len()).unwrap_or(u16::MAX),
+                u16::try_from(offset).unwrap_or(u16::MAX),
+            )
+        };
+
+        header.extend_from_slice(&version.to_be_bytes());
+        header.extend_from_slice(&u16::try_from(records.len()).unwrap().to_be_bytes());
+        header.extend_from_slice(&u16::try_from(header_len).unwrap().to_be_bytes());
+        for record in records {
+            let (length, offset) = store(&record.raw_string);
+            for x in [
+                record.key.0,
+                record.key.1,
+                record.key.2,
+                record.name_id,
+                length,
+                offset,
+            ]
+            .iter()
+            {
+                header.extend_from_slice(&x.to_be_bytes());
+            }
+        }
+        if !self.lang_tags.is_empty() {
+            header.extend_from_slice(&u16::try_from(self.lang_tags.len()).unwrap().to_be_bytes());
+            for lang_tag in self.lang_tags.iter() {
+                let (length, offset) = store(lang_tag);
+                header.extend_from_slice(&length.to_be_bytes());
+                header.extend_from_slice(&offset.to_be_bytes());
+            }
+        }
+        header.extend(storage);
+        header
+    }
+
+    // Distinct languages of the strings with any of the name IDs, in order.
+    pub fn keys(&self, name_ids: &[u16]) -> Vec<NameKey> {
+        let mut keys = self
+            .records
+            .iter()
+            .filter(|x| name_ids.contains(&x.name_id))
+            .map(|x| x.key)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    pub fn contains_key(&self, key: NameKey, name_id: u16) -> bool {
+        self.records
+            .iter()
+            .any(|x| x.key == key && x.name_id == name_id)
+    }
+
+    pub fn get_by_key(&self, key: NameKey, name_id: u16) -> Option<String> {
+        self.records
+            .iter()
+            .find(|x| x.key == key && x.name_id == name_id)
+            .and_then(|x| decode_string(x.key, &x.raw_string))
+    }
+
+    // Returns a string, preferably in English, from any platform.
+    pub fn get(&self, name_id: u16) -> Option<String> {
+        self.get_by_key(DEFAULT_KEY, name_id).or_else(|| {
+            self.records
+                .iter()
+                .filter(|x| x.name_id == name_id)
+                .min_by_key(|x| (x.key.0 != 3, x.key.0 != 0, x.key))
+                .and_then(|x| decode_string(x.key, &x.raw_string))
+        })
+    }
+
+    // Returns false if the language does not support all the characters.
+    pub fn set_by_key(&mut self, key: NameKey, name_id: u16, value: &str) -> bool {
+        let raw_string = match encode_string(key, value) {
+            Some(raw_string) => raw_string,
+            None => return false,
+        };
+        match self
+            .records
+            .iter_mut()
+            .find(|x| x.key == key && x.name_id == name_id)
+        {
+            Some(record) => record.raw_string = raw_string,
+            None => self.records.push(NameRecord {
+                key,
+                name_id,
+                raw_string,
+            }),
+        }
+        true
+    }
+
+    // Replaces the string in all languages. Languages that cannot encode it
+    // lose their translation.
+    pub fn set(&mut self, name_id: u16, value: &str) {
+        let mut keys = self.keys(&[name_id]);
+        if !keys.iter().any(|x| x.0 == 3) {
+            keys.push(DEFAULT_KEY);
+        }
+        for key in keys {
+            if !self.set_by_key(key, name_id, value) {
+                self.remove_by_key(key, name_id);
+            }
+        }
+    }
+
+    pub fn remove_by_key(&mut self, key: NameKey, name_id: u16) {
+        self.records
+            .retain(|x| !(x.key == key && x.name_id == name_id));
+    }
+
+    pub fn remove(&mut self, name_id: u16) {
+        self.records.retain(|x| x.name_id != name_id);
+    }
+}
+
+fn decode_string(key: NameKey, raw_string: &[u8]) -> Option<String> {
+    match key {
+        // Unicode, and Windows Symbol, Unicode BMP, Unicode full repertoire
+        (0, _, _) | (3, 0, _) | (3, 1, _) | (3, 10, _) => {
+            let utf16 = raw_string
+                .chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]));
+            std::char::decode_utf16(utf16)
+                .collect::<Result<_, _>>()
+                .ok()
+        }
+        // Macintosh, Roman
+        (1, 0, _) => Some(
+            raw_string
+                .iter()
+                .map(|&x| match x {
+                    0x00..=0x7f => char::from(x),
+                    _ => MAC_ROMAN_HIGH.chars().nth(usize::from(x - 0x80)).unwrap(),
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn encode_string(key: NameKey, value: &str) -> Option<Vec<u8>> {
+    match key {
+        (0, _, _) | (3, 0, _) | (3, 1, _) | (3, 10, _) => {
+            Some(value.encode_utf16().flat_map(u16::to_be_bytes).collect())
+        }
+        (1, 0, _) => value
+            .chars()
+            .map(|c| match c {
+                '\0'..='\x7f' => Some(c as u8),
+                _ => MAC_ROMAN_HIGH
+                    .chars()
+                    .position(|x| x == c)
+                    .map(|x| 0x80 + x as u8),
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+// Drops the characters not allowed in PostScript names, and limits the
+// length to 63 characters.
+pub fn sanitize_post_script_name(value: &str) -> String {
+    value
+        .chars()
+        .filter(|&c| ('!'..='~').contains(&c) && !"[](){}<>/%".contains(c))
+        .take(63)
+        .collect()
+}
@@ -0,0 +1,226 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::types::SfntHeader;
+
+// The standard Macintosh ordering of the first 258 glyph names
+#[rustfmt::skip]
+pub const MAC_GLYPH_NAMES: [&str; 258] = [
+    ".notdef", ".null", "nonmarkingreturn", "space", "exclam", "quotedbl", "numbersign",
+    "dollar", "percent", "ampersand", "quotesingle", "parenleft", "parenright", "asterisk",
+    "plus", "comma", "hyphen", "period", "slash", "zero", "one", "two", "three", "four", "five",
+    "six", "seven", "eight", "nine", "colon", "semicolon", "less", "equal", "greater",
+    "question", "at", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O",
+    "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "bracketleft", "backslash",
+    "bracketright", "asciicircum", "underscore", "grave", "a", "b", "c", "d", "e", "f", "g", "h",
+    "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+    "braceleft", "bar", "braceright", "asciitilde", "Adieresis", "Aring", "Ccedilla", "Eacute",
+    "Ntilde", "Odieresis", "Udieresis", "aacute", "agrave", "acircumflex", "adieresis", "atilde",
+    "aring", "ccedilla", "eacute", "egrave", "ecircumflex", "edieresis", "iacute", "igrave",
+    "icircumflex", "idieresis", "ntilde", "oacute", "ograve", "ocircumflex", "odieresis",
+    "otilde", "uacute", "ugrave", "ucircumflex", "udieresis", "dagger", "degree", "cent",
+    "sterling", "section", "bullet", "paragraph", "germandbls", "registered", "copyright",
+    "trademark", "acute", "dieresis", "notequal", "AE", "Oslash", "infinity", "plusminus",
+    "lessequal", "greaterequal", "yen", "mu", "partialdiff", "summation", "product", "pi",
+    "integral", "ordfeminine", "ordmasculine", "Omega", "ae", "oslash", "questiondown",
+    "exclamdown", "logicalnot", "radical", "florin", "approxequal", "Delta", "guillemotleft",
+    "guillemotright", "ellipsis", "nonbreakingspace", "Agrave", "Atilde", "Otilde", "OE", "oe",
+    "endash", "emdash", "quotedblleft", "quotedblright", "quoteleft", "quoteright", "divide",
+    "lozenge", "ydieresis", "Ydieresis", "fraction", "currency", "guilsinglleft",
+    "guilsinglright", "fi", "fl", "daggerdbl", "periodcentered", "quotesinglbase",
+    "quotedblbase", "perthousand", "Acircumflex", "Ecircumflex", "Aacute", "Edieresis",
+    "Egrave", "Iacute", "Icircumflex", "Idieresis", "Igrave", "Oacute", "Ocircumflex", "apple",
+    "Ograve", "Uacute", "Ucircumflex", "Ugrave", "dotlessi", "circumflex", "tilde", "macron",
+    "breve", "dotaccent", "ring", "cedilla", "hungarumlaut", "ogonek", "caron", "Lslash",
+    "lslash", "Scaron", "scaron", "Zcaron", "zcaron", "brokenbar", "Eth", "eth", "Yacute",
+    "yacute", "Thorn", "thorn", "minus", "multiply", "onesuperior", "twosuperior",
+    "threesuperior", "onehalf", "onequarter", "threequarters", "franc", "Gbreve", "gbreve",
+    "Idotaccent", "Scedilla", "scedilla", "Cacute", "cacute", "Ccaron", "ccaron", "dcroat",
+];
+
+// Reads the glyph names from "post", an empty list if the font has none.
+pub fn read_glyph_names(sfnt_index: usize, sfnt: &SfntHeader) -> Result<Vec<String>> {
+    let raw_post = match sfnt.table_records.get(&b"post".into()) {
+        Some(post) => &post.raw_data,
+        None => return Ok(Vec::new()),
+    };
+    let header = raw_post
+        .get(..32)
+        .ok_or_else(|| anyhow!("sfnt {} table “post”: data truncated (0..32)", sfnt_index))?;
+    let version = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    match version {
+        0x00010000 => Ok(MAC_GLYPH_NAMES.iter().map(|&x| x.to_owned()).collect()),
+        0x00020000 => {
+            let num_glyphs = usize::from(u16::from_be_bytes(
+                raw_post
+                    .get(32..34)
+                    .ok_or_else(|| {
+                        anyhow!("sfnt {} table “post”: data truncated (32..34)", sfnt_index)
+                    })?
+                    .try_into()?,
+            ));
+            let glyph_name_index = raw_post.get(34..34 + num_glyphs * 2).ok_or_else(|| {
+                anyhow!(
+                    "sfnt {} table “post”: data truncated (34..{})",
+                    sfnt_index,
+                    34 + num_glyphs * 2
+                )
+            })?;
+
+            // Pascal strings following the index array
+            let mut custom_names = Vec::new();
+            let mut pos = 34 + num_glyphs * 2;
+            while let Some(&len) = raw_post.get(pos) {
+                let name = raw_post
+                    .get(pos + 1..pos + 1 + usize::from(len))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "sfnt {} table “post”: data truncated ({}..{})",
+                            sfnt_index,
+                            pos + 1,
+                            pos + 1 + usize::from(len)
+                        )
+                    })?;
+                custom_names.push(String::from_utf8_lossy(name).into_owned());
+                pos += 1 + usize::from(len);
+            }
+
+            glyph_name_index
+                .chunks_exact(2)
+                .map(|x| {
+                    let index = usize::from(u16::from_be_bytes([x[0], x[1]]));
+                    if index < MAC_GLYPH_NAMES.len() {
+                        Ok(MAC_GLYPH_NAMES[index].to_owned())
+                    } else {
+                        custom_names
+                            .get(index - MAC_GLYPH_NAMES.len())
+                            .cloned()
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "sfnt {} table “post”: glyph name index {} out of range",
+                                    sfnt_index,
+                                    index
+                                )
+                            })
+                    }
+                })
+                .collect()
+        }
+        0x00030000 => Ok(Vec::new()),
+        _ => bail!(
+            "sfnt {} table “post”: unsupported version: 0x{:08x}",
+            sfnt_index,
+            version
+        ),
+    }
+}
+
+// Builds a version 3.0 "post" table, which has no glyph names, keeping the
+// other header fields from the original table if there is one.
+pub fn build_post_without_names(raw_post: Option<&[u8]>) -> Vec<u8> {
+    let mut new_post = Vec::with_capacity(32);
+    new_post.extend_from_slice(&0x00030000u32.to_be_bytes());
+    match raw_post.and_then(|x| x.get(4..32)) {
+        Some(header) => new_post.extend_from_slice(header),
+        None => new_post.extend_from_slice(&[0; 28]),
+    }
+    new_post
+}
+
+// Builds a version 2.0 "post" table with the given glyph names, keeping the
+// other header fields from the original table if there is one. Falls back to
+// version 3.0 if the names do not fit.
+pub fn build_post(sfnt_index: usize, raw_post: Option<&[u8]>, glyph_names: &[String]) -> Vec<u8> {
+    let too_many_names = || {
+        eprintln!(
+            "[ WARN ] sfnt {} table “post”: too many glyph names, writing version 3.0 without names",
+            sfnt_index
+        );
+        build_post_without_names(raw_post)
+    };
+    let mut new_post = build_post_without_names(raw_post);
+    new_post[..4].clone_from_slice(&0x00020000u32.to_be_bytes());
+    match u16::try_from(glyph_names.len()) {
+        Ok(num_glyphs) => new_post.extend_from_slice(&num_glyphs.to_be_bytes()),
+        Err(_) => return too_many_names(),
+    }
+
+    let mac_glyph_names = MAC_GLYPH_NAMES
+        .iter()
+        .enumerate()
+        .map(|(index, &name)| (name, index))
+        .collect::<HashMap<_, _>>();
+    let mut custom_name_indices = HashMap::<&str, usize>::new();
+    let mut custom_names = Vec::<&str>::new();
+    for name in glyph_names {
+        let index = match mac_glyph_names.get(name.as_str()) {
+            Some(&index) => index,
+            None => *custom_name_indices.entry(name).or_insert_with(|| {
+                custom_names.push(name);
+                MAC_GLYPH_NAMES.len() + custom_names.len() - 1
+            }),
+        };
+        match u16::try_from(index) {
+            Ok(index) => new_post.extend_from_slice(&index.to_be_bytes()),
+            Err(_) => return too_many_names(),
+        }
+    }
+    // Pascal strings, names are limited to 255 bytes
+    for name in custom_names {
+        let name = &name.as_bytes()[..name.len().min(255)];
+        new_post.push(name.len() as u8);
+        new_post.extend_from_slice(name);
+    }
+    new_post
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_post_shares_names() {
+        let glyph_names = [".notdef", "A", "uni4E00", "uni4E00", "A.alt"].map(String::from);
+        let post = build_post(0, None, &glyph_names);
+        assert_eq!(post[..4], 0x00020000u32.to_be_bytes());
+        assert_eq!(post[32..34], 5u16.to_be_bytes());
+        // Standard names use the Macintosh ordering, custom names are shared
+        assert_eq!(post[34..44], [0, 0, 0, 36, 1, 2, 1, 2, 1, 3]);
+        assert_eq!(post[44..], *b"\x07uni4E00\x05A.alt");
+    }
+
+    #[test]
+    fn build_post_too_many_names() {
+        // One more custom name than the 16-bit indices can hold
+        let glyph_names = (0..=0x10000 - MAC_GLYPH_NAMES.len())
+            .map(|x| format!("glyph{}", x))
+            .collect::<Vec<_>>();
+        let mut raw_post = [0; 32];
+        raw_post[..6].clone_from_slice(&[0x00, 0x02, 0x00, 0x00, 0x12, 0x34]);
+        let post = build_post(0, Some(&raw_post), &glyph_names);
+        assert_eq!(post.len(), 32);
+        assert_eq!(post[..4], 0x00030000u32.to_be_bytes());
+        assert_eq!(post[4..], raw_post[4..]);
+    }
+}
@@ -0,0 +1,221 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::glyf::OutlinePoint;
+
+// An anti-aliased glyph image, rows from top to bottom.
+pub struct GrayscaleImage {
+    pub width: usize,
+    pub height: usize,
+    // Distance from the origin to the left edge, in pixels
+    pub left: i32,
+    // Distance from the baseline to the top edge, in pixels
+    pub top: i32,
+    // Coverage of each pixel, from 0.0 to 1.0
+    pub coverage: Vec<f32>,
+}
+
+// Renders TrueType contours without hinting, scaled by pixels per font unit.
+// Returns None if the glyph has no visible pixels.
+pub fn rasterize(contours: &[Vec<OutlinePoint>], scale: f32) -> Option<GrayscaleImage> {
+    let lines = contours
+        .iter()
+        .flat_map(|contour| flatten_contour(contour, scale))
+        .collect::<Vec<_>>();
+
+    let (mut x_min, mut y_min) = (f32::INFINITY, f32::INFINITY);
+    let (mut x_max, mut y_max) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for &((x, y), _) in lines.iter() {
+        x_min = x_min.min(x);
+        y_min = y_min.min(y);
+        x_max = x_max.max(x);
+        y_max = y_max.max(y);
+    }
+    if !(x_min < x_max && y_min < y_max) {
+        return None;
+    }
+    let left = x_min.floor();
+    let top = y_max.ceil();
+    let width = (x_max.ceil() - left) as usize;
+    let height = (top - y_min.floor()) as usize;
+
+    // Signed area accumulation, as used by font-rs and stb_truetype.
+    // One extra column on the right, so lines touching the right edge fit.
+    let stride = width + 1;
+    let mut accumulation = vec![0f32; stride * height + 1];
+    for &((x0, y0), (x1, y1)) in lines.iter() {
+        // Move into image space, y axis pointing down
+        draw_line(
+            &mut accumulation,
+            stride,
+            height,
+            (x0 - left, top - y0),
+            (x1 - left, top - y1),
+        );
+    }
+
+    let mut coverage = Vec::with_capacity(width * height);
+    for row in accumulation.chunks_exact(stride).take(height) {
+        let mut sum = 0.0;
+        for &x in row[..width].iter() {
+            sum += x;
+            coverage.push(sum.abs().min(1.0));
+        }
+    }
+    if coverage.iter().all(|&x| x < 0.5 / 255.0) {
+        return None;
+    }
+    Some(GrayscaleImage {
+        width,
+        height,
+        left: left as i32,
+        top: top as i32,
+        coverage,
+    })
+}
+
+type Line = ((f32, f32), (f32, f32));
+
+// Converts a closed quadratic contour into line segments in pixel space.
+fn flatten_contour(contour: &[OutlinePoint], scale: f32) -> Vec<Line> {
+    let mut lines = Vec::new();
+    if contour.is_empty() {
+        return lines;
+    }
+    let points = contour
+        .iter()
+        .map(|&(x, y, on_curve)| ((x * scale, y * scale), on_curve))
+        .collect::<Vec<_>>();
+    let midpoint = |a: (f32, f32), b: (f32, f32)| ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5);
+
+    // Start from an on-curve point, or the implied one between two off-curve points
+    let (start, first) = match points.iter().position(|x| x.1) {
+        Some(i) => (points[i].0, i),
+        None => (midpoint(points[0].0, points[points.len() - 1].0), 0),
+    };
+    let mut current = start;
+    let mut control = None;
+    for i in 0..points.len() {
+        let (point, on_curve) = points[(first + i + usize::from(points[first].1)) % points.len()];
+        match (on_curve, control) {
+            (true, None) => {
+                lines.push((current, point));
+                current = point;
+            }
+            (true, Some(c)) => {
+                flatten_quadratic(&mut lines, current, c, point);
+                current = point;
+                control = None;
+            }
+            (false, None) => control = Some(point),
+            (false, Some(c)) => {
+                let implied = midpoint(c, point);
+                flatten_quadratic(&mut lines, current, c, implied);
+                current = implied;
+                control = Some(point);
+            }
+        }
+    }
+    match control {
+        Some(c) => flatten_quadratic(&mut lines, current, c, start),
+        None => lines.push((current, start)),
+    }
+    lines
+}
+
+fn flatten_quadratic(lines: &mut Vec<Line>, p0: (f32, f32), p1: (f32, f32), p2: (f32, f32)) {
+    // Subdivide until the error is below 1/32 pixel
+    let ddx = p0.0 - 2.0 * p1.0 + p2.0;
+    let ddy = p0.1 - 2.0 * p1.1 + p2.1;
+    let segments = ((ddx * ddx + ddy * ddy).sqrt() * 4.0)
+        .sqrt()
+        .ceil()
+        .max(1.0) as usize;
+    let mut previous = p0;
+    for i in 1..=segments {
+        let t = i as f32 / segments as f32;
+        let u = 1.0 - t;
+        let point = (
+            u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p2.0,
+            u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p2.1,
+        );
+        lines.push((previous, point));
+        previous = point;
+    }
+}
+
+fn draw_line(
+    accumulation: &mut [f32],
+    stride: usize,
+    height: usize,
+    p0: (f32, f32),
+    p1: (f32, f32),
+) {
+    if p0.1 == p1.1 {
+        return;
+    }
+    let (direction, p0, p1) = if p0.1 < p1.1 {
+        (1.0, p0, p1)
+    } else {
+        (-1.0, p1, p0)
+    };
+    let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+    let mut x = p0.0;
+    let y_from = p0.1.max(0.0) as usize;
+    if p0.1 < 0.0 {
+        x -= p0.1 * dxdy;
+    }
+    let y_to = (p1.1.ceil().max(0.0) as usize).min(height);
+    for y in y_from..y_to {
+        let line_start = y * stride;
+        let dy = ((y + 1) as f32).min(p1.1) - (y as f32).max(p0.1);
+        let x_next = x + dxdy * dy;
+        let d = dy * direction;
+        let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+        // Clamp rounding errors at the image edges
+        let (x0, x1) = (x0.max(0.0), x1.min((stride - 1) as f32));
+        let x0_floor = x0.floor();
+        let x0_index = x0_floor as usize;
+        let x1_ceil = x1.ceil();
+        let x1_index = (x1_ceil as usize).max(x0_index + 1);
+        if x1_index <= x0_index + 1 {
+            // The segment stays within one pixel column
+            let x_mid = 0.5 * (x + x_next) - x0_floor;
+            accumulation[line_start + x0_index] += d - d * x_mid;
+            accumulation[line_start + x0_index + 1] += d * x_mid;
+        } else {
+            let s = (x1 - x0).recip();
+            let x0_fraction = x0 - x0_floor;
+            let a0 = 0.5 * s * (1.0 - x0_fraction) * (1.0 - x0_fraction);
+            let x1_fraction = x1 - x1_ceil + 1.0;
+            let a_max = 0.5 * s * x1_fraction * x1_fraction;
+            accumulation[line_start + x0_index] += d * a0;
+            if x1_index == x0_index + 2 {
+                accumulation[line_start + x0_index + 1] += d * (1.0 - a0 - a_max);
+            } else {
+                let a1 = s * (1.5 - x0_fraction);
+                accumulation[line_start + x0_index + 1] += d * (a1 - a0);
+                for xi in x0_index + 2..x1_index - 1 {
+                    accumulation[line_start + xi] += d * s;
+                }
+                let a2 = a1 + (x1_index - x0_index - 3) as f32 * s;
+                accumulation[line_start + x1_index - 1] += d * (1.0 - a2 - a_max);
+            }
+            accumulation[line_start + x1_index] += d * a_max;
+        }
+        x = x_next;
+    }
+}
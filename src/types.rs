@@ -21,6 +21,7 @@ use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Write;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 
 #[derive(Clone)]
@@ -49,6 +50,52 @@ pub struct TableRecord {
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FourCC(pub [u8; 4]);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GaspRange {
+    pub range_max_ppem: u16,
+    pub range_gasp_behavior: u16,
+}
+
+// Selects the glyphs that lose their instructions when hinting is only
+// partially removed
+#[derive(Clone, Debug, Default)]
+pub struct HintingFilter {
+    pub strip_unicode: Vec<RangeInclusive<u32>>,
+    pub strip_glyphs: Vec<GlyphSpec>,
+    pub keep_glyphs: Vec<GlyphSpec>,
+}
+
+// A glyph ID range, or a glyph name looked up through "post"
+#[derive(Clone, Debug)]
+pub enum GlyphSpec {
+    Range(RangeInclusive<u16>),
+    Name(String),
+}
+
+// Static instances to create from a variable font
+#[derive(Clone, Debug)]
+pub enum InstanceSelection {
+    // All named instances in "fvar"
+    Named,
+    // User coordinates by axis tag, axes not listed stay at their defaults
+    Locations(Vec<Vec<(FourCC, f32)>>),
+}
+
+// How much wider strokes become when emboldening outlines
+#[derive(Clone, Copy, Debug)]
+pub enum EmboldenStrength {
+    // In font units
+    Units(f32),
+    // In percent of the em size
+    Percent(f32),
+}
+
+// "gasp" rangeGaspBehavior flags
+pub const GASP_GRIDFIT: u16 = 0x0001;
+pub const GASP_DOGRAY: u16 = 0x0002;
+pub const GASP_SYMMETRIC_GRIDFIT: u16 = 0x0004;
+pub const GASP_SYMMETRIC_SMOOTHING: u16 = 0x0008;
+
 impl SfntHeader {
     pub fn search_range(&self) -> u16 {
         if self.table_records.is_empty() {
@@ -111,6 +158,35 @@ impl Debug for FourCC {
     }
 }
 
+impl Display for GaspRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const FLAG_NAMES: [(u16, &str); 4] = [
+            (GASP_GRIDFIT, "gridfit"),
+            (GASP_DOGRAY, "gray"),
+            (GASP_SYMMETRIC_GRIDFIT, "symmetric-gridfit"),
+            (GASP_SYMMETRIC_SMOOTHING, "symmetric-smoothing"),
+        ];
+
+        write!(f, "{}:", self.range_max_ppem)?;
+        let mut behavior = self.range_gasp_behavior;
+        if behavior == 0 {
+            return f.write_str("none");
+        }
+        let mut separator = "";
+        for &(flag, name) in FLAG_NAMES.iter() {
+            if behavior & flag != 0 {
+                write!(f, "{}{}", separator, name)?;
+                behavior &= !flag;
+                separator = "+";
+            }
+        }
+        if behavior != 0 {
+            write!(f, "{}0x{:04x}", separator, behavior)?;
+        }
+        Ok(())
+    }
+}
+
 impl Debug for TTCHeader {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("TTC")
@@ -0,0 +1,1569 @@
+// FaithType
+// Copyright (C) 2021  Star Brilliant <coder@poorlab.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+
+use super::glyf;
+use super::name::NameTable;
+use super::types::FourCC;
+use super::types::SfntHeader;
+use super::types::TableRecord;
+
+// Tables that only make sense in a variable font
+const VARIATION_TABLES: [FourCC; 7] = [
+    FourCC(*b"avar"),
+    FourCC(*b"cvar"),
+    FourCC(*b"fvar"),
+    FourCC(*b"gvar"),
+    FourCC(*b"HVAR"),
+    FourCC(*b"MVAR"),
+    FourCC(*b"VVAR"),
+];
+
+// "MVAR" value tags, and the table and byte offset of the field they vary
+const MVAR_FIELDS: [(FourCC, FourCC, usize); 28] = [
+    (FourCC(*b"hasc"), FourCC(*b"OS/2"), 68), // sTypoAscender
+    (FourCC(*b"hdsc"), FourCC(*b"OS/2"), 70), // sTypoDescender
+    (FourCC(*b"hlgp"), FourCC(*b"OS/2"), 72), // sTypoLineGap
+    (FourCC(*b"hcla"), FourCC(*b"OS/2"), 74), // usWinAscent
+    (FourCC(*b"hcld"), FourCC(*b"OS/2"), 76), // usWinDescent
+    (FourCC(*b"xhgt"), FourCC(*b"OS/2"), 86), // sxHeight
+    (FourCC(*b"cpht"), FourCC(*b"OS/2"), 88), // sCapHeight
+    (FourCC(*b"sbxs"), FourCC(*b"OS/2"), 10), // ySubscriptXSize
+    (FourCC(*b"sbys"), FourCC(*b"OS/2"), 12), // ySubscriptYSize
+    (FourCC(*b"sbxo"), FourCC(*b"OS/2"), 14), // ySubscriptXOffset
+    (FourCC(*b"sbyo"), FourCC(*b"OS/2"), 16), // ySubscriptYOffset
+    (FourCC(*b"spxs"), FourCC(*b"OS/2"), 18), // ySuperscriptXSize
+    (FourCC(*b"spys"), FourCC(*b"OS/2"), 20), // ySuperscriptYSize
+    (FourCC(*b"spxo"), FourCC(*b"OS/2"), 22), // ySuperscriptXOffset
+    (FourCC(*b"spyo"), FourCC(*b"OS/2"), 24), // ySuperscriptYOffset
+    (FourCC(*b"strs"), FourCC(*b"OS/2"), 26), // yStrikeoutSize
+    (FourCC(*b"stro"), FourCC(*b"OS/2"), 28), // yStrikeoutPosition
+    (FourCC(*b"hcrs"), FourCC(*b"hhea"), 18), // caretSlopeRise
+    (FourCC(*b"hcrn"), FourCC(*b"hhea"), 20), // caretSlopeRun
+    (FourCC(*b"hcof"), FourCC(*b"hhea"), 22), // caretOffset
+    (FourCC(*b"undo"), FourCC(*b"post"), 8),  // underlinePosition
+    (FourCC(*b"unds"), FourCC(*b"post"), 10), // underlineThickness
+    (FourCC(*b"vasc"), FourCC(*b"vhea"), 4),  // ascent
+    (FourCC(*b"vdsc"), FourCC(*b"vhea"), 6),  // descent
+    (FourCC(*b"vlgp"), FourCC(*b"vhea"), 8),  // lineGap
+    (FourCC(*b"vcrs"), FourCC(*b"vhea"), 18), // caretSlopeRise
+    (FourCC(*b"vcrn"), FourCC(*b"vhea"), 20), // caretSlopeRun
+    (FourCC(*b"vcof"), FourCC(*b"vhea"), 22), // caretOffset
+];
+
+// Subfamily names that fit in the legacy four-style family model
+const RIBBI_STYLES: [&str; 4] = ["Regular", "Bold", "Italic", "Bold Italic"];
+
+pub struct Fvar {
+    pub axes: Vec<Axis>,
+    pub instances: Vec<NamedInstance>,
+}
+
+pub struct Axis {
+    pub tag: FourCC,
+    pub min_value: f32,
+    pub default_value: f32,
+    pub max_value: f32,
+    pub name_id: u16,
+}
+
+pub struct NamedInstance {
+    pub subfamily_name_id: u16,
+    pub post_script_name_id: Option<u16>,
+    // User coordinate of each axis
+    pub coordinates: Vec<f32>,
+}
+
+// Reads big-endian values from a table, reporting truncated data.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    sfnt_index: usize,
+    table_tag: FourCC,
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(sfnt_index: usize, table_tag: FourCC, data: &'a [u8]) -> Self {
+        Self {
+            sfnt_index,
+            table_tag,
+            data,
+        }
+    }
+
+    // A reader for a subtable, with offsets relative to its start
+    fn subtable(&self, offset: usize) -> Result<Self> {
+        Ok(Self {
+            data: self.get(offset, self.data.len().max(offset))?,
+            ..*self
+        })
+    }
+
+    fn get(&self, from: usize, to: usize) -> Result<&'a [u8]> {
+        self.data.get(from..to).ok_or_else(|| {
+            anyhow!(
+                "sfnt {} table {}: data truncated ({}..{})",
+                self.sfnt_index,
+                self.table_tag,
+                from,
+                to
+            )
+        })
+    }
+
+    fn u8(&self, pos: usize) -> Result<u8> {
+        Ok(self.get(pos, pos + 1)?[0])
+    }
+
+    fn u16(&self, pos: usize) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.get(pos, pos + 2)?.try_into()?))
+    }
+
+    fn i16(&self, pos: usize) -> Result<i16> {
+        Ok(self.u16(pos)? as i16)
+    }
+
+    fn u32(&self, pos: usize) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.get(pos, pos + 4)?.try_into()?))
+    }
+
+    fn offset32(&self, pos: usize) -> Result<usize> {
+        Ok(usize::try_from(self.u32(pos)?)?)
+    }
+
+    fn fixed(&self, pos: usize) -> Result<f32> {
+        Ok(self.u32(pos)? as i32 as f32 / 65536.0)
+    }
+
+    fn f2dot14(&self, pos: usize) -> Result<f32> {
+        Ok(f32::from(self.i16(pos)?) / 16384.0)
+    }
+}
+
+pub fn read_fvar(sfnt_index: usize, raw_fvar: &[u8]) -> Result<Fvar> {
+    let r = Reader::new(sfnt_index, b"fvar".into(), raw_fvar);
+    // byte 0..2: majorVersion
+    // byte 4..6: axesArrayOffset
+    // byte 8..10: axisCount
+    // byte 10..12: axisSize
+    // byte 12..14: instanceCount
+    // byte 14..16: instanceSize
+    let major_version = r.u16(0)?;
+    if major_version != 1 {
+        bail!(
+            "sfnt {} table “fvar”: unsupported version {}",
+            sfnt_index,
+            major_version
+        );
+    }
+    let axes_offset = usize::from(r.u16(4)?);
+    let axis_count = usize::from(r.u16(8)?);
+    let axis_size = usize::from(r.u16(10)?);
+    let instance_count = usize::from(r.u16(12)?);
+    let instance_size = usize::from(r.u16(14)?);
+    if axis_size < 20 || instance_size < 4 + axis_count * 4 {
+        bail!(
+            "sfnt {} table “fvar”: invalid record sizes ({}, {})",
+            sfnt_index,
+            axis_size,
+            instance_size
+        );
+    }
+
+    let mut axes = Vec::with_capacity(axis_count);
+    for i in 0..axis_count {
+        let pos = axes_offset + i * axis_size;
+        axes.push(Axis {
+            tag: FourCC(r.get(pos, pos + 4)?.try_into()?),
+            min_value: r.fixed(pos + 4)?,
+            default_value: r.fixed(pos + 8)?,
+            max_value: r.fixed(pos + 12)?,
+            name_id: r.u16(pos + 18)?,
+        });
+    }
+    let mut instances = Vec::with_capacity(instance_count);
+    for i in 0..instance_count {
+        let pos = axes_offset + axis_count * axis_size + i * instance_size;
+        instances.push(NamedInstance {
+            subfamily_name_id: r.u16(pos)?,
+            post_script_name_id: if instance_size >= 6 + axis_count * 4 {
+                Some(r.u16(pos + 4 + axis_count * 4)?).filter(|&x| x != 0xffff)
+            } else {
+                None
+            },
+            coordinates: (0..axis_count)
+                .map(|j| r.fixed(pos + 4 + j * 4))
+                .collect::<Result<_>>()?,
+        });
+    }
+    Ok(Fvar { axes, instances })
+}
+
+// Returns the "avar" segment maps of each axis.
+fn read_avar(
+    sfnt_index: usize,
+    raw_avar: &[u8],
+    axis_count: usize,
+) -> Result<Vec<Vec<(f32, f32)>>> {
+    let r = Reader::new(sfnt_index, b"avar".into(), raw_avar);
+    // byte 0..2: majorVersion
+    // byte 6..8: axisCount
+    let major_version = r.u16(0)?;
+    if major_version != 1 && major_version != 2 {
+        bail!(
+            "sfnt {} table “avar”: unsupported version {}",
+            sfnt_index,
+            major_version
+        );
+    }
+    if major_version == 2 {
+        eprintln!(
+            "[ WARN ] sfnt {} table “avar”: ignoring version 2 axis variations",
+            sfnt_index
+        );
+    }
+    if usize::from(r.u16(6)?) != axis_count {
+        bail!(
+            "sfnt {} table “avar”: axis count does not match “fvar”",
+            sfnt_index
+        );
+    }
+    let mut pos = 8;
+    let mut segment_maps = Vec::with_capacity(axis_count);
+    for _ in 0..axis_count {
+        let position_map_count = usize::from(r.u16(pos)?);
+        pos += 2;
+        let mut segment_map = Vec::with_capacity(position_map_count);
+        for _ in 0..position_map_count {
+            segment_map.push((r.f2dot14(pos)?, r.f2dot14(pos + 2)?));
+            pos += 4;
+        }
+        segment_maps.push(segment_map);
+    }
+    Ok(segment_maps)
+}
+
+// Fills in the axes missing from a list of user coordinates with defaults,
+// and clamps the others to the axis ranges.
+pub fn user_location(sfnt_index: usize, fvar: &Fvar, coordinates: &[(FourCC, f32)]) -> Vec<f32> {
+    for (tag, _) in coordinates.iter() {
+        if !fvar.axes.iter().any(|x| x.tag == *tag) {
+            eprintln!(
+                "[ WARN ] sfnt {} table “fvar”: no axis {}, ignoring",
+                sfnt_index, tag
+            );
+        }
+    }
+    fvar.axes
+        .iter()
+        .map(|axis| {
+            let value = coordinates
+                .iter()
+                .rev()
+                .find(|(tag, _)| *tag == axis.tag)
+                .map_or(axis.default_value, |&(_, value)| value);
+            if value < axis.min_value || value > axis.max_value {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “fvar”: axis {} value {} is out of range ({}–{}), clamping",
+                    sfnt_index, axis.tag, value, axis.min_value, axis.max_value
+                );
+            }
+            value.max(axis.min_value).min(axis.max_value)
+        })
+        .collect()
+}
+
+pub fn format_location(fvar: &Fvar, location: &[f32]) -> String {
+    fvar.axes
+        .iter()
+        .zip(location.iter())
+        .map(|(axis, value)| {
+            format!(
+                "{}={}",
+                String::from_utf8_lossy(&axis.tag.0).trim_end(),
+                value
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Maps user coordinates to normalized coordinates in -1..=1.
+fn normalize_location(
+    axes: &[Axis],
+    segment_maps: Option<&[Vec<(f32, f32)>]>,
+    location: &[f32],
+) -> Vec<f32> {
+    // Normalized coordinates are stored as F2DOT14
+    let quantize = |x: f32| (x * 16384.0).round() / 16384.0;
+    axes.iter()
+        .zip(location.iter())
+        .enumerate()
+        .map(|(i, (axis, &value))| {
+            let value = value.max(axis.min_value).min(axis.max_value);
+            let normalized = if value < axis.default_value {
+                (value - axis.default_value) / (axis.default_value - axis.min_value)
+            } else if value > axis.default_value {
+                (value - axis.default_value) / (axis.max_value - axis.default_value)
+            } else {
+                0.0
+            };
+            let normalized = quantize(normalized);
+            match segment_maps.and_then(|x| x.get(i)) {
+                Some(segment_map) if !segment_map.is_empty() => {
+                    quantize(map_segments(segment_map, normalized))
+                }
+                _ => normalized,
+            }
+        })
+        .collect()
+}
+
+// Piecewise linear mapping through the "avar" segment map of an axis.
+fn map_segments(segment_map: &[(f32, f32)], value: f32) -> f32 {
+    let first = segment_map[0];
+    let last = segment_map[segment_map.len() - 1];
+    if value <= first.0 {
+        return value + first.1 - first.0;
+    }
+    if value >= last.0 {
+        return value + last.1 - last.0;
+    }
+    for segment in segment_map.windows(2) {
+        let ((from_a, to_a), (from_b, to_b)) = (segment[0], segment[1]);
+        if value == from_a {
+            return to_a;
+        }
+        if value < from_b {
+            return to_a + (to_b - to_a) * (value - from_a) / (from_b - from_a);
+        }
+    }
+    value
+}
+
+// Scalar of a region at the normalized location, given the start, peak and
+// end coordinates of each axis.
+fn region_scalar(location: &[f32], regions: impl Iterator<Item = (f32, f32, f32)>) -> f32 {
+    let mut scalar = 1.0;
+    for (&value, (start, peak, end)) in location.iter().zip(regions) {
+        // Axes without a peak, or with an invalid region, are ignored
+        if peak == 0.0 || start > peak || peak > end || (start < 0.0 && end > 0.0) {
+            continue;
+        }
+        if value == peak {
+            continue;
+        }
+        if value <= start || value >= end {
+            return 0.0;
+        }
+        scalar *= if value < peak {
+            (value - start) / (peak - start)
+        } else {
+            (end - value) / (end - peak)
+        };
+    }
+    scalar
+}
+
+// An ItemVariationStore with the region scalars evaluated at a location.
+struct ItemVariationStore<'a> {
+    r: Reader<'a>,
+    region_scalars: Vec<f32>,
+    item_variation_data_offsets: Vec<usize>,
+}
+
+impl<'a> ItemVariationStore<'a> {
+    fn new(r: Reader<'a>, location: &[f32]) -> Result<Self> {
+        // byte 0..2: format
+        // byte 2..6: variationRegionListOffset
+        // byte 6..8: itemVariationDataCount
+        let format = r.u16(0)?;
+        if format != 1 {
+            bail!(
+                "sfnt {} table {}: unsupported ItemVariationStore format {}",
+                r.sfnt_index,
+                r.table_tag,
+                format
+            );
+        }
+        let region_list = r.subtable(r.offset32(2)?)?;
+        let axis_count = usize::from(region_list.u16(0)?);
+        let region_count = usize::from(region_list.u16(2)?);
+        let region_scalars = (0..region_count)
+            .map(|i| {
+                let pos = 4 + i * axis_count * 6;
+                let regions = (0..axis_count)
+                    .map(|j| {
+                        let pos = pos + j * 6;
+                        Ok((
+                            region_list.f2dot14(pos)?,
+                            region_list.f2dot14(pos + 2)?,
+                            region_list.f2dot14(pos + 4)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(region_scalar(location, regions.into_iter()))
+            })
+            .collect::<Result<_>>()?;
+        let item_variation_data_offsets = (0..usize::from(r.u16(6)?))
+            .map(|i| r.offset32(8 + i * 4))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            r,
+            region_scalars,
+            item_variation_data_offsets,
+        })
+    }
+
+    fn delta(&self, outer: u16, inner: u16) -> Result<f32> {
+        let invalid = || {
+            anyhow!(
+                "sfnt {} table {}: invalid delta set index ({}, {})",
+                self.r.sfnt_index,
+                self.r.table_tag,
+                outer,
+                inner
+            )
+        };
+        let data = self.r.subtable(
+            *self
+                .item_variation_data_offsets
+                .get(usize::from(outer))
+                .ok_or_else(invalid)?,
+        )?;
+        // byte 0..2: itemCount
+        // byte 2..4: wordDeltaCount
+        // byte 4..6: regionIndexCount
+        if inner >= data.u16(0)? {
+            return Err(invalid());
+        }
+        let word_delta_count = data.u16(2)?;
+        // LONG_WORDS
+        let (word_size, short_size) = if word_delta_count & 0x8000 != 0 {
+            (4, 2)
+        } else {
+            (2, 1)
+        };
+        let word_count = usize::from(word_delta_count & 0x7fff);
+        let region_index_count = usize::from(data.u16(4)?);
+        let row_size =
+            word_count * word_size + (region_index_count.saturating_sub(word_count)) * short_size;
+        let mut pos = 6 + region_index_count * 2 + usize::from(inner) * row_size;
+        let mut delta = 0.0;
+        for i in 0..region_index_count {
+            let size = if i < word_count {
+                word_size
+            } else {
+                short_size
+            };
+            let value = match size {
+                1 => f32::from(data.u8(pos)? as i8),
+                2 => f32::from(data.i16(pos)?),
+                _ => data.u32(pos)? as i32 as f32,
+            };
+            pos += size;
+            let region_index = usize::from(data.u16(6 + i * 2)?);
+            delta += value
+                * self
+                    .region_scalars
+                    .get(region_index)
+                    .copied()
+                    .unwrap_or(0.0);
+        }
+        Ok(delta)
+    }
+}
+
+// Looks up the delta set index of an item in a DeltaSetIndexMap.
+fn read_delta_set_index(r: Reader, index: usize) -> Result<(u16, u16)> {
+    // byte 0: format
+    // byte 1: entryFormat
+    // byte 2..: mapCount, 16-bit in format 0 and 32-bit in format 1
+    let format = r.u8(0)?;
+    let entry_format = r.u8(1)?;
+    let (map_count, entries_pos) = match format {
+        0 => (usize::from(r.u16(2)?), 4),
+        1 => (r.offset32(2)?, 6),
+        _ => bail!(
+            "sfnt {} table {}: unsupported DeltaSetIndexMap format {}",
+            r.sfnt_index,
+            r.table_tag,
+            format
+        ),
+    };
+    if map_count == 0 {
+        bail!(
+            "sfnt {} table {}: empty DeltaSetIndexMap",
+            r.sfnt_index,
+            r.table_tag
+        );
+    }
+    let entry_size = usize::from((entry_format >> 4) & 0x03) + 1;
+    let inner_bit_count = u32::from(entry_format & 0x0f) + 1;
+    let pos = entries_pos + index.min(map_count - 1) * entry_size;
+    let entry = r
+        .get(pos, pos + entry_size)?
+        .iter()
+        .fold(0u32, |acc, &x| acc << 8 | u32::from(x));
+    Ok((
+        (entry >> inner_bit_count) as u16,
+        (entry & ((1 << inner_bit_count) - 1)) as u16,
+    ))
+}
+
+// Advance width or height deltas from "HVAR" or "VVAR".
+struct MetricsVariations<'a> {
+    store: ItemVariationStore<'a>,
+    advance_mapping: Option<Reader<'a>>,
+}
+
+impl<'a> MetricsVariations<'a> {
+    fn new(r: Reader<'a>, location: &[f32]) -> Result<Self> {
+        // byte 4..8: itemVariationStoreOffset
+        // byte 8..12: advanceWidthMappingOffset / advanceHeightMappingOffset
+        let store = ItemVariationStore::new(r.subtable(r.offset32(4)?)?, location)?;
+        let advance_mapping = match r.offset32(8)? {
+            0 => None,
+            offset => Some(r.subtable(offset)?),
+        };
+        Ok(Self {
+            store,
+            advance_mapping,
+        })
+    }
+
+    fn advance_delta(&self, glyph_index: usize) -> Result<f32> {
+        let (outer, inner) = match self.advance_mapping {
+            Some(r) => read_delta_set_index(r, glyph_index)?,
+            None => (0, u16::try_from(glyph_index)?),
+        };
+        self.store.delta(outer, inner)
+    }
+}
+
+struct Gvar<'a> {
+    r: Reader<'a>,
+    axis_count: usize,
+    shared_tuples: Vec<Vec<f32>>,
+    // Start and end of the variation data of each glyph
+    glyph_data_offsets: Vec<usize>,
+}
+
+impl<'a> Gvar<'a> {
+    fn new(sfnt_index: usize, raw_gvar: &'a [u8]) -> Result<Self> {
+        let r = Reader::new(sfnt_index, b"gvar".into(), raw_gvar);
+        // byte 0..2: majorVersion
+        // byte 4..6: axisCount
+        // byte 6..8: sharedTupleCount
+        // byte 8..12: sharedTuplesOffset
+        // byte 12..14: glyphCount
+        // byte 14..16: flags
+        // byte 16..20: glyphVariationDataArrayOffset
+        let major_version = r.u16(0)?;
+        if major_version != 1 {
+            bail!(
+                "sfnt {} table “gvar”: unsupported version {}",
+                sfnt_index,
+                major_version
+            );
+        }
+        let axis_count = usize::from(r.u16(4)?);
+        let shared_tuples_offset = r.offset32(8)?;
+        let shared_tuples = (0..usize::from(r.u16(6)?))
+            .map(|i| {
+                (0..axis_count)
+                    .map(|j| r.f2dot14(shared_tuples_offset + (i * axis_count + j) * 2))
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+        let glyph_count = usize::from(r.u16(12)?);
+        let long_offsets = r.u16(14)? & 0x0001 != 0;
+        let data_offset = r.offset32(16)?;
+        let glyph_data_offsets = (0..=glyph_count)
+            .map(|i| {
+                Ok(data_offset
+                    + if long_offsets {
+                        r.offset32(20 + i * 4)?
+                    } else {
+                        usize::from(r.u16(20 + i * 2)?) * 2
+                    })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            r,
+            axis_count,
+            shared_tuples,
+            glyph_data_offsets,
+        })
+    }
+
+    // Returns the delta of each point, including the four phantom points.
+    // `points` are the original coordinates, and `end_points` the last point
+    // of each contour, used to infer the deltas of untouched points in simple
+    // glyphs.
+    fn glyph_deltas(
+        &self,
+        glyph_index: usize,
+        location: &[f32],
+        points: &[(f32, f32)],
+        end_points: &[usize],
+    ) -> Result<Vec<(f32, f32)>> {
+        let mut deltas = vec![(0.0, 0.0); points.len()];
+        let (from, to) = match (
+            self.glyph_data_offsets.get(glyph_index),
+            self.glyph_data_offsets.get(glyph_index + 1),
+        ) {
+            (Some(&from), Some(&to)) if from < to => (from, to),
+            _ => return Ok(deltas),
+        };
+        let r = Reader {
+            data: self.r.get(from, to)?,
+            ..self.r
+        };
+        let invalid = || {
+            anyhow!(
+                "sfnt {} table “gvar”: glyph {} has invalid variation data",
+                r.sfnt_index,
+                glyph_index
+            )
+        };
+
+        // byte 0..2: tupleVariationCount
+        // byte 2..4: dataOffset
+        let tuple_variation_count = r.u16(0)?;
+        let mut data_pos = usize::from(r.u16(2)?);
+        // SHARED_POINT_NUMBERS
+        let shared_points = if tuple_variation_count & 0x8000 != 0 {
+            read_packed_points(r, &mut data_pos)?
+        } else {
+            None
+        };
+        let mut header_pos = 4;
+        for _ in 0..tuple_variation_count & 0x0fff {
+            // byte 0..2: variationDataSize
+            // byte 2..4: tupleIndex
+            let variation_data_size = usize::from(r.u16(header_pos)?);
+            let tuple_index = r.u16(header_pos + 2)?;
+            header_pos += 4;
+            let read_tuple = |pos: &mut usize| -> Result<Vec<f32>> {
+                let tuple = (0..self.axis_count)
+                    .map(|i| r.f2dot14(*pos + i * 2))
+                    .collect();
+                *pos += self.axis_count * 2;
+                tuple
+            };
+            // EMBEDDED_PEAK_TUPLE
+            let peak = if tuple_index & 0x8000 != 0 {
+                read_tuple(&mut header_pos)?
+            } else {
+                self.shared_tuples
+                    .get(usize::from(tuple_index & 0x0fff))
+                    .ok_or_else(invalid)?
+                    .clone()
+            };
+            // INTERMEDIATE_REGION
+            let (start, end) = if tuple_index & 0x4000 != 0 {
+                (read_tuple(&mut header_pos)?, read_tuple(&mut header_pos)?)
+            } else {
+                (
+                    peak.iter().map(|&x| x.min(0.0)).collect(),
+                    peak.iter().map(|&x| x.max(0.0)).collect(),
+                )
+            };
+            let scalar = region_scalar(
+                location,
+                (0..self.axis_count).map(|i| (start[i], peak[i], end[i])),
+            );
+            let next_data_pos = data_pos + variation_data_size;
+            if scalar == 0.0 {
+                data_pos = next_data_pos;
+                continue;
+            }
+
+            // PRIVATE_POINT_NUMBERS
+            let tuple_points = if tuple_index & 0x2000 != 0 {
+                read_packed_points(r, &mut data_pos)?
+            } else {
+                shared_points.clone()
+            };
+            let num_deltas = tuple_points.as_ref().map_or(points.len(), |x| x.len());
+            let x_deltas = read_packed_deltas(r, &mut data_pos, num_deltas)?;
+            let y_deltas = read_packed_deltas(r, &mut data_pos, num_deltas)?;
+            data_pos = next_data_pos;
+
+            match tuple_points {
+                None => {
+                    for (delta, (dx, dy)) in
+                        deltas.iter_mut().zip(x_deltas.into_iter().zip(y_deltas))
+                    {
+                        delta.0 += scalar * dx;
+                        delta.1 += scalar * dy;
+                    }
+                }
+                Some(tuple_points) => {
+                    let mut tuple_deltas = vec![(0.0, 0.0); points.len()];
+                    let mut touched = vec![false; points.len()];
+                    for (i, &point) in tuple_points.iter().enumerate() {
+                        if let Some(x) = tuple_deltas.get_mut(point) {
+                            *x = (x_deltas[i], y_deltas[i]);
+                            touched[point] = true;
+                        }
+                    }
+                    infer_deltas(points, end_points, &touched, &mut tuple_deltas);
+                    for (delta, (dx, dy)) in deltas.iter_mut().zip(tuple_deltas) {
+                        delta.0 += scalar * dx;
+                        delta.1 += scalar * dy;
+                    }
+                }
+            }
+        }
+        Ok(deltas)
+    }
+}
+
+// Reads packed point numbers, None if all points are referenced.
+fn read_packed_points(r: Reader, pos: &mut usize) -> Result<Option<Vec<usize>>> {
+    let mut count = usize::from(r.u8(*pos)?);
+    *pos += 1;
+    // POINTS_ARE_WORDS in the count
+    if count & 0x80 != 0 {
+        count = (count & 0x7f) << 8 | usize::from(r.u8(*pos)?);
+        *pos += 1;
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    let mut points = Vec::with_capacity(count);
+    let mut point = 0;
+    while points.len() < count {
+        let control = r.u8(*pos)?;
+        *pos += 1;
+        for _ in 0..=(control & 0x7f) {
+            // POINTS_ARE_WORDS
+            if control & 0x80 != 0 {
+                point += usize::from(r.u16(*pos)?);
+                *pos += 2;
+            } else {
+                point += usize::from(r.u8(*pos)?);
+                *pos += 1;
+            }
+            points.push(point);
+        }
+    }
+    points.truncate(count);
+    Ok(Some(points))
+}
+
+fn read_packed_deltas(r: Reader, pos: &mut usize, count: usize) -> Result<Vec<f32>> {
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count {
+        let control = r.u8(*pos)?;
+        *pos += 1;
+        for _ in 0..=(control & 0x3f) {
+            // DELTAS_ARE_ZERO, DELTAS_ARE_WORDS, or both for 32-bit deltas
+            let delta = match control & 0xc0 {
+                0x80 => 0.0,
+                0x40 => {
+                    *pos += 2;
+                    f32::from(r.i16(*pos - 2)?)
+                }
+                0xc0 => {
+                    *pos += 4;
+                    r.u32(*pos - 4)? as i32 as f32
+                }
+                _ => {
+                    *pos += 1;
+                    f32::from(r.u8(*pos - 1)? as i8)
+                }
+            };
+            deltas.push(delta);
+        }
+    }
+    deltas.truncate(count);
+    Ok(deltas)
+}
+
+// Interpolates the deltas of untouched points from the touched points
+// around them in the same contour.
+fn infer_deltas(
+    points: &[(f32, f32)],
+    end_points: &[usize],
+    touched: &[bool],
+    deltas: &mut [(f32, f32)],
+) {
+    let mut start = 0;
+    for &end in end_points.iter() {
+        let contour = (start..=end).filter(|&i| touched[i]).collect::<Vec<_>>();
+        if contour.len() == 1 {
+            let delta = deltas[contour[0]];
+            for delta_i in deltas[start..=end].iter_mut() {
+                *delta_i = delta;
+            }
+        } else if contour.len() > 1 {
+            for (k, &a) in contour.iter().enumerate() {
+                let b = contour[(k + 1) % contour.len()];
+                // Untouched points from a to b, wrapping around the contour
+                let mut i = if a == end { start } else { a + 1 };
+                while i != b {
+                    let interpolate = |axis: fn(&(f32, f32)) -> f32| {
+                        let (pa, pb, p) = (axis(&points[a]), axis(&points[b]), axis(&points[i]));
+                        let (da, db) = (axis(&deltas[a]), axis(&deltas[b]));
+                        if pa == pb {
+                            return if da == db { da } else { 0.0 };
+                        }
+                        let ((p1, d1), (p2, d2)) = if pa < pb {
+                            ((pa, da), (pb, db))
+                        } else {
+                            ((pb, db), (pa, da))
+                        };
+                        if p <= p1 {
+                            d1
+                        } else if p >= p2 {
+                            d2
+                        } else {
+                            d1 + (p - p1) * (d2 - d1) / (p2 - p1)
+                        }
+                    };
+                    deltas[i] = (interpolate(|x| x.0), interpolate(|x| x.1));
+                    i = if i == end { start } else { i + 1 };
+                }
+            }
+        }
+        start = end + 1;
+    }
+}
+
+// Creates a static instance at the user coordinates of each axis. Variation
+// tables are removed, and the names and style fields describe the instance.
+pub fn instantiate(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    fvar: &Fvar,
+    location: &[f32],
+) -> Result<SfntHeader> {
+    let raw_table = |table_tag: &[u8; 4]| {
+        sfnt.table_records
+            .get(&FourCC(*table_tag))
+            .map(|x| x.raw_data.as_ref())
+    };
+    let segment_maps = raw_table(b"avar")
+        .map(|x| read_avar(sfnt_index, x, fvar.axes.len()))
+        .transpose()?;
+    let normalized = normalize_location(&fvar.axes, segment_maps.as_deref(), location);
+
+    let mut instance = sfnt.clone();
+    for table_tag in VARIATION_TABLES.iter() {
+        instance.table_records.remove(table_tag);
+    }
+    if let Some(raw_gvar) = raw_table(b"gvar") {
+        let hvar = raw_table(b"HVAR")
+            .map(|x| {
+                MetricsVariations::new(Reader::new(sfnt_index, b"HVAR".into(), x), &normalized)
+            })
+            .transpose()?;
+        let vvar = raw_table(b"VVAR")
+            .map(|x| {
+                MetricsVariations::new(Reader::new(sfnt_index, b"VVAR".into(), x), &normalized)
+            })
+            .transpose()?;
+        let tables = instantiate_glyphs(
+            sfnt_index,
+            sfnt,
+            &Gvar::new(sfnt_index, raw_gvar)?,
+            &normalized,
+            hvar.as_ref(),
+            vvar.as_ref(),
+        )?;
+        glyf::set_glyf(
+            &mut instance,
+            Rc::from(tables.glyf),
+            Rc::from(tables.loca),
+            tables.loca_format,
+        );
+        for (table_tag, raw_data) in tables.metrics {
+            set_table(&mut instance, table_tag, raw_data);
+        }
+        if let Some(head) = instance.table_records.get_mut(&b"head".into()) {
+            let mut new_head = head.raw_data.to_vec();
+            // byte 36..44: xMin, yMin, xMax, yMax
+            if let Some(x) = new_head.get_mut(36..44) {
+                for (i, value) in tables.bbox.iter().enumerate() {
+                    x[i * 2..i * 2 + 2].clone_from_slice(&value.to_be_bytes());
+                }
+            }
+            head.raw_data = Rc::from(new_head);
+        }
+    }
+
+    if let Some(raw_mvar) = raw_table(b"MVAR") {
+        for (table_tag, raw_data) in apply_mvar(sfnt_index, &instance, raw_mvar, &normalized)? {
+            set_table(&mut instance, table_tag, raw_data);
+        }
+    }
+
+    let style = InstanceStyle::new(sfnt_index, sfnt, fvar, location)?;
+    if let Some(raw_name) = raw_table(b"name") {
+        let new_name = style.rename(sfnt_index, raw_name)?;
+        set_table(&mut instance, b"name", new_name);
+    }
+    if let Some(os2) = instance.table_records.get_mut(&b"OS/2".into()) {
+        let mut new_os2 = os2.raw_data.to_vec();
+        // byte 4..6: usWeightClass
+        // byte 6..8: usWidthClass
+        // byte 62..64: fsSelection
+        if let (Some(weight_class), Some(x)) = (style.weight_class, new_os2.get_mut(4..6)) {
+            x.clone_from_slice(&weight_class.to_be_bytes());
+        }
+        if let (Some(width_class), Some(x)) = (style.width_class, new_os2.get_mut(6..8)) {
+            x.clone_from_slice(&width_class.to_be_bytes());
+        }
+        if let Some(x) = new_os2.get_mut(62..64) {
+            // ITALIC, BOLD, REGULAR
+            let mut fs_selection = u16::from_be_bytes([x[0], x[1]]) & !0x0061;
+            if style.italic {
+                fs_selection |= 0x0001;
+            }
+            if style.bold {
+                fs_selection |= 0x0020;
+            }
+            if !style.italic && !style.bold {
+                fs_selection |= 0x0040;
+            }
+            x.clone_from_slice(&fs_selection.to_be_bytes());
+        }
+        os2.raw_data = Rc::from(new_os2);
+    }
+    if let Some(head) = instance.table_records.get_mut(&b"head".into()) {
+        let mut new_head = head.raw_data.to_vec();
+        // byte 44..46: macStyle, bit 0 bold and bit 1 italic
+        if let Some(x) = new_head.get_mut(45) {
+            *x = (*x & !0x03) | u8::from(style.bold) | u8::from(style.italic) << 1;
+        }
+        head.raw_data = Rc::from(new_head);
+    }
+    Ok(instance)
+}
+
+fn set_table(sfnt: &mut SfntHeader, table_tag: &[u8; 4], raw_data: Vec<u8>) {
+    sfnt.table_records.insert(
+        FourCC(*table_tag),
+        TableRecord {
+            checksum: 0,
+            offset: 0,
+            raw_data: Rc::from(raw_data),
+        },
+    );
+}
+
+// Tables rebuilt from the instanced glyphs
+struct InstancedGlyphs {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    loca_format: [u8; 2],
+    // "hmtx", "hhea", and "vmtx", "vhea" if present
+    metrics: Vec<(&'static [u8; 4], Vec<u8>)>,
+    // Bounding box of all glyphs
+    bbox: [i16; 4],
+}
+
+fn instantiate_glyphs(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    gvar: &Gvar,
+    location: &[f32],
+    hvar: Option<&MetricsVariations>,
+    vvar: Option<&MetricsVariations>,
+) -> Result<InstancedGlyphs> {
+    let glyphs = glyf::split_glyf(sfnt_index, sfnt)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: unsupported format", sfnt_index))?;
+    let horizontal = Metrics::read(sfnt_index, sfnt, b"hhea", b"hmtx", glyphs.len())?
+        .ok_or_else(|| anyhow!("sfnt {}: table “hmtx” not found", sfnt_index))?;
+    let vertical = Metrics::read(sfnt_index, sfnt, b"vhea", b"vmtx", glyphs.len())?;
+
+    let mut new_glyphs = Vec::with_capacity(glyphs.len());
+    // Origin of each glyph after variation: the x of the left phantom point,
+    // and the y of the top one
+    let mut origins = Vec::with_capacity(glyphs.len());
+    let mut advances = Vec::with_capacity(glyphs.len());
+    for (glyph_index, &glyph) in glyphs.iter().enumerate() {
+        let number_of_contours = glyph
+            .get(0..2)
+            .map_or(0, |x| i16::from_be_bytes([x[0], x[1]]));
+        let (x_min, y_max) = match glyph.get(2..10) {
+            Some(x) => (
+                f32::from(i16::from_be_bytes([x[0], x[1]])),
+                f32::from(i16::from_be_bytes([x[6], x[7]])),
+            ),
+            None => (0.0, 0.0),
+        };
+
+        let simple_glyph = if number_of_contours >= 0 && !glyph.is_empty() {
+            Some(glyf::decode_simple_glyph(sfnt_index, glyph_index, glyph)?)
+        } else {
+            None
+        };
+        let mut points = match &simple_glyph {
+            Some(simple_glyph) => simple_glyph
+                .points
+                .iter()
+                .map(|&(x, y, _)| (x as f32, y as f32))
+                .collect(),
+            None => glyf::glyph_components(sfnt_index, glyph_index, glyph)?
+                .iter()
+                .map(|_| (0.0, 0.0))
+                .collect::<Vec<_>>(),
+        };
+        if simple_glyph.is_none() {
+            // Only the offsets of the components vary
+            for (point, offset) in points.iter_mut().zip(component_offsets(glyph)) {
+                *point = offset;
+            }
+        }
+        let (advance_width, lsb) = horizontal.get(glyph_index);
+        let left = x_min - f32::from(lsb);
+        let (advance_height, top) = match &vertical {
+            Some(vertical) => {
+                let (advance_height, tsb) = vertical.get(glyph_index);
+                (advance_height, y_max + f32::from(tsb))
+            }
+            None => (0, 0.0),
+        };
+        points.extend_from_slice(&[
+            (left, 0.0),
+            (left + f32::from(advance_width), 0.0),
+            (0.0, top),
+            (0.0, top - f32::from(advance_height)),
+        ]);
+        let end_points = simple_glyph
+            .as_ref()
+            .map_or(&[][..], |x| x.end_points.as_slice());
+
+        let deltas = gvar.glyph_deltas(glyph_index, location, &points, end_points)?;
+        let new_points = points
+            .iter()
+            .zip(deltas.iter())
+            .map(|(p, d)| ((p.0 + d.0).round() as i32, (p.1 + d.1).round() as i32))
+            .collect::<Vec<_>>();
+        let num_points = points.len() - 4;
+        let phantom = &new_points[num_points..];
+        origins.push((phantom[0].0, phantom[2].1));
+        let advance =
+            |advance: u16, delta: Option<Result<f32>>, phantom_advance: i32| -> Result<u16> {
+                let advance = match delta {
+                    Some(delta) => (f32::from(advance) + delta?).round() as i32,
+                    None => phantom_advance,
+                };
+                Ok(u16::try_from(advance.max(0)).unwrap_or(u16::MAX))
+            };
+        advances.push((
+            advance(
+                advance_width,
+                hvar.map(|x| x.advance_delta(glyph_index)),
+                phantom[1].0 - phantom[0].0,
+            )?,
+            advance(
+                advance_height,
+                vvar.map(|x| x.advance_delta(glyph_index)),
+                phantom[2].1 - phantom[3].1,
+            )?,
+        ));
+
+        new_glyphs.push(match simple_glyph {
+            Some(mut simple_glyph) => {
+                for (point, &(x, y)) in simple_glyph.points.iter_mut().zip(new_points.iter()) {
+                    point.0 = x;
+                    point.1 = y;
+                }
+                glyf::encode_simple_glyph(sfnt_index, glyph_index, &simple_glyph)?
+            }
+            None if glyph.is_empty() => Vec::new(),
+            None => {
+                let offset_deltas = points[..num_points]
+                    .iter()
+                    .zip(new_points.iter())
+                    .map(|(p, q)| (q.0 - p.0 as i32, q.1 - p.1 as i32))
+                    .collect::<Vec<_>>();
+                glyf::move_components(sfnt_index, glyph_index, glyph, &offset_deltas)?
+            }
+        });
+    }
+
+    // Composite glyphs take the bounding box of their moved components
+    let bboxes = {
+        let glyph_refs = new_glyphs.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+        (0..new_glyphs.len())
+            .map(|glyph_index| glyf::glyph_bbox(sfnt_index, glyph_index, &glyph_refs))
+            .collect::<Result<Vec<_>>>()?
+    };
+    for (glyph, bbox) in new_glyphs.iter_mut().zip(bboxes.iter()) {
+        if let (Some(bbox), true) = (bbox, glyph.len() >= 10) {
+            for (i, value) in bbox.iter().enumerate() {
+                glyph[2 + i * 2..4 + i * 2].clone_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+
+    let mut metrics = Vec::new();
+    let (hmtx, hhea) =
+        horizontal.build(advances.iter().zip(origins.iter().zip(bboxes.iter())).map(
+            |(&(advance, _), (&(left, _), bbox))| {
+                (
+                    advance,
+                    bbox.map_or(0, |x| i32::from(x[0])) - left,
+                    bbox.map(|x| (x[0], x[2])),
+                )
+            },
+        ));
+    metrics.push((b"hmtx", hmtx));
+    metrics.push((b"hhea", hhea));
+    if let Some(vertical) = vertical {
+        let (vmtx, vhea) =
+            vertical.build(advances.iter().zip(origins.iter().zip(bboxes.iter())).map(
+                |(&(_, advance), (&(_, top), bbox))| {
+                    (
+                        advance,
+                        top - bbox.map_or(0, |x| i32::from(x[3])),
+                        bbox.map(|x| (-x[3], -x[1])),
+                    )
+                },
+            ));
+        metrics.push((b"vmtx", vmtx));
+        metrics.push((b"vhea", vhea));
+    }
+
+    let bbox = bboxes
+        .iter()
+        .flatten()
+        .fold([i16::MAX, i16::MAX, i16::MIN, i16::MIN], |acc, x| {
+            [
+                acc[0].min(x[0]),
+                acc[1].min(x[1]),
+                acc[2].max(x[2]),
+                acc[3].max(x[3]),
+            ]
+        });
+    let bbox = if bboxes.iter().flatten().next().is_none() {
+        [0; 4]
+    } else {
+        bbox
+    };
+    let (glyf, loca, loca_format) = glyf::build_glyf(sfnt_index, &new_glyphs)
+        .ok_or_else(|| anyhow!("sfnt {} table “glyf”: failed to build", sfnt_index))?;
+    Ok(InstancedGlyphs {
+        glyf,
+        loca,
+        loca_format,
+        metrics,
+        bbox,
+    })
+}
+
+// Offsets of the components of a composite glyph, (0, 0) for components
+// placed by matching points.
+fn component_offsets(glyph: &[u8]) -> Vec<(f32, f32)> {
+    let mut offsets = Vec::new();
+    let mut pos = 10;
+    while let Some(header) = glyph.get(pos..pos + 4) {
+        let flags = u16::from_be_bytes([header[0], header[1]]);
+        pos += 4;
+        // ARG_1_AND_2_ARE_WORDS, ARGS_ARE_XY_VALUES
+        let offset = match (flags & 0x0001 != 0, glyph.get(pos..pos + 4)) {
+            (true, Some(x)) => (
+                f32::from(i16::from_be_bytes([x[0], x[1]])),
+                f32::from(i16::from_be_bytes([x[2], x[3]])),
+            ),
+            (false, _) => match glyph.get(pos..pos + 2) {
+                Some(x) => (f32::from(x[0] as i8), f32::from(x[1] as i8)),
+                None => break,
+            },
+            _ => break,
+        };
+        offsets.push(if flags & 0x0002 != 0 {
+            offset
+        } else {
+            (0.0, 0.0)
+        });
+        pos += if flags & 0x0001 != 0 { 4 } else { 2 };
+        // WE_HAVE_A_SCALE, WE_HAVE_AN_X_AND_Y_SCALE, WE_HAVE_A_TWO_BY_TWO
+        pos += if flags & 0x0008 != 0 {
+            2
+        } else if flags & 0x0040 != 0 {
+            4
+        } else if flags & 0x0080 != 0 {
+            8
+        } else {
+            0
+        };
+        // MORE_COMPONENTS
+        if flags & 0x0020 == 0 {
+            break;
+        }
+    }
+    offsets
+}
+
+// Horizontal or vertical metrics: "hhea" and "hmtx", or "vhea" and "vmtx".
+struct Metrics {
+    raw_header: Vec<u8>,
+    // Advance and side bearing of each glyph
+    metrics: Vec<(u16, i16)>,
+}
+
+impl Metrics {
+    fn read(
+        sfnt_index: usize,
+        sfnt: &SfntHeader,
+        header_tag: &'static [u8; 4],
+        table_tag: &'static [u8; 4],
+        num_glyphs: usize,
+    ) -> Result<Option<Self>> {
+        let (raw_header, raw_table) = match (
+            sfnt.table_records.get(&header_tag.into()),
+            sfnt.table_records.get(&table_tag.into()),
+        ) {
+            (Some(header), Some(table)) => (&header.raw_data, &table.raw_data),
+            _ => return Ok(None),
+        };
+        // byte 34..36: numberOfHMetrics / numOfLongVerMetrics
+        let num_long_metrics =
+            usize::from(Reader::new(sfnt_index, header_tag.into(), raw_header).u16(34)?);
+        let r = Reader::new(sfnt_index, table_tag.into(), raw_table);
+        let mut metrics = Vec::with_capacity(num_glyphs);
+        for glyph_index in 0..num_glyphs {
+            metrics.push(if glyph_index < num_long_metrics {
+                (r.u16(glyph_index * 4)?, r.i16(glyph_index * 4 + 2)?)
+            } else {
+                let last_advance = metrics.last().map_or(0, |x: &(u16, i16)| x.0);
+                let pos = num_long_metrics * 4 + (glyph_index - num_long_metrics) * 2;
+                (last_advance, r.i16(pos).unwrap_or(0))
+            });
+        }
+        Ok(Some(Self {
+            raw_header: raw_header.to_vec(),
+            metrics,
+        }))
+    }
+
+    fn get(&self, glyph_index: usize) -> (u16, i16) {
+        self.metrics[glyph_index]
+    }
+
+    // Builds the metrics table and its header from the advance, the side
+    // bearing, and the extent along the advance of each glyph.
+    fn build(
+        &self,
+        metrics: impl Iterator<Item = (u16, i32, Option<(i16, i16)>)>,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let metrics = metrics
+            .map(|(advance, side_bearing, extent)| {
+                (
+                    advance,
+                    side_bearing.max(i16::MIN.into()).min(i16::MAX.into()) as i16,
+                    extent,
+                )
+            })
+            .collect::<Vec<_>>();
+        // Trailing glyphs with the same advance share the last long metric
+        let num_long_metrics = match metrics.last() {
+            Some(&(last_advance, _, _)) => {
+                metrics.len()
+                    - metrics
+                        .iter()
+                        .rev()
+                        .take_while(|x| x.0 == last_advance)
+                        .count()
+                    + 1
+            }
+            None => 0,
+        };
+        let mut table = Vec::with_capacity(metrics.len() * 4);
+        for (i, &(advance, side_bearing, _)) in metrics.iter().enumerate() {
+            if i < num_long_metrics {
+                table.extend_from_slice(&advance.to_be_bytes());
+            }
+            table.extend_from_slice(&side_bearing.to_be_bytes());
+        }
+
+        let mut header = self.raw_header.clone();
+        let advance_max = metrics.iter().map(|x| x.0).max().unwrap_or(0);
+        let with_extent = metrics
+            .iter()
+            .filter_map(|&(advance, side_bearing, extent)| {
+                extent.map(|x| (advance, side_bearing, x))
+            });
+        // Side bearing at the start and the end, and extent from the origin
+        let min_start = with_extent.clone().map(|x| x.1).min().unwrap_or(0);
+        let min_end = with_extent
+            .clone()
+            .map(|(advance, side_bearing, (min, max))| {
+                i32::from(advance) - i32::from(side_bearing) - (i32::from(max) - i32::from(min))
+            })
+            .min()
+            .unwrap_or(0);
+        let max_extent = with_extent
+            .map(|(_, side_bearing, (min, max))| {
+                i32::from(side_bearing) + i32::from(max) - i32::from(min)
+            })
+            .max()
+            .unwrap_or(0);
+        let clamp = |x: i32| x.max(i16::MIN.into()).min(i16::MAX.into()) as i16;
+        // byte 10..12: advanceWidthMax / advanceHeightMax
+        // byte 12..14: minLeftSideBearing / minTopSideBearing
+        // byte 14..16: minRightSideBearing / minBottomSideBearing
+        // byte 16..18: xMaxExtent / yMaxExtent
+        // byte 34..36: numberOfHMetrics / numOfLongVerMetrics
+        for (pos, value) in [
+            (10, advance_max.to_be_bytes()),
+            (12, min_start.to_be_bytes()),
+            (14, clamp(min_end).to_be_bytes()),
+            (16, clamp(max_extent).to_be_bytes()),
+            (
+                34,
+                u16::try_from(num_long_metrics)
+                    .unwrap_or(u16::MAX)
+                    .to_be_bytes(),
+            ),
+        ]
+        .iter()
+        {
+            if let Some(x) = header.get_mut(*pos..*pos + 2) {
+                x.clone_from_slice(value);
+            }
+        }
+        (table, header)
+    }
+}
+
+// Applies "MVAR" to the fields of "OS/2", "hhea", "post" and "vhea", as
+// found in `sfnt`.
+fn apply_mvar(
+    sfnt_index: usize,
+    sfnt: &SfntHeader,
+    raw_mvar: &[u8],
+    location: &[f32],
+) -> Result<Vec<(&'static [u8; 4], Vec<u8>)>> {
+    let r = Reader::new(sfnt_index, b"MVAR".into(), raw_mvar);
+    // byte 6..8: valueRecordSize
+    // byte 8..10: valueRecordCount
+    // byte 10..12: itemVariationStoreOffset
+    let value_record_size = usize::from(r.u16(6)?);
+    let value_record_count = usize::from(r.u16(8)?);
+    let store = match r.u16(10)? {
+        0 => return Ok(Vec::new()),
+        offset => ItemVariationStore::new(r.subtable(usize::from(offset))?, location)?,
+    };
+
+    let mut tables = Vec::<(&'static [u8; 4], Vec<u8>)>::new();
+    for i in 0..value_record_count {
+        let pos = 12 + i * value_record_size;
+        let value_tag = FourCC(r.get(pos, pos + 4)?.try_into()?);
+        let (table_tag, field_pos) = match MVAR_FIELDS.iter().find(|x| x.0 == value_tag) {
+            Some((_, table_tag, field_pos)) => (table_tag, *field_pos),
+            // Such as “gsp0”, as “gasp” is regenerated anyway
+            None => continue,
+        };
+        let delta = store.delta(r.u16(pos + 4)?, r.u16(pos + 6)?)?.round() as i32;
+        let table_index = match tables.iter().position(|x| x.0 == &table_tag.0) {
+            Some(table_index) => table_index,
+            None => match sfnt.table_records.get(table_tag) {
+                Some(table) => {
+                    let table_tag = [b"OS/2", b"hhea", b"post", b"vhea"]
+                        .iter()
+                        .find(|&&x| *x == table_tag.0)
+                        .unwrap();
+                    tables.push((table_tag, table.raw_data.to_vec()));
+                    tables.len() - 1
+                }
+                None => continue,
+            },
+        };
+        if let Some(x) = tables[table_index].1.get_mut(field_pos..field_pos + 2) {
+            // usWinAscent and usWinDescent are unsigned
+            let value = i32::from(i16::from_be_bytes([x[0], x[1]]));
+            let value = if value_tag == b"hcla".into() || value_tag == b"hcld".into() {
+                i32::from(u16::from_be_bytes([x[0], x[1]])) + delta
+            } else {
+                value + delta
+            };
+            x.clone_from_slice(&(value as u16).to_be_bytes());
+        }
+    }
+    Ok(tables)
+}
+
+// Naming and style of an instance
+struct InstanceStyle {
+    // Subfamily name ID from "fvar", or the generated English name
+    subfamily_name_id: Option<u16>,
+    subfamily: String,
+    post_script_name: Option<String>,
+    weight_class: Option<u16>,
+    width_class: Option<u16>,
+    bold: bool,
+    italic: bool,
+}
+
+impl InstanceStyle {
+    fn new(sfnt_index: usize, sfnt: &SfntHeader, fvar: &Fvar, location: &[f32]) -> Result<Self> {
+        let name = sfnt
+            .table_records
+            .get(&b"name".into())
+            .map(|x| NameTable::parse(sfnt_index, &x.raw_data))
+            .transpose()?;
+        let get_name = |name_id: u16| name.as_ref().and_then(|x| x.get(name_id));
+        let named_instance = fvar
+            .instances
+            .iter()
+            .find(|x| x.coordinates.as_slice() == location);
+        let subfamily_name_id = named_instance.map(|x| x.subfamily_name_id);
+        let subfamily = match subfamily_name_id.and_then(get_name) {
+            Some(subfamily) => subfamily,
+            // Name the axes away from their defaults, e.g. “Weight 650”
+            None => {
+                let subfamily = fvar
+                    .axes
+                    .iter()
+                    .zip(location.iter())
+                    .filter(|(axis, &value)| value != axis.default_value)
+                    .map(|(axis, value)| {
+                        let axis_name = get_name(axis.name_id).unwrap_or_else(|| {
+                            String::from_utf8_lossy(&axis.tag.0).trim_end().to_owned()
+                        });
+                        format!("{} {}", axis_name, value)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if subfamily.is_empty() {
+                    "Regular".to_owned()
+                } else {
+                    subfamily
+                }
+            }
+        };
+        let post_script_name = named_instance
+            .and_then(|x| x.post_script_name_id)
+            .and_then(get_name);
+
+        let axis_value = |tag: &[u8; 4]| {
+            fvar.axes
+                .iter()
+                .zip(location.iter())
+                .find(|(axis, _)| axis.tag == FourCC(*tag))
+                .map(|(_, &value)| value)
+        };
+        let weight_class = axis_value(b"wght").map(|x| x.round().clamp(1.0, 1000.0) as u16);
+        // usWidthClass 1 to 9 by the percentage of the normal width
+        let width_class = axis_value(b"wdth").map(|x| {
+            const WIDTHS: [f32; 9] = [50.0, 62.5, 75.0, 87.5, 100.0, 112.5, 125.0, 150.0, 200.0];
+            (WIDTHS
+                .iter()
+                .enumerate()
+                .min_by(|a, b| (a.1 - x).abs().partial_cmp(&(b.1 - x).abs()).unwrap())
+                .unwrap()
+                .0
+                + 1) as u16
+        });
+        let italic = match RIBBI_STYLES.contains(&subfamily.as_str()) {
+            true => subfamily.contains("Italic"),
+            // ITALIC in fsSelection
+            false => sfnt
+                .table_records
+                .get(&b"OS/2".into())
+                .and_then(|x| x.raw_data.get(63).copied())
+                .is_some_and(|x| x & 0x01 != 0),
+        };
+        Ok(Self {
+            subfamily_name_id,
+            bold: subfamily == "Bold" || subfamily == "Bold Italic",
+            italic,
+            subfamily,
+            post_script_name,
+            weight_class,
+            width_class,
+        })
+    }
+
+    // Returns the new "name" table. Regular, Bold, Italic and Bold Italic
+    // stay in the family; other styles get a family of their own, with the
+    // typographic family and subfamily names grouping them.
+    fn rename(&self, sfnt_index: usize, raw_name: &[u8]) -> Result<Vec<u8>> {
+        let mut name = NameTable::parse(sfnt_index, raw_name)?;
+        let ribbi = RIBBI_STYLES.contains(&self.subfamily.as_str());
+        let old_post_script_name = name.get(6);
+
+        for key in name.keys(&[1, 2, 4, 16, 17]) {
+            let family = match name.get_by_key(key, 16).or_else(|| name.get_by_key(key, 1)) {
+                Some(family) => family,
+                None => continue,
+            };
+            let subfamily = self
+                .subfamily_name_id
+                .and_then(|x| name.get_by_key(key, x))
+                .unwrap_or_else(|| self.subfamily.clone());
+            let full_name = format!("{} {}", family, subfamily);
+            let mut ok = true;
+            if ribbi {
+                ok &= name.set_by_key(key, 1, &family);
+                ok &= name.set_by_key(key, 2, &subfamily);
+                name.remove_by_key(key, 16);
+                name.remove_by_key(key, 17);
+            } else {
+                ok &= name.set_by_key(key, 1, &full_name);
+                ok &= name.set_by_key(key, 2, if self.italic { "Italic" } else { "Regular" });
+                ok &= name.set_by_key(key, 16, &family);
+                ok &= name.set_by_key(key, 17, &subfamily);
+            }
+            ok &= name.set_by_key(key, 4, &full_name);
+            if !ok {
+                eprintln!(
+                    "[ WARN ] sfnt {} table “name”: cannot encode “{}” for platform {} encoding {} language 0x{:04x}",
+                    sfnt_index, full_name, key.0, key.1, key.2
+                );
+            }
+        }
+
+        let family = name.get(16).or_else(|| name.get(1)).unwrap_or_default();
+        let post_script_name = match &self.post_script_name {
+            Some(post_script_name) => post_script_name.clone(),
+            None => {
+                let prefix = name.get(25).unwrap_or(family);
+                super::name::sanitize_post_script_name(&format!("{}-{}", prefix, self.subfamily))
+            }
+        };
+        // The unique ID usually contains the PostScript name, otherwise the
+        // instance is told apart by appending it
+        if let Some(unique_id) = name.get(3) {
+            let new_unique_id = match &old_post_script_name {
+                Some(x) if unique_id.contains(x.as_str()) => {
+                    unique_id.replace(x.as_str(), &post_script_name)
+                }
+                _ => format!("{};{}", unique_id, post_script_name),
+            };
+            name.set(3, &new_unique_id);
+        }
+        name.set(6, &post_script_name);
+        // Variations PostScript Name Prefix
+        name.remove(25);
+        Ok(name.build())
+    }
+}